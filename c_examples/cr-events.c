@@ -0,0 +1,100 @@
+#include <inttypes.h>
+#include <signal.h>
+#include <stdio.h>
+#include <stdlib.h>
+
+#include <libmicrovmi.h>
+
+static volatile sig_atomic_t running = 1;
+
+static void stop(int signum) {
+    (void)signum;
+    running = 0;
+}
+
+bool toggle_cr3_intercept(void* driver, uint16_t vcpu_count, bool enabled) {
+    InterceptType intercept;
+    intercept.tag = InterceptType_Cr;
+    intercept.cr = Cr3;
+    if (!microvmi_pause(driver)) {
+        printf("Unable to pause VM.\n");
+        return false;
+    }
+    printf("%s intercept on Cr3\n", enabled ? "Enabling" : "Disabling");
+    bool success = true;
+    for (uint16_t vcpu = 0; vcpu < vcpu_count; vcpu++) {
+        if (!microvmi_toggle_intercept(driver, vcpu, intercept, enabled)) {
+            printf("Unable to toggle the intercept on VCPU %d.\n", vcpu);
+            success = false;
+        }
+    }
+    if (!microvmi_resume(driver)) {
+        printf("Unable to resume VM.\n");
+        return false;
+    }
+    return success;
+}
+
+void listen_cr3_events(void* driver) {
+    EventReplyType reply;
+    reply.tag = EventReplyType_Continue;
+    unsigned long long count = 0;
+    printf("Listen for control register events...\n");
+    while (running) {
+        Event event;
+        bool received = false;
+        if (!microvmi_listen(driver, 1000, &event, &received)) {
+            printf("Unable to listen for events.\n");
+            return;
+        }
+        if (!received) {
+            printf("No events yet...\n");
+            continue;
+        }
+        if (event.kind.tag == EventType_Cr) {
+            printf("[%llu] VCPU %d - Cr3:    old value: 0x%" PRIx64 "    new value: 0x%" PRIx64 "\n",
+                   count, event.vcpu, event.kind.cr.old, event.kind.cr.new_);
+        }
+        if (!microvmi_reply_event(driver, event, reply)) {
+            printf("Unable to reply to the event.\n");
+            return;
+        }
+        count++;
+    }
+    printf("Caught %llu events\n", count);
+}
+
+int main(int argc, char* argv[]) {
+    if (argc < 2) {
+        printf("Usage: cr-events <vm_name>.\n");
+        return 1;
+    }
+    microvmi_envlogger_init();
+    const char* init_error = NULL;
+    void* vm_name = argv[1];
+    DriverInitParamsFFI init_params = {
+        .common = {
+            .vm_name = vm_name
+        }
+    };
+    void* driver = microvmi_init(NULL, &init_params, &init_error);
+    if (!driver) {
+        fprintf(stderr, "%s\n", init_error);
+        rs_cstring_free((char*)init_error);
+        return 1;
+    }
+    uint16_t vcpu_count = 0;
+    if (!microvmi_get_vcpu_count(driver, &vcpu_count)) {
+        printf("Unable to get the VCPU count.\n");
+        microvmi_destroy(driver);
+        return 1;
+    }
+    // stop listening on Ctrl-C, the intercepts are then disabled
+    signal(SIGINT, stop);
+    if (toggle_cr3_intercept(driver, vcpu_count, true)) {
+        listen_cr3_events(driver);
+    }
+    toggle_cr3_intercept(driver, vcpu_count, false);
+    microvmi_destroy(driver);
+    return 0;
+}
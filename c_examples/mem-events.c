@@ -0,0 +1,118 @@
+#include <inttypes.h>
+#include <signal.h>
+#include <stdio.h>
+#include <stdlib.h>
+
+#include <libmicrovmi.h>
+
+static volatile sig_atomic_t running = 1;
+
+static void stop(int signum) {
+    (void)signum;
+    running = 0;
+}
+
+bool toggle_pf_intercept(void* driver, uint16_t vcpu_count, bool enabled) {
+    InterceptType intercept;
+    intercept.tag = InterceptType_Pagefault;
+    if (!microvmi_pause(driver)) {
+        printf("Unable to pause VM.\n");
+        return false;
+    }
+    printf("%s memory events\n", enabled ? "Enabling" : "Disabling");
+    bool success = true;
+    for (uint16_t vcpu = 0; vcpu < vcpu_count; vcpu++) {
+        if (!microvmi_toggle_intercept(driver, vcpu, intercept, enabled)) {
+            printf("Unable to toggle the intercept on VCPU %d.\n", vcpu);
+            success = false;
+        }
+    }
+    if (!microvmi_resume(driver)) {
+        printf("Unable to resume VM.\n");
+        return false;
+    }
+    return success;
+}
+
+void listen_pf_events(void* driver) {
+    EventReplyType reply;
+    reply.tag = EventReplyType_Continue;
+    unsigned long long count = 0;
+    printf("Listen for memory events...\n");
+    while (running) {
+        Event event;
+        bool received = false;
+        if (!microvmi_listen(driver, 1000, &event, &received)) {
+            printf("Unable to listen for events.\n");
+            return;
+        }
+        if (!received) {
+            printf("No events yet...\n");
+            continue;
+        }
+        if (event.kind.tag == EventType_Pagefault) {
+            uint64_t gpa = event.kind.pagefault.gpa;
+            Access fault_access = event.kind.pagefault.access;
+            printf("[%llu] VCPU %d - pagefault occurred!    gva = 0x%" PRIx64 "    gpa = 0x%" PRIx64
+                   "    access = %c%c%c\n",
+                   count, event.vcpu, event.kind.pagefault.gva, gpa,
+                   fault_access & Access_R ? 'r' : '-',
+                   fault_access & Access_W ? 'w' : '-',
+                   fault_access & Access_X ? 'x' : '-');
+            // grant the faulting access, so that the guest can go on
+            Access page_access = Access_NIL;
+            if (!microvmi_get_page_access(driver, gpa, &page_access)
+                || !microvmi_set_page_access(driver, gpa, page_access | fault_access)) {
+                printf("Unable to update the page access.\n");
+                return;
+            }
+        }
+        if (!microvmi_reply_event(driver, event, reply)) {
+            printf("Unable to reply to the event.\n");
+            return;
+        }
+        count++;
+    }
+    printf("Caught %llu events\n", count);
+}
+
+int main(int argc, char* argv[]) {
+    if (argc < 3) {
+        printf("Usage: mem-events <vm_name> <physical_address>.\n");
+        return 1;
+    }
+    uint64_t paddr = strtoull(argv[2], NULL, 0);
+    microvmi_envlogger_init();
+    const char* init_error = NULL;
+    void* vm_name = argv[1];
+    DriverInitParamsFFI init_params = {
+        .common = {
+            .vm_name = vm_name
+        }
+    };
+    void* driver = microvmi_init(NULL, &init_params, &init_error);
+    if (!driver) {
+        fprintf(stderr, "%s\n", init_error);
+        rs_cstring_free((char*)init_error);
+        return 1;
+    }
+    uint16_t vcpu_count = 0;
+    if (!microvmi_get_vcpu_count(driver, &vcpu_count)) {
+        printf("Unable to get the VCPU count.\n");
+        microvmi_destroy(driver);
+        return 1;
+    }
+    // stop listening on Ctrl-C, the driver restores the page access when destroyed
+    signal(SIGINT, stop);
+    if (toggle_pf_intercept(driver, vcpu_count, true)) {
+        // the first read or write of the page triggers an event
+        if (microvmi_set_page_access(driver, paddr, Access_X)) {
+            listen_pf_events(driver);
+        } else {
+            printf("Unable to set the page access of 0x%" PRIx64 ".\n", paddr);
+        }
+    }
+    toggle_pf_intercept(driver, vcpu_count, false);
+    microvmi_destroy(driver);
+    return 0;
+}
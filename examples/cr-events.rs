@@ -1,14 +1,13 @@
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
+use std::sync::atomic::Ordering;
 use std::time::Instant;
 
 use clap::{App, Arg, ArgMatches};
 use colored::*;
 
-use microvmi::api::events::{CrType, EventType, InterceptType};
+use microvmi::api::events::{CrType, EventReplyType, EventType, InterceptType};
 use microvmi::api::params::DriverInitParams;
 use microvmi::api::Introspectable;
-use utilities::Clappable;
+use utilities::{ctrlc_handler, Clappable};
 
 fn parse_args() -> ArgMatches<'static> {
     App::new(file!())
@@ -71,12 +70,8 @@ fn main() {
     let vec_cr = get_cr(registers);
 
     // set CTRL-C handler
-    let running = Arc::new(AtomicBool::new(true));
-    let r = running.clone();
-    ctrlc::set_handler(move || {
-        r.store(false, Ordering::SeqCst);
-    })
-    .expect("Error setting Ctrl-C handler");
+    // the driver restores the guest state when dropped
+    let running = ctrlc_handler().expect("Error setting Ctrl-C handler");
 
     println!("Initialize Libmicrovmi");
     let init_params = DriverInitParams::from_matches(&matches);
@@ -111,8 +106,8 @@ fn main() {
                     "[{}] {} - {}:    old value: 0x{:x}    new value: 0x{:x}",
                     ev_nb_output, vcpu_output, cr_output, old, new
                 );
-                // drv.reply_event(ev, EventReplyType::Continue)
-                //   .expect("Failed to send event reply");
+                drv.reply_event(ev, EventReplyType::Continue)
+                    .expect("Failed to send event reply");
                 i += 1;
             }
             None => println!("No events yet..."),
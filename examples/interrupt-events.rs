@@ -1,5 +1,4 @@
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
+use std::sync::atomic::Ordering;
 use std::time::Instant;
 
 use clap::{App, ArgMatches};
@@ -8,7 +7,7 @@ use colored::*;
 use microvmi::api::events::{EventReplyType, EventType, InterceptType};
 use microvmi::api::params::DriverInitParams;
 use microvmi::api::Introspectable;
-use utilities::Clappable;
+use utilities::{ctrlc_handler, Clappable};
 
 fn parse_args() -> ArgMatches<'static> {
     App::new(file!())
@@ -38,12 +37,8 @@ fn main() {
     let matches = parse_args();
 
     // set CTRL-C handler
-    let running = Arc::new(AtomicBool::new(true));
-    let r = running.clone();
-    ctrlc::set_handler(move || {
-        r.store(false, Ordering::SeqCst);
-    })
-    .expect("Error setting Ctrl-C handler");
+    // the driver restores the guest state when dropped
+    let running = ctrlc_handler().expect("Error setting Ctrl-C handler");
 
     println!("Initialize Libmicrovmi");
     let init_params = DriverInitParams::from_matches(&matches);
@@ -63,11 +63,19 @@ fn main() {
         microvmi::init(None, Some(init_params)).expect("Failed to init libmicrovmi");
     spinner.finish_and_clear();
 
-    if !no_pause {
+    if no_pause {
+        dump(drv.as_ref(), &mut dump_file, &dump_path);
+    } else {
         println!("pausing the VM");
-        drv.pause().expect("Failed to pause VM");
+        // the VM is resumed when the guard is dropped, even if the dump fails
+        let guard = drv.pause_guard().expect("Failed to pause VM");
+        dump(&*guard, &mut dump_file, &dump_path);
+        drop(guard);
+        println!("resumed the VM");
     }
+}
 
+fn dump(drv: &dyn Introspectable, dump_file: &mut File, dump_path: &Path) {
     let max_addr = drv.get_max_physical_addr().unwrap();
     println!(
         "Dumping physical memory to {} until {:#X}",
@@ -105,9 +113,4 @@ fn main() {
         "Finished dumping physical memory at {}",
         dump_path.display()
     );
-
-    if !no_pause {
-        println!("resuming the VM");
-        drv.resume().expect("Failed to resume VM");
-    }
 }
@@ -1,5 +1,4 @@
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
+use std::sync::atomic::Ordering;
 use std::time::Instant;
 
 use clap::{App, ArgMatches};
@@ -8,9 +7,7 @@ use colored::*;
 use microvmi::api::events::{EventReplyType, EventType, InterceptType};
 use microvmi::api::params::DriverInitParams;
 use microvmi::api::{Access, Introspectable};
-use utilities::Clappable;
-
-const PAGE_SIZE: usize = 4096;
+use utilities::{ctrlc_handler, Clappable};
 
 fn parse_args() -> ArgMatches<'static> {
     App::new(file!())
@@ -40,12 +37,8 @@ fn main() {
     let matches = parse_args();
 
     // set CTRL-C handler
-    let running = Arc::new(AtomicBool::new(true));
-    let r = running.clone();
-    ctrlc::set_handler(move || {
-        r.store(false, Ordering::SeqCst);
-    })
-    .expect("Error setting Ctrl-C handler");
+    // the driver restores the guest state when dropped
+    let running = ctrlc_handler().expect("Error setting Ctrl-C handler");
 
     println!("Initialize Libmicrovmi");
     let init_params = DriverInitParams::from_matches(&matches);
@@ -60,19 +53,17 @@ fn main() {
     //Code snippet to get page fault
     let max_addr = drv.get_max_physical_addr().unwrap();
 
-    for cur_addr in (0..max_addr).step_by(PAGE_SIZE) {
-        let mut access: Access = drv.get_page_access(cur_addr).unwrap();
-        access &= !Access::X;
-        drv.set_page_access(cur_addr, access)
-            .expect("failed to set page access");
-    }
+    drv.set_page_access_range(0, max_addr, Access::RW)
+        .expect("failed to set page access");
 
     while running.load(Ordering::SeqCst) {
         let event = drv.listen(1000).expect("Failed to listen for events");
         match event {
             Some(ev) => {
                 let (gva, gpa, pf_access) = match ev.kind {
-                    EventType::Pagefault { gva, gpa, access } => (gva, gpa, access),
+                    EventType::Pagefault {
+                        gva, gpa, access, ..
+                    } => (gva, gpa, access),
                     _ => panic!("Not pf event"),
                 };
                 let ev_nb_output = format!("{}", i).cyan();
@@ -1,5 +1,4 @@
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
+use std::sync::atomic::Ordering;
 use std::time::Instant;
 
 use clap::{App, Arg, ArgMatches};
@@ -8,7 +7,7 @@ use colored::*;
 use microvmi::api::events::{EventReplyType, EventType, InterceptType};
 use microvmi::api::params::DriverInitParams;
 use microvmi::api::Introspectable;
-use utilities::Clappable;
+use utilities::{ctrlc_handler, Clappable};
 
 // default set of MSRs to be intercepted
 const DEFAULT_MSR: [u32; 6] = [0x174, 0x175, 0x176, 0xc0000080, 0xc0000081, 0xc0000082];
@@ -71,12 +70,8 @@ fn main() {
     }
 
     // set CTRL-C handler
-    let running = Arc::new(AtomicBool::new(true));
-    let r = running.clone();
-    ctrlc::set_handler(move || {
-        r.store(false, Ordering::SeqCst);
-    })
-    .expect("Error setting Ctrl-C handler");
+    // the driver restores the guest state when dropped
+    let running = ctrlc_handler().expect("Error setting Ctrl-C handler");
 
     println!("Initialize Libmicrovmi");
     let init_params = DriverInitParams::from_matches(&matches);
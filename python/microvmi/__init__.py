@@ -1,3 +1,12 @@
 from microvmi.microvmi import DriverType, Microvmi
 
-from .pymicrovmi import CommonInitParamsPy, DriverInitParamsPy, KVMInitParamsPy, MemflowInitParamsPy
+from .pymicrovmi import (
+    CommonInitParamsPy,
+    DriverInitParamsPy,
+    KVMInitParamsPy,
+    MemflowConnectorParamsPy,
+    MemflowInitParamsPy,
+    RemoteInitParamsPy,
+    XenInitParamsPy,
+    parse_url,
+)
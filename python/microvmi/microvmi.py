@@ -12,6 +12,8 @@ class DriverType(Enum):
     KVM = 0
     VirtualBox = 1
     Xen = 2
+    Memflow = 3
+    Remote = 4
 
 
 class Microvmi:
@@ -1,9 +1,8 @@
 import logging
-from typing import Any, Dict, List, Optional, Tuple
-from urllib.parse import parse_qs, urlparse
+from typing import Any, List, Optional, Tuple
 from urllib.request import BaseHandler, Request
 
-from microvmi import CommonInitParamsPy, DriverInitParamsPy, DriverType, KVMInitParamsPy, MemflowInitParamsPy, Microvmi
+from microvmi import DriverInitParamsPy, DriverType, Microvmi, parse_url
 
 # to be used by volatility, the VMIHandler should inherit from VolatilityHandler
 # in order to be non cacheable
@@ -57,68 +56,9 @@ class VMIHandler(VolatilityHandler):
 
 def url_to_driver_parameters(url: str) -> Tuple[Optional[DriverType], Optional[DriverInitParamsPy]]:
     """Parses a given request and extracts the Microvmi driver initialization parameters"""
-    parsed_url = urlparse(url)
-    # scheme
-    _validate_scheme(parsed_url.scheme)
-    # hypervisor
-    driver_type: Optional[DriverType] = _parse_hypervisor(parsed_url.netloc)
-    # init params
-    init_params: Optional[DriverInitParamsPy] = _parse_driver_init_params(parsed_url.query)
+    try:
+        drv_type_ext, init_params = parse_url(url)
+    except ValueError as e:
+        raise MicrovmiHandlerError(str(e)) from e
+    driver_type: Optional[DriverType] = DriverType(drv_type_ext) if drv_type_ext is not None else None
     return driver_type, init_params
-
-
-def _validate_scheme(scheme: str):
-    if scheme != VMIHandler.SCHEME:
-        raise MicrovmiHandlerError(f"Scheme error: Got {scheme}, expected {VMIHandler.SCHEME}")
-
-
-def _parse_vm_name(path: str) -> str:
-    # remove /
-    vm_name = path[1:]
-    if not vm_name:
-        raise MicrovmiHandlerError("Empty vm_name")
-    return vm_name
-
-
-def _parse_hypervisor(netloc: str) -> Optional[DriverType]:
-    if netloc:
-        try:
-            drv_type = DriverType[netloc]
-        except KeyError:
-            raise MicrovmiHandlerError(
-                f"Invalid driver type. Valid driver types: {' '.join([d.name for d in DriverType])}"
-            )
-        else:
-            return drv_type
-    return None
-
-
-def _parse_driver_init_params(query: str) -> Optional[DriverInitParamsPy]:
-    if not query:
-        return None
-    url_params: Dict[str, List[str]] = parse_qs(query, strict_parsing=True)
-    if not url_params:
-        return None
-    common = None
-    kvm = None
-    memflow = None
-    for param, list_value in url_params.items():
-        if param == "vm_name":
-            common = CommonInitParamsPy()
-            common.vm_name = list_value[0]
-        elif param == "kvm_unix_socket":
-            kvm = KVMInitParamsPy()
-            kvm.unix_socket = list_value[0]
-        elif param == "memflow_connector_name":
-            memflow = MemflowInitParamsPy(list_value[0])
-        elif param == "memflow_connector_args":
-            if memflow is None:
-                raise MicrovmiHandlerError("memflow connector args received but no connector name specified")
-            memflow.connector_args = list_value
-        else:
-            raise MicrovmiHandlerError(f"Unknown driver initialization parameter: {param}")
-    init_params = DriverInitParamsPy()
-    init_params.common = common
-    init_params.kvm = kvm
-    init_params.memflow = memflow
-    return init_params
@@ -11,7 +11,10 @@ use errors::PyMicrovmiError;
 use microvmi::api as rapi; // rust api
 use microvmi::api::params as rparams; // rust params
 use microvmi::init;
-use params::{CommonInitParamsPy, DriverInitParamsPy, KVMInitParamsPy, MemflowInitParamsPy};
+use params::{
+    CommonInitParamsPy, DriverInitParamsPy, KVMInitParamsPy, MemflowConnectorParamsPy,
+    MemflowInitParamsPy, RemoteInitParamsPy, XenInitParamsPy,
+};
 
 /// microvmi Python module declaration
 #[pymodule]
@@ -23,8 +26,12 @@ fn pymicrovmi(_py: Python<'_>, m: &Bound<'_, PyModule>) -> PyResult<()> {
     m.add_class::<DriverType>()?;
     m.add_class::<DriverInitParamsPy>()?;
     m.add_class::<CommonInitParamsPy>()?;
+    m.add_class::<XenInitParamsPy>()?;
     m.add_class::<KVMInitParamsPy>()?;
     m.add_class::<MemflowInitParamsPy>()?;
+    m.add_class::<MemflowConnectorParamsPy>()?;
+    m.add_class::<RemoteInitParamsPy>()?;
+    m.add_function(wrap_pyfunction!(parse_url, m)?)?;
 
     Ok(())
 }
@@ -41,6 +48,106 @@ impl DriverType {
     const VIRTUALBOX: u32 = 1;
     #[classattr]
     const XEN: u32 = 2;
+    #[classattr]
+    const MEMFLOW: u32 = 3;
+    #[classattr]
+    const REMOTE: u32 = 4;
+}
+
+/// parses a libmicrovmi connection URL
+///
+/// Args:
+///     url (str): vmi://<hypervisor>/?param1=value1&param2=value2
+///
+/// Returns:
+///     Tuple[Optional[int], Optional[DriverInitParamsPy]]: the driver type and the initialization parameters
+#[pyfunction]
+fn parse_url(url: &str) -> PyResult<(Option<u32>, Option<DriverInitParamsPy>)> {
+    let (driver_type, init_params) = rapi::url::parse_url(url)
+        .map_err(|e| PyMicrovmiError::from(microvmi::errors::MicrovmiError::from(e)))?;
+    let py_driver_type = driver_type.map(|drv_type| match drv_type {
+        rapi::DriverType::KVM => DriverType::KVM,
+        rapi::DriverType::VirtualBox => DriverType::VIRTUALBOX,
+        rapi::DriverType::Xen => DriverType::XEN,
+        rapi::DriverType::Memflow => DriverType::MEMFLOW,
+        rapi::DriverType::Remote => DriverType::REMOTE,
+    });
+    let py_init_params = init_params.map(|v| DriverInitParamsPy {
+        common: v.common.map(|k| CommonInitParamsPy {
+            vm_name: k.vm_name,
+            read_only: k.read_only,
+            audit_log: k.audit_log,
+        }),
+        xen: v.xen.map(|k| match k {
+            rparams::XenInitParams::DomId(domid) => XenInitParamsPy {
+                domid: Some(domid),
+                monitor_ring: true,
+            },
+            rparams::XenInitParams::Options {
+                domid,
+                monitor_ring,
+            } => XenInitParamsPy {
+                domid,
+                monitor_ring: monitor_ring == rparams::XenMonitorRing::Enabled,
+            },
+        }),
+        kvm: v.kvm.map(|k| match k {
+            rparams::KVMInitParams::UnixSocket { path } => KVMInitParamsPy {
+                unix_socket: path,
+                vsock_port: None,
+            },
+            rparams::KVMInitParams::Vsock { port, .. } => KVMInitParamsPy {
+                unix_socket: String::new(),
+                vsock_port: Some(port),
+            },
+        }),
+        memflow: v.memflow.map(|k| match k.connector_args {
+            Some(rparams::MemflowConnectorParams::Default { args }) => MemflowInitParamsPy {
+                connector_name: k.connector_name,
+                connector_args: args,
+                connector_params: None,
+                os_name: k.os_name,
+                os_args: k.os_args.unwrap_or_default(),
+            },
+            params => MemflowInitParamsPy {
+                connector_name: k.connector_name,
+                connector_args: Vec::new(),
+                connector_params: params.map(|params| MemflowConnectorParamsPy { params }),
+                os_name: k.os_name,
+                os_args: k.os_args.unwrap_or_default(),
+            },
+        }),
+        remote: v.remote.map(|k| RemoteInitParamsPy {
+            addr: k.addr,
+            token: k.token,
+        }),
+    });
+    Ok((py_driver_type, py_init_params))
+}
+
+/// convert the Python memflow parameters, the typed and free-form connector parameters are exclusive
+fn memflow_init_params(params: MemflowInitParamsPy) -> PyResult<rparams::MemflowInitParams> {
+    let connector_args = match params.connector_params {
+        Some(_) if !params.connector_args.is_empty() => {
+            return Err(PyValueError::new_err(
+                "connector_params and connector_args cannot be both set",
+            ))
+        }
+        Some(typed) => typed.params,
+        None => rparams::MemflowConnectorParams::Default {
+            args: params.connector_args,
+        },
+    };
+    Ok(rparams::MemflowInitParams {
+        connector_name: params.connector_name,
+        connector_args: Some(connector_args),
+        os_name: params.os_name,
+        os_args: if params.os_args.is_empty() {
+            None
+        } else {
+            Some(params.os_args)
+        },
+    })
 }
 
 /// Main class to interact with libmicrovmi
@@ -77,27 +184,44 @@ impl MicrovmiExt {
                 DriverType::KVM => Ok(rapi::DriverType::KVM),
                 DriverType::VIRTUALBOX => Ok(rapi::DriverType::VirtualBox),
                 DriverType::XEN => Ok(rapi::DriverType::Xen),
+                DriverType::MEMFLOW => Ok(rapi::DriverType::Memflow),
+                DriverType::REMOTE => Ok(rapi::DriverType::Remote),
                 _ => Err(PyValueError::new_err(format!(
                     "Invalid value for DriverType: {}",
                     drv_type
                 ))),
             })
             .transpose()?;
-        let rust_init_params = init_params.map(|v| rparams::DriverInitParams {
-            common: v
-                .common
-                .map(|k| rparams::CommonInitParams { vm_name: k.vm_name }),
-            kvm: v.kvm.map(|k| rparams::KVMInitParams::UnixSocket {
-                path: k.unix_socket,
-            }),
-            memflow: v.memflow.map(|k| rparams::MemflowInitParams {
-                connector_name: k.connector_name,
-                connector_args: Some(rparams::MemflowConnectorParams::Default {
-                    args: k.connector_args,
-                }),
-            }),
-            ..Default::default()
-        });
+        let rust_init_params = init_params
+            .map(|v| -> PyResult<rparams::DriverInitParams> {
+                Ok(rparams::DriverInitParams {
+                    common: v.common.map(|k| rparams::CommonInitParams {
+                        vm_name: k.vm_name,
+                        read_only: k.read_only,
+                        audit_log: k.audit_log,
+                    }),
+                    xen: v.xen.map(|k| rparams::XenInitParams::Options {
+                        domid: k.domid,
+                        monitor_ring: rparams::XenMonitorRing::from(k.monitor_ring),
+                    }),
+                    kvm: v.kvm.map(|k| match k.vsock_port {
+                        Some(port) => rparams::KVMInitParams::Vsock {
+                            cid: rparams::VMADDR_CID_ANY,
+                            port,
+                        },
+                        None => rparams::KVMInitParams::UnixSocket {
+                            path: k.unix_socket,
+                        },
+                    }),
+                    memflow: v.memflow.map(memflow_init_params).transpose()?,
+                    remote: v.remote.map(|k| rparams::RemoteInitParams {
+                        addr: k.addr,
+                        token: k.token,
+                    }),
+                    ..Default::default()
+                })
+            })
+            .transpose()?;
 
         let driver = init(rust_driver_type, rust_init_params).map_err(PyMicrovmiError::from)?;
         Ok(MicrovmiExt { driver })
@@ -1,4 +1,5 @@
 /// This modules defines the driver initialization parameters to be exposed from Python
+use microvmi::api::params as rparams;
 use pyo3::prelude::*;
 
 /// equivalent of `CommonInitParams` for Python
@@ -7,6 +8,11 @@ use pyo3::prelude::*;
 pub struct CommonInitParamsPy {
     #[pyo3(get, set)]
     pub vm_name: String,
+    #[pyo3(get, set)]
+    pub read_only: bool,
+    /// path of the JSON lines audit log of the operations modifying the guest
+    #[pyo3(get, set)]
+    pub audit_log: Option<String>,
 }
 
 #[pymethods]
@@ -17,12 +23,44 @@ impl CommonInitParamsPy {
     }
 }
 
+/// equivalent of `XenInitParams` for Python
+#[pyclass]
+#[derive(Debug, Clone)]
+pub struct XenInitParamsPy {
+    /// attach to this domain ID instead of looking up the VM name in xenstore
+    #[pyo3(get, set)]
+    pub domid: Option<u32>,
+    /// disable it for domains which are not allowed to monitor the guest
+    #[pyo3(get, set)]
+    pub monitor_ring: bool,
+}
+
+impl Default for XenInitParamsPy {
+    fn default() -> Self {
+        Self {
+            domid: None,
+            monitor_ring: true,
+        }
+    }
+}
+
+#[pymethods]
+impl XenInitParamsPy {
+    #[new]
+    fn new() -> Self {
+        Self::default()
+    }
+}
+
 /// equivalent of `KVMInitParams` for Python
 #[pyclass]
 #[derive(Default, Debug, Clone)]
 pub struct KVMInitParamsPy {
     #[pyo3(get, set)]
     pub unix_socket: String,
+    /// listen on this vsock port instead of the unix socket
+    #[pyo3(get, set)]
+    pub vsock_port: Option<u32>,
 }
 
 #[pymethods]
@@ -33,7 +71,66 @@ impl KVMInitParamsPy {
     }
 }
 
+/// equivalent of the typed `MemflowConnectorParams` for Python
+///
+/// # Examples
+///
+/// Usage from Python
+/// ```Python
+/// from microvmi import MemflowInitParamsPy, MemflowConnectorParamsPy
+/// memflow = MemflowInitParamsPy("kvm")
+/// memflow.connector_params = MemflowConnectorParamsPy.kvm(1234)
+/// ```
+#[pyclass]
+#[derive(Debug, Clone)]
+pub struct MemflowConnectorParamsPy {
+    pub params: rparams::MemflowConnectorParams,
+}
+
+#[pymethods]
+impl MemflowConnectorParamsPy {
+    /// qemu connector, selecting the VM by name, otherwise the first QEMU process
+    #[staticmethod]
+    #[pyo3(signature = (vm_name=None))]
+    fn qemu(vm_name: Option<String>) -> Self {
+        Self {
+            params: rparams::MemflowConnectorParams::QEMUProcFs { vm_name },
+        }
+    }
+
+    /// kvm connector, selecting the QEMU process by pid
+    #[staticmethod]
+    fn kvm(pid: u32) -> Self {
+        Self {
+            params: rparams::MemflowConnectorParams::KVM { pid },
+        }
+    }
+
+    /// pcileech connector, the device defaults to "FPGA"
+    #[staticmethod]
+    #[pyo3(signature = (device=None, memmap=None))]
+    fn pcileech(device: Option<String>, memmap: Option<String>) -> Self {
+        Self {
+            params: rparams::MemflowConnectorParams::PCILeech { device, memmap },
+        }
+    }
+
+    /// coredump connector
+    #[staticmethod]
+    fn coredump(filepath: String) -> Self {
+        Self {
+            params: rparams::MemflowConnectorParams::Coredump { filepath },
+        }
+    }
+
+    fn __repr__(&self) -> String {
+        format!("{:?}", self.params)
+    }
+}
+
 /// equivalent of `MemflowInitParams` for Python
+///
+/// `connector_params` and the free-form `connector_args` (`key=value` strings) are exclusive
 #[pyclass]
 #[derive(Default, Debug, Clone)]
 pub struct MemflowInitParamsPy {
@@ -41,6 +138,13 @@ pub struct MemflowInitParamsPy {
     pub connector_name: String,
     #[pyo3(get, set)]
     pub connector_args: Vec<String>,
+    #[pyo3(get, set)]
+    pub connector_params: Option<MemflowConnectorParamsPy>,
+    /// optional OS plugin name (e.g. "win32"), giving access to the guest processes
+    #[pyo3(get, set)]
+    pub os_name: Option<String>,
+    #[pyo3(get, set)]
+    pub os_args: Vec<String>,
 }
 
 #[pymethods]
@@ -49,11 +153,30 @@ impl MemflowInitParamsPy {
     fn new(name: &str) -> Self {
         Self {
             connector_name: String::from(name),
-            connector_args: Vec::new(),
+            ..Default::default()
         }
     }
 }
 
+/// equivalent of `RemoteInitParams` for Python
+#[pyclass]
+#[derive(Default, Debug, Clone)]
+pub struct RemoteInitParamsPy {
+    /// address of the microvmi-server: tcp://<host>:<port> or unix://<path>
+    #[pyo3(get, set)]
+    pub addr: String,
+    #[pyo3(get, set)]
+    pub token: String,
+}
+
+#[pymethods]
+impl RemoteInitParamsPy {
+    #[new]
+    fn new() -> Self {
+        Self::default()
+    }
+}
+
 /// equivalent of `DriverInitParams` for Python
 ///
 /// # Examples
@@ -64,6 +187,10 @@ impl MemflowInitParamsPy {
 /// # setup common params
 /// common = CommonInitParamsPy()
 /// common.vm_name = "windows10"
+/// # optional: refuse any modification of the VM
+/// common.read_only = True
+/// # optional: log the modifications of the VM
+/// common.audit_log = "/var/log/microvmi-audit.jsonl"
 /// # setup kvm params
 /// kvm = KVMInitParamsPy()
 /// kvm.unix_socket = "/tmp/introspector"
@@ -78,9 +205,13 @@ pub struct DriverInitParamsPy {
     #[pyo3(get, set)]
     pub common: Option<CommonInitParamsPy>,
     #[pyo3(get, set)]
+    pub xen: Option<XenInitParamsPy>,
+    #[pyo3(get, set)]
     pub kvm: Option<KVMInitParamsPy>,
     #[pyo3(get, set)]
     pub memflow: Option<MemflowInitParamsPy>,
+    #[pyo3(get, set)]
+    pub remote: Option<RemoteInitParamsPy>,
 }
 
 #[pymethods]
@@ -0,0 +1,130 @@
+//! microvmi-server serves a locally initialized libmicrovmi driver over a TCP or UNIX socket
+//!
+//! The protocol is described in the `microvmi::api::protocol` module.
+//! It is not encrypted: over `--tcp`, the token and the guest memory travel in clear text.
+
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::net::TcpListener;
+#[cfg(unix)]
+use std::os::unix::net::UnixListener;
+use std::process;
+
+use clap::{App, Arg, ArgGroup, ArgMatches};
+use log::{info, warn};
+
+use microvmi::api::params::DriverInitParams;
+use microvmi::api::protocol::Stream;
+use microvmi::api::Introspectable;
+use utilities::Clappable;
+
+mod session;
+
+fn parse_args() -> ArgMatches<'static> {
+    App::new("microvmi-server")
+        .version(env!("CARGO_PKG_VERSION"))
+        .about("Serves a libmicrovmi driver over the network")
+        .arg(
+            Arg::with_name("tcp")
+                .long("tcp")
+                .takes_value(true)
+                .help("Listen on a TCP address (e.g 0.0.0.0:7475), the token is sent in clear text"),
+        )
+        .arg(
+            Arg::with_name("unix")
+                .long("unix")
+                .takes_value(true)
+                .help("Listen on a UNIX socket path"),
+        )
+        .group(
+            ArgGroup::with_name("listen")
+                .args(&["tcp", "unix"])
+                .required(true),
+        )
+        .arg(
+            Arg::with_name("token_file")
+                .long("token_file")
+                .takes_value(true)
+                .help("File containing the token shared with the clients, otherwise taken from MICROVMI_SERVER_TOKEN"),
+        )
+        .arg(
+            Arg::with_name("url")
+                .long("url")
+                .takes_value(true)
+                .help("Initialize the driver from a connection URL, instead of the driver parameters"),
+        )
+        .args(DriverInitParams::to_clap_args().as_ref())
+        .get_matches()
+}
+
+/// Read the shared token from the token file, or the environment
+fn read_token(matches: &ArgMatches) -> Result<String, Box<dyn Error>> {
+    let token = match matches.value_of("token_file") {
+        Some(path) => fs::read_to_string(path)?,
+        None => env::var("MICROVMI_SERVER_TOKEN")
+            .map_err(|_| "no token: set MICROVMI_SERVER_TOKEN or --token_file")?,
+    };
+    let token = token.trim();
+    if token.is_empty() {
+        return Err("the token is empty".into());
+    }
+    Ok(token.to_string())
+}
+
+/// Serve the clients one after the other
+///
+/// A client which doesn't complete the handshake in time is disconnected, see session::serve.
+fn serve_clients<S: Stream, I: Iterator<Item = std::io::Result<S>>>(
+    drv: &mut dyn Introspectable,
+    token: &str,
+    incoming: I,
+) {
+    for stream in incoming {
+        match stream {
+            Ok(stream) => {
+                info!("Client connected");
+                match session::serve(drv, stream, token) {
+                    Ok(()) => info!("Client disconnected"),
+                    Err(e) => warn!("Client session failed: {}", e),
+                }
+            }
+            Err(e) => warn!("Failed to accept client: {}", e),
+        }
+    }
+}
+
+fn run(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let token = read_token(matches)?;
+    let mut drv: Box<dyn Introspectable> = match matches.value_of("url") {
+        Some(url) => microvmi::init_from_url(url)?,
+        None => microvmi::init(None, Some(DriverInitParams::from_matches(matches)))?,
+    };
+    info!("Driver initialized: {:?}", drv.get_driver_type());
+
+    if let Some(addr) = matches.value_of("tcp") {
+        let listener = TcpListener::bind(addr)?;
+        info!("Listening on {}", listener.local_addr()?);
+        serve_clients(drv.as_mut(), &token, listener.incoming());
+    } else if let Some(path) = matches.value_of("unix") {
+        #[cfg(unix)]
+        {
+            let listener = UnixListener::bind(path)?;
+            info!("Listening on {}", path);
+            serve_clients(drv.as_mut(), &token, listener.incoming());
+        }
+        #[cfg(not(unix))]
+        return Err(format!("UNIX sockets are not supported on this platform: {}", path).into());
+    }
+    Ok(())
+}
+
+fn main() {
+    env_logger::init();
+
+    let matches = parse_args();
+    if let Err(e) = run(&matches) {
+        eprintln!("microvmi-server: {}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,537 @@
+use std::error::Error;
+use std::io::{self, ErrorKind, Read};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::time::{Duration, Instant};
+
+use log::{debug, warn};
+
+use microvmi::api::protocol::{
+    read_frame, read_hello_frame, try_read_frame, write_frame, ProtocolError, Request, Response,
+    Stream, MAX_DATA_SIZE, PROTOCOL_VERSION,
+};
+use microvmi::api::Introspectable;
+
+/// How long to wait for a request or an event, when the client subscribed to the events
+const POLL_INTERVAL_MS: u32 = 10;
+/// How long a client has to send its Hello: the clients are served one at a time
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Serve a client until it disconnects
+///
+/// The guest state is restored when the session ends, so that a client going away
+/// does not leave the intercepts and page access restrictions behind.
+/// A client which doesn't send its Hello within `HANDSHAKE_TIMEOUT` is disconnected.
+pub fn serve<S: Stream>(
+    drv: &mut dyn Introspectable,
+    mut stream: S,
+    token: &str,
+) -> Result<(), ProtocolError> {
+    if !handshake(drv, &mut stream, token, HANDSHAKE_TIMEOUT)? {
+        return Ok(());
+    }
+    let result = serve_requests(drv, &mut stream);
+    if let Err(e) = catch(|| drv.restore_guest_state()) {
+        warn!("Failed to restore the guest state: {}", e);
+    }
+    result
+}
+
+/// Check the client hello, returns whether the session can go on
+///
+/// The client has to send its Hello within the timeout, and without any data.
+fn handshake<S: Stream>(
+    drv: &mut dyn Introspectable,
+    stream: &mut S,
+    token: &str,
+    timeout: Duration,
+) -> Result<bool, ProtocolError> {
+    let hello = read_hello_frame(&mut Deadline {
+        stream: &mut *stream,
+        deadline: Instant::now() + timeout,
+    });
+    stream.set_read_timeout(None)?;
+    let refused = match hello {
+        Err(e @ ProtocolError::FrameTooLarge(..)) => Some(e.to_string()),
+        Err(e) => return Err(e),
+        Ok(hello) => check_hello(drv, stream, token, hello)?,
+    };
+    match refused {
+        None => Ok(true),
+        Some(refused) => {
+            warn!("Client refused: {}", refused);
+            write_frame(stream, &Response::Error(refused), &[])?;
+            Ok(false)
+        }
+    }
+}
+
+/// Answer a valid hello, otherwise returns why the client is refused
+fn check_hello<S: Stream>(
+    drv: &mut dyn Introspectable,
+    stream: &mut S,
+    token: &str,
+    hello: Request,
+) -> Result<Option<String>, ProtocolError> {
+    let refused = match hello {
+        Request::Hello { version, .. } if version != PROTOCOL_VERSION => format!(
+            "protocol version {} is not supported, expected {}",
+            version, PROTOCOL_VERSION
+        ),
+        Request::Hello {
+            token: client_token,
+            ..
+        } if tokens_equal(&client_token, token) => {
+            write_frame(
+                stream,
+                &Response::Hello {
+                    driver_type: drv.get_driver_type(),
+                },
+                &[],
+            )?;
+            return Ok(None);
+        }
+        Request::Hello { .. } => String::from("authentication failed"),
+        _ => String::from("expected Hello"),
+    };
+    Ok(Some(refused))
+}
+
+/// Stream reader failing once the deadline has passed
+struct Deadline<'a, S: Stream> {
+    stream: &'a mut S,
+    deadline: Instant,
+}
+
+impl<S: Stream> Read for Deadline<'_, S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let remaining = self.deadline.saturating_duration_since(Instant::now());
+        if remaining == Duration::from_secs(0) {
+            return Err(io::Error::new(ErrorKind::TimedOut, "handshake timed out"));
+        }
+        self.stream.set_read_timeout(Some(remaining))?;
+        self.stream.read(buf)
+    }
+}
+
+fn serve_requests<S: Stream>(
+    drv: &mut dyn Introspectable,
+    stream: &mut S,
+) -> Result<(), ProtocolError> {
+    let mut subscribed = false;
+    loop {
+        let frame = if subscribed {
+            try_read_frame(stream)
+        } else {
+            read_frame(stream).map(Some)
+        };
+        let (request, data): (Request, Vec<u8>) = match frame {
+            Ok(Some(frame)) => frame,
+            // no request, push the next event
+            Ok(None) => {
+                subscribed = push_event(drv, stream)?;
+                continue;
+            }
+            Err(ProtocolError::Io(e)) if e.kind() == ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        debug!("Request: {:?}", request);
+        let response = match request {
+            Request::Subscribe { enabled } => {
+                subscribed = enabled;
+                let timeout = Duration::from_millis(POLL_INTERVAL_MS.into());
+                stream.set_read_timeout(if enabled { Some(timeout) } else { None })?;
+                Response::Done
+            }
+            request => {
+                let mut reply_data = Vec::new();
+                let response = catch(|| Ok(handle(drv, request, &data, &mut reply_data)))
+                    .unwrap_or_else(Response::Error);
+                write_frame(stream, &response, &reply_data)?;
+                continue;
+            }
+        };
+        write_frame(stream, &response, &[])?;
+    }
+}
+
+/// Push the next event to a subscribed client, returns whether the subscription goes on
+fn push_event<S: Stream>(
+    drv: &mut dyn Introspectable,
+    stream: &mut S,
+) -> Result<bool, ProtocolError> {
+    match catch(|| drv.listen(POLL_INTERVAL_MS)) {
+        Ok(None) => Ok(true),
+        Ok(Some(event)) => {
+            write_frame(stream, &Response::PushedEvent(event), &[])?;
+            Ok(true)
+        }
+        Err(e) => {
+            warn!("Subscription ended: {}", e);
+            stream.set_read_timeout(None)?;
+            write_frame(stream, &Response::PushError(e), &[])?;
+            Ok(false)
+        }
+    }
+}
+
+/// Run a driver operation, turning a panic into an error
+///
+/// The drivers panic on the operations they do not implement.
+fn catch<T, F: FnOnce() -> Result<T, Box<dyn Error>>>(f: F) -> Result<T, String> {
+    match catch_unwind(AssertUnwindSafe(f)) {
+        Ok(result) => result.map_err(|e| e.to_string()),
+        Err(panic) => {
+            let message = panic
+                .downcast_ref::<&str>()
+                .map(|s| s.to_string())
+                .or_else(|| panic.downcast_ref::<String>().cloned())
+                .unwrap_or_default();
+            Err(format!("driver panicked: {}", message))
+        }
+    }
+}
+
+fn handle(
+    drv: &mut dyn Introspectable,
+    request: Request,
+    data: &[u8],
+    reply_data: &mut Vec<u8>,
+) -> Response {
+    match request {
+        Request::Hello { .. } => Response::Error(String::from("already authenticated")),
+        Request::GetVcpuCount => Response::from_result(drv.get_vcpu_count(), Response::VcpuCount),
+        Request::ReadPhysical { paddr, size } => read(reply_data, size, |buf, bytes_read| {
+            drv.read_physical(paddr, buf, bytes_read)
+        }),
+        Request::WritePhysical { paddr } => {
+            Response::from_result(drv.write_physical(paddr, data), |_| Response::Done)
+        }
+        Request::GetMaxPhysicalAddr => {
+            Response::from_result(drv.get_max_physical_addr(), Response::MaxPhysicalAddr)
+        }
+        Request::ReadRegisters { vcpu } => {
+            Response::from_result(drv.read_registers(vcpu), Response::Registers)
+        }
+        Request::WriteRegisters { vcpu, registers } => {
+            Response::from_result(drv.write_registers(vcpu, registers), |_| Response::Done)
+        }
+        Request::GetPageAccess { paddr } => {
+            Response::from_result(drv.get_page_access(paddr), Response::PageAccess)
+        }
+        Request::SetPageAccess { paddr, access } => {
+            Response::from_result(drv.set_page_access(paddr, access), |_| Response::Done)
+        }
+        Request::SetPageAccessRange { start, end, access } => {
+            Response::from_result(drv.set_page_access_range(start, end, access), |_| {
+                Response::Done
+            })
+        }
+        Request::SetPageAccessMulti { pages } => {
+            Response::from_result(drv.set_page_access_multi(&pages), |_| Response::Done)
+        }
+        Request::CreateView => Response::from_result(drv.create_view(), Response::View),
+        Request::DestroyView { view } => {
+            Response::from_result(drv.destroy_view(view), |_| Response::Done)
+        }
+        Request::SwitchView { vcpu, view } => {
+            Response::from_result(drv.switch_view(vcpu, view), |_| Response::Done)
+        }
+        Request::SetPageAccessInView {
+            view,
+            paddr,
+            access,
+        } => Response::from_result(drv.set_page_access_in_view(view, paddr, access), |_| {
+            Response::Done
+        }),
+        Request::RemapGfn { view, from, to } => {
+            Response::from_result(drv.remap_gfn(view, from, to), |_| Response::Done)
+        }
+        Request::Pause => Response::from_result(drv.pause(), |_| Response::Done),
+        Request::Resume => Response::from_result(drv.resume(), |_| Response::Done),
+        Request::InjectInterrupt {
+            vcpu,
+            vector,
+            error_code,
+            cr2,
+        } => Response::from_result(drv.inject_interrupt(vcpu, vector, error_code, cr2), |_| {
+            Response::Done
+        }),
+        Request::ToggleIntercept {
+            vcpu,
+            intercept_type,
+            enabled,
+        } => Response::from_result(drv.toggle_intercept(vcpu, intercept_type, enabled), |_| {
+            Response::Done
+        }),
+        Request::GetActiveIntercepts { vcpu } => {
+            Response::from_result(drv.get_active_intercepts(vcpu), Response::Intercepts)
+        }
+        Request::RestoreGuestState => {
+            Response::from_result(drv.restore_guest_state(), |_| Response::Done)
+        }
+        Request::Listen { timeout } => Response::from_result(drv.listen(timeout), Response::Event),
+        Request::ReplyEvent { event, reply_type } => {
+            Response::from_result(drv.reply_event(event, reply_type), |_| Response::Done)
+        }
+        Request::ListProcesses => Response::from_result(drv.list_processes(), Response::Processes),
+        Request::ListModules { pid } => {
+            Response::from_result(drv.list_modules(pid), Response::Modules)
+        }
+        Request::ReadVirtual { pid, vaddr, size } => read(reply_data, size, |buf, bytes_read| {
+            drv.read_virtual(pid, vaddr, buf, bytes_read)
+        }),
+        // handled by the session
+        Request::Subscribe { .. } => unreachable!(),
+    }
+}
+
+/// Read memory into the reply data
+fn read<F>(reply_data: &mut Vec<u8>, size: u32, f: F) -> Response
+where
+    F: FnOnce(&mut [u8], &mut u64) -> Result<(), Box<dyn Error>>,
+{
+    if size > MAX_DATA_SIZE {
+        return Response::Error(format!(
+            "read size {} exceeds the limit of {} bytes",
+            size, MAX_DATA_SIZE
+        ));
+    }
+    reply_data.resize(size as usize, 0);
+    let mut bytes_read = 0;
+    Response::from_result(f(reply_data, &mut bytes_read), |_| Response::Read {
+        bytes_read,
+    })
+}
+
+/// Compare the tokens in constant time
+fn tokens_equal(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |acc, (x, y)| acc | (x ^ y))
+            == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+
+    use microvmi::api::events::{Event, EventType};
+    use microvmi::api::params::{DriverInitParams, RemoteInitParams};
+    use microvmi::api::{Access, DriverType};
+
+    struct FakeDriver {
+        memory: RefCell<Vec<u8>>,
+        events: Vec<Event>,
+        restored: Arc<AtomicBool>,
+    }
+
+    impl Introspectable for FakeDriver {
+        fn read_physical(
+            &self,
+            paddr: u64,
+            buf: &mut [u8],
+            bytes_read: &mut u64,
+        ) -> Result<(), Box<dyn Error>> {
+            let start = paddr as usize;
+            let memory = self.memory.borrow();
+            let data = memory
+                .get(start..start + buf.len())
+                .ok_or("out of bounds")?;
+            buf.copy_from_slice(data);
+            *bytes_read = buf.len() as u64;
+            Ok(())
+        }
+
+        fn write_physical(&self, paddr: u64, buf: &[u8]) -> Result<(), Box<dyn Error>> {
+            let start = paddr as usize;
+            self.memory.borrow_mut()[start..start + buf.len()].copy_from_slice(buf);
+            Ok(())
+        }
+
+        fn restore_guest_state(&mut self) -> Result<(), Box<dyn Error>> {
+            self.restored.store(true, Ordering::SeqCst);
+            Ok(())
+        }
+
+        fn listen(&mut self, _timeout: u32) -> Result<Option<Event>, Box<dyn Error>> {
+            Ok(self.events.pop())
+        }
+
+        fn get_driver_type(&self) -> DriverType {
+            DriverType::Xen
+        }
+    }
+
+    /// Serve a single client with a fake driver, returns its address and the restored flag
+    fn spawn_server(memory_size: usize) -> (String, Arc<AtomicBool>, thread::JoinHandle<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let restored = Arc::new(AtomicBool::new(false));
+        let server_restored = restored.clone();
+        let handle = thread::spawn(move || {
+            let mut drv = FakeDriver {
+                memory: RefCell::new(vec![0u8; memory_size]),
+                events: vec![Event {
+                    vcpu: 1,
+                    kind: EventType::Pagefault {
+                        gva: 0x7ff0,
+                        gpa: 0x1000,
+                        access: Access::W,
+                        view: 0,
+                    },
+                }],
+                restored: server_restored,
+            };
+            let (stream, _) = listener.accept().unwrap();
+            serve(&mut drv, stream, "secret").unwrap();
+        });
+        (addr, restored, handle)
+    }
+
+    fn request(stream: &mut TcpStream, request: Request, data: &[u8]) -> (Response, Vec<u8>) {
+        write_frame(stream, &request, data).unwrap();
+        read_frame(stream).unwrap()
+    }
+
+    fn hello(token: &str) -> Request {
+        Request::Hello {
+            version: PROTOCOL_VERSION,
+            token: token.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_hello_with_data_is_refused() {
+        let (addr, _, handle) = spawn_server(16);
+        let mut stream = TcpStream::connect(addr).unwrap();
+
+        let (response, _) = request(&mut stream, hello("secret"), b"data");
+
+        assert!(matches!(response, Response::Error(ref e) if e.contains("data")));
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_silent_client_times_out() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let _client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut drv = FakeDriver {
+            memory: RefCell::new(Vec::new()),
+            events: Vec::new(),
+            restored: Arc::new(AtomicBool::new(false)),
+        };
+
+        let result = handshake(&mut drv, &mut stream, "secret", Duration::from_millis(50));
+
+        assert!(matches!(result, Err(ProtocolError::Io(_))));
+    }
+
+    #[test]
+    fn test_invalid_token_is_refused() {
+        let (addr, _, handle) = spawn_server(16);
+        let mut stream = TcpStream::connect(addr).unwrap();
+
+        let (response, _) = request(&mut stream, hello("guess"), &[]);
+
+        assert!(matches!(response, Response::Error(ref e) if e == "authentication failed"));
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_requests_are_served_until_disconnection() {
+        let (addr, restored, handle) = spawn_server(16);
+        let mut stream = TcpStream::connect(addr).unwrap();
+
+        let (response, _) = request(&mut stream, hello("secret"), &[]);
+        assert!(matches!(
+            response,
+            Response::Hello {
+                driver_type: DriverType::Xen
+            }
+        ));
+        let (response, _) = request(&mut stream, Request::WritePhysical { paddr: 4 }, b"abcd");
+        assert!(matches!(response, Response::Done));
+        let (response, data) = request(
+            &mut stream,
+            Request::ReadPhysical { paddr: 2, size: 4 },
+            &[],
+        );
+        assert!(matches!(response, Response::Read { bytes_read: 4 }));
+        assert_eq!(b"\0\0ab", data.as_slice());
+        // not implemented by the driver, the session goes on
+        let (response, _) = request(&mut stream, Request::Pause, &[]);
+        assert!(matches!(response, Response::Error(_)));
+        let (response, _) = request(
+            &mut stream,
+            Request::ReadPhysical { paddr: 32, size: 4 },
+            &[],
+        );
+        assert!(matches!(response, Response::Error(ref e) if e == "out of bounds"));
+
+        drop(stream);
+        handle.join().unwrap();
+        assert!(restored.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_remote_driver() {
+        let memory_size = 3 << 20;
+        let (addr, restored, handle) = spawn_server(memory_size);
+        let init_params = DriverInitParams {
+            remote: Some(RemoteInitParams {
+                addr: format!("tcp://{}", addr),
+                token: String::from("secret"),
+            }),
+            ..Default::default()
+        };
+        let mut drv = microvmi::init(Some(DriverType::Remote), Some(init_params)).unwrap();
+        assert_eq!(DriverType::Remote, drv.get_driver_type());
+
+        // spans several pipelined requests
+        let data: Vec<u8> = (0..memory_size - 8).map(|i| (i % 251) as u8).collect();
+        drv.write_physical(8, &data).unwrap();
+        let mut buf = vec![0u8; data.len()];
+        let mut bytes_read = 0;
+        drv.read_physical(8, &mut buf, &mut bytes_read).unwrap();
+        assert_eq!(data.len() as u64, bytes_read);
+        assert!(buf == data);
+        // the last chunk fails, the first one is still read
+        let mut buf = vec![0u8; 2 << 20];
+        let result = drv.read_physical(2 << 20, &mut buf, &mut bytes_read);
+        assert_eq!(
+            "Server error: out of bounds",
+            result.unwrap_err().to_string()
+        );
+        assert_eq!(1 << 20, bytes_read);
+
+        let event = drv.listen(1000).unwrap().unwrap();
+        assert_eq!(1, event.vcpu);
+        assert!(matches!(
+            event.kind,
+            EventType::Pagefault { gpa: 0x1000, .. }
+        ));
+        assert!(drv.listen(10).unwrap().is_none());
+        // the requests still work while subscribed
+        drv.read_physical(8, &mut buf[..4], &mut bytes_read)
+            .unwrap();
+        assert_eq!(&data[..4], &buf[..4]);
+
+        drop(drv);
+        handle.join().unwrap();
+        assert!(restored.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_tokens_equal() {
+        assert!(tokens_equal("secret", "secret"));
+        assert!(!tokens_equal("secret", "secreT"));
+        assert!(!tokens_equal("secret", "secret2"));
+    }
+}
@@ -0,0 +1,760 @@
+//! Audit log of the operations modifying the guest
+//!
+//! The [`Audited`](struct.Audited.html) driver wrapper records every mutating operation
+//! into an [`AuditSink`](trait.AuditSink.html): a closure, or a JSON lines file with [`JsonAuditSink`](struct.JsonAuditSink.html).
+//! [`init`](../../fn.init.html) wraps the driver into a JSON lines audit log when `CommonInitParams.audit_log` is set.
+//!
+//! # Examples
+//! ```no_run
+//! use microvmi::api::audit::{AuditedVm, Audited, JsonAuditSink};
+//! use microvmi::api::Introspectable;
+//! use microvmi::init;
+//!
+//! let drv = init(None, None).expect("Failed to init driver");
+//! let sink = JsonAuditSink::create("/var/log/microvmi-audit.jsonl").expect("Failed to create audit log");
+//! let vm = AuditedVm {
+//!     vm_name: Some(String::from("windows10")),
+//!     domid: None,
+//! };
+//! let mut drv = Audited::new(drv, sink, vm);
+//! // recorded with the previous content of the memory
+//! drv.write_physical(0x1000, &[0xCC]).unwrap();
+//! ```
+
+use std::error::Error;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::Write;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Serialize, Serializer};
+
+use crate::api::events::{Event, EventReplyType, EventType, InterceptType};
+use crate::api::os::{ModuleInfo, ProcessInfo};
+use crate::api::params::{DriverInitParams, XenInitParams};
+use crate::api::registers::Registers;
+use crate::api::{Access, DriverType, Introspectable};
+
+/// A register modified by `write_registers` or by an event reply
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RegisterChange {
+    pub name: &'static str,
+    /// None if the registers could not be read before the write
+    pub before: Option<u64>,
+    pub after: u64,
+}
+
+/// An operation modifying the guest
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "operation", rename_all = "snake_case")]
+pub enum AuditOperation {
+    WritePhysical {
+        paddr: u64,
+        /// None if the memory could not be read before the write
+        #[serde(serialize_with = "serialize_optional_hex")]
+        before: Option<Vec<u8>>,
+        #[serde(serialize_with = "serialize_hex")]
+        after: Vec<u8>,
+    },
+    WriteRegisters {
+        vcpu: u16,
+        changes: Vec<RegisterChange>,
+    },
+    SetPageAccess {
+        view: u16,
+        start: u64,
+        end: u64,
+        access: Access,
+    },
+    ToggleIntercept {
+        vcpu: u16,
+        intercept_type: InterceptType,
+        enabled: bool,
+    },
+    InjectInterrupt {
+        vcpu: u16,
+        vector: u8,
+        error_code: Option<u32>,
+        cr2: u64,
+    },
+    ReplyEvent {
+        vcpu: u16,
+        reply_type: &'static str,
+        /// registers modified by a SetRegisters or SkipInstruction reply
+        changes: Vec<RegisterChange>,
+    },
+    CreateView,
+    DestroyView {
+        view: u16,
+    },
+    SwitchView {
+        vcpu: u16,
+        view: u16,
+    },
+    RemapGfn {
+        view: u16,
+        from: u64,
+        to: u64,
+    },
+    RestoreGuestState,
+    Pause,
+    Resume,
+}
+
+/// The audited VM, as selected by the initialization parameters
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct AuditedVm {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vm_name: Option<String>,
+    /// Xen domain ID
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub domid: Option<u32>,
+}
+
+impl AuditedVm {
+    pub fn from_init_params(init_params: &DriverInitParams) -> Self {
+        let vm_name = init_params
+            .common
+            .as_ref()
+            .map(|common| common.vm_name.clone())
+            .filter(|vm_name| !vm_name.is_empty());
+        let domid = match init_params.xen {
+            Some(XenInitParams::DomId(domid)) => Some(domid),
+            Some(XenInitParams::Options { domid, .. }) => domid,
+            None => None,
+        };
+        AuditedVm { vm_name, domid }
+    }
+}
+
+/// An audit log entry
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AuditEntry {
+    #[serde(serialize_with = "serialize_timestamp")]
+    pub timestamp: SystemTime,
+    pub driver: DriverType,
+    #[serde(flatten)]
+    pub vm: AuditedVm,
+    #[serde(flatten)]
+    pub operation: AuditOperation,
+    /// whether the driver completed the operation
+    pub success: bool,
+}
+
+impl AuditEntry {
+    /// Format the entry as a single line JSON object
+    pub fn to_json(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string(self)
+    }
+}
+
+// seconds since the UNIX epoch
+fn serialize_timestamp<S: Serializer>(timestamp: &SystemTime, s: S) -> Result<S::Ok, S::Error> {
+    let secs = timestamp
+        .duration_since(UNIX_EPOCH)
+        .map_or(0.0, |d| d.as_secs_f64());
+    s.serialize_f64(secs)
+}
+
+fn serialize_hex<S: Serializer>(bytes: &[u8], s: S) -> Result<S::Ok, S::Error> {
+    s.serialize_str(&hex(bytes))
+}
+
+fn serialize_optional_hex<S: Serializer>(bytes: &Option<Vec<u8>>, s: S) -> Result<S::Ok, S::Error> {
+    match bytes {
+        Some(bytes) => serialize_hex(bytes, s),
+        None => s.serialize_none(),
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Receives the audit log entries
+pub trait AuditSink {
+    fn record(&self, entry: &AuditEntry);
+}
+
+impl<F: Fn(&AuditEntry)> AuditSink for F {
+    fn record(&self, entry: &AuditEntry) {
+        self(entry)
+    }
+}
+
+/// Appends the audit log entries to a file, one JSON object per line
+#[derive(Debug)]
+pub struct JsonAuditSink {
+    file: File,
+}
+
+impl JsonAuditSink {
+    /// Open the audit log file, creating it if needed
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<Self, io::Error> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(JsonAuditSink { file })
+    }
+}
+
+impl AuditSink for JsonAuditSink {
+    fn record(&self, entry: &AuditEntry) {
+        let result = entry
+            .to_json()
+            .map_err(io::Error::from)
+            .and_then(|json| writeln!(&self.file, "{}", json));
+        if let Err(e) = result {
+            error!("Failed to write audit log entry: {}", e);
+        }
+    }
+}
+
+/// Driver wrapper recording every operation modifying the guest into an audit sink
+///
+/// Operations are recorded once the driver returned, whether they succeeded or not.
+/// The guest state is restored, and recorded, when the wrapper is dropped.
+pub struct Audited<T: Introspectable + ?Sized, S: AuditSink> {
+    drv: Box<T>,
+    sink: S,
+    vm: AuditedVm,
+}
+
+impl<T: Introspectable + ?Sized, S: AuditSink> Audited<T, S> {
+    pub fn new(drv: Box<T>, sink: S, vm: AuditedVm) -> Self {
+        Audited { drv, sink, vm }
+    }
+
+    fn entry(&self, operation: AuditOperation, success: bool) -> AuditEntry {
+        AuditEntry {
+            timestamp: SystemTime::now(),
+            driver: self.drv.get_driver_type(),
+            vm: self.vm.clone(),
+            operation,
+            success,
+        }
+    }
+
+    fn record<R>(
+        &self,
+        operation: AuditOperation,
+        result: Result<R, Box<dyn Error>>,
+    ) -> Result<R, Box<dyn Error>> {
+        self.sink.record(&self.entry(operation, result.is_ok()));
+        result
+    }
+}
+
+// before the inner driver restores the guest state itself, unrecorded
+impl<T: Introspectable + ?Sized, S: AuditSink> Drop for Audited<T, S> {
+    fn drop(&mut self) {
+        if let Err(e) = self.restore_guest_state() {
+            error!("Failed to restore guest state: {}", e);
+        }
+    }
+}
+
+/// Registers whose value differ, or every register if the previous values are unknown
+fn register_changes(before: Option<&Registers>, after: &Registers) -> Vec<RegisterChange> {
+    let Registers::X86(after) = after;
+    let after_values = after.values();
+    match before {
+        Some(Registers::X86(before)) => before
+            .values()
+            .into_iter()
+            .zip(after_values)
+            .filter(|((_, old), (_, new))| old != new)
+            .map(|((name, old), (_, new))| RegisterChange {
+                name,
+                before: Some(old),
+                after: new,
+            })
+            .collect(),
+        None => after_values
+            .into_iter()
+            .map(|(name, new)| RegisterChange {
+                name,
+                before: None,
+                after: new,
+            })
+            .collect(),
+    }
+}
+
+/// Registers an event reply is about to modify
+fn reply_changes(
+    before: Option<&Registers>,
+    event: &Event,
+    reply_type: &EventReplyType,
+) -> Vec<RegisterChange> {
+    match reply_type {
+        EventReplyType::SetRegisters(regs) => register_changes(before, regs),
+        EventReplyType::SkipInstruction => {
+            let insn_len = match event.kind {
+                EventType::Breakpoint { insn_len, .. } | EventType::Cpuid { insn_len, .. } => {
+                    insn_len
+                }
+                _ => return Vec::new(),
+            };
+            match before {
+                Some(Registers::X86(regs)) => vec![RegisterChange {
+                    name: "rip",
+                    before: Some(regs.rip),
+                    after: regs.rip + u64::from(insn_len),
+                }],
+                None => Vec::new(),
+            }
+        }
+        _ => Vec::new(),
+    }
+}
+
+fn reply_name(reply_type: &EventReplyType) -> &'static str {
+    match reply_type {
+        EventReplyType::Continue => "continue",
+        EventReplyType::Reinject => "reinject",
+        EventReplyType::SkipInstruction => "skip_instruction",
+        EventReplyType::Emulate => "emulate",
+        EventReplyType::SetRegisters(_) => "set_registers",
+        EventReplyType::Retry => "retry",
+    }
+}
+
+impl<T: Introspectable + ?Sized, S: AuditSink> Introspectable for Audited<T, S> {
+    fn get_vcpu_count(&self) -> Result<u16, Box<dyn Error>> {
+        self.drv.get_vcpu_count()
+    }
+
+    fn read_physical(
+        &self,
+        paddr: u64,
+        buf: &mut [u8],
+        bytes_read: &mut u64,
+    ) -> Result<(), Box<dyn Error>> {
+        self.drv.read_physical(paddr, buf, bytes_read)
+    }
+
+    fn write_physical(&self, paddr: u64, buf: &[u8]) -> Result<(), Box<dyn Error>> {
+        let mut before = vec![0u8; buf.len()];
+        let mut bytes_read = 0;
+        let before = self
+            .drv
+            .read_physical(paddr, &mut before, &mut bytes_read)
+            .ok()
+            .map(|_| before);
+        let result = self.drv.write_physical(paddr, buf);
+        self.record(
+            AuditOperation::WritePhysical {
+                paddr,
+                before,
+                after: buf.to_vec(),
+            },
+            result,
+        )
+    }
+
+    fn get_max_physical_addr(&self) -> Result<u64, Box<dyn Error>> {
+        self.drv.get_max_physical_addr()
+    }
+
+    fn read_registers(&self, vcpu: u16) -> Result<Registers, Box<dyn Error>> {
+        self.drv.read_registers(vcpu)
+    }
+
+    fn get_page_access(&self, paddr: u64) -> Result<Access, Box<dyn Error>> {
+        self.drv.get_page_access(paddr)
+    }
+
+    fn set_page_access(&self, paddr: u64, access: Access) -> Result<(), Box<dyn Error>> {
+        let result = self.drv.set_page_access(paddr, access);
+        self.record(
+            AuditOperation::SetPageAccess {
+                view: 0,
+                start: paddr,
+                end: paddr + 1,
+                access,
+            },
+            result,
+        )
+    }
+
+    fn set_page_access_range(
+        &self,
+        start: u64,
+        end: u64,
+        access: Access,
+    ) -> Result<(), Box<dyn Error>> {
+        let result = self.drv.set_page_access_range(start, end, access);
+        self.record(
+            AuditOperation::SetPageAccess {
+                view: 0,
+                start,
+                end,
+                access,
+            },
+            result,
+        )
+    }
+
+    fn set_page_access_multi(&self, pages: &[(u64, Access)]) -> Result<(), Box<dyn Error>> {
+        let result = self.drv.set_page_access_multi(pages);
+        let success = result.is_ok();
+        for (paddr, access) in pages {
+            self.sink.record(&self.entry(
+                AuditOperation::SetPageAccess {
+                    view: 0,
+                    start: *paddr,
+                    end: paddr + 1,
+                    access: *access,
+                },
+                success,
+            ));
+        }
+        result
+    }
+
+    fn create_view(&mut self) -> Result<u16, Box<dyn Error>> {
+        let result = self.drv.create_view();
+        self.record(AuditOperation::CreateView, result)
+    }
+
+    fn destroy_view(&mut self, view: u16) -> Result<(), Box<dyn Error>> {
+        let result = self.drv.destroy_view(view);
+        self.record(AuditOperation::DestroyView { view }, result)
+    }
+
+    fn switch_view(&mut self, vcpu: u16, view: u16) -> Result<(), Box<dyn Error>> {
+        let result = self.drv.switch_view(vcpu, view);
+        self.record(AuditOperation::SwitchView { vcpu, view }, result)
+    }
+
+    fn set_page_access_in_view(
+        &self,
+        view: u16,
+        paddr: u64,
+        access: Access,
+    ) -> Result<(), Box<dyn Error>> {
+        let result = self.drv.set_page_access_in_view(view, paddr, access);
+        self.record(
+            AuditOperation::SetPageAccess {
+                view,
+                start: paddr,
+                end: paddr + 1,
+                access,
+            },
+            result,
+        )
+    }
+
+    fn remap_gfn(&mut self, view: u16, from: u64, to: u64) -> Result<(), Box<dyn Error>> {
+        let result = self.drv.remap_gfn(view, from, to);
+        self.record(AuditOperation::RemapGfn { view, from, to }, result)
+    }
+
+    fn write_registers(&self, vcpu: u16, reg: Registers) -> Result<(), Box<dyn Error>> {
+        let before = self.drv.read_registers(vcpu).ok();
+        let changes = register_changes(before.as_ref(), &reg);
+        let result = self.drv.write_registers(vcpu, reg);
+        self.record(AuditOperation::WriteRegisters { vcpu, changes }, result)
+    }
+
+    fn pause(&mut self) -> Result<(), Box<dyn Error>> {
+        let result = self.drv.pause();
+        self.record(AuditOperation::Pause, result)
+    }
+
+    fn resume(&mut self) -> Result<(), Box<dyn Error>> {
+        let result = self.drv.resume();
+        self.record(AuditOperation::Resume, result)
+    }
+
+    fn inject_interrupt(
+        &mut self,
+        vcpu: u16,
+        vector: u8,
+        error_code: Option<u32>,
+        cr2: u64,
+    ) -> Result<(), Box<dyn Error>> {
+        let result = self.drv.inject_interrupt(vcpu, vector, error_code, cr2);
+        self.record(
+            AuditOperation::InjectInterrupt {
+                vcpu,
+                vector,
+                error_code,
+                cr2,
+            },
+            result,
+        )
+    }
+
+    fn toggle_intercept(
+        &mut self,
+        vcpu: u16,
+        intercept_type: InterceptType,
+        enabled: bool,
+    ) -> Result<(), Box<dyn Error>> {
+        let result = self.drv.toggle_intercept(vcpu, intercept_type, enabled);
+        self.record(
+            AuditOperation::ToggleIntercept {
+                vcpu,
+                intercept_type,
+                enabled,
+            },
+            result,
+        )
+    }
+
+    fn get_active_intercepts(&self, vcpu: u16) -> Result<Vec<InterceptType>, Box<dyn Error>> {
+        self.drv.get_active_intercepts(vcpu)
+    }
+
+    fn restore_guest_state(&mut self) -> Result<(), Box<dyn Error>> {
+        let result = self.drv.restore_guest_state();
+        self.record(AuditOperation::RestoreGuestState, result)
+    }
+
+    fn listen(&mut self, timeout: u32) -> Result<Option<Event>, Box<dyn Error>> {
+        self.drv.listen(timeout)
+    }
+
+    fn reply_event(
+        &mut self,
+        event: Event,
+        reply_type: EventReplyType,
+    ) -> Result<(), Box<dyn Error>> {
+        let vcpu = event.vcpu;
+        let changes = match reply_type {
+            EventReplyType::SetRegisters(_) | EventReplyType::SkipInstruction => {
+                let before = self.drv.read_registers(vcpu).ok();
+                reply_changes(before.as_ref(), &event, &reply_type)
+            }
+            _ => Vec::new(),
+        };
+        let reply_name = reply_name(&reply_type);
+        let result = self.drv.reply_event(event, reply_type);
+        self.record(
+            AuditOperation::ReplyEvent {
+                vcpu,
+                reply_type: reply_name,
+                changes,
+            },
+            result,
+        )
+    }
+
+    fn list_processes(&self) -> Result<Vec<ProcessInfo>, Box<dyn Error>> {
+        self.drv.list_processes()
+    }
+
+    fn list_modules(&self, pid: u32) -> Result<Vec<ModuleInfo>, Box<dyn Error>> {
+        self.drv.list_modules(pid)
+    }
+
+    fn read_virtual(
+        &self,
+        pid: u32,
+        vaddr: u64,
+        buf: &mut [u8],
+        bytes_read: &mut u64,
+    ) -> Result<(), Box<dyn Error>> {
+        self.drv.read_virtual(pid, vaddr, buf, bytes_read)
+    }
+
+    fn get_driver_type(&self) -> DriverType {
+        self.drv.get_driver_type()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::api::params::CommonInitParams;
+    use crate::api::registers::{SegmentReg, X86Registers};
+    use crate::driver::fake::FakeDriver;
+
+    fn audited_driver() -> (
+        Audited<FakeDriver, impl AuditSink>,
+        Rc<RefCell<Vec<AuditEntry>>>,
+    ) {
+        let entries = Rc::new(RefCell::new(Vec::new()));
+        let sink_entries = entries.clone();
+        let drv = FakeDriver::new(vec![0x90; 0x10]);
+        drv.rip.set(0x1000);
+        let vm = AuditedVm {
+            vm_name: Some(String::from("win10")),
+            domid: None,
+        };
+        let audited = Audited::new(
+            Box::new(drv),
+            move |entry: &AuditEntry| sink_entries.borrow_mut().push(entry.clone()),
+            vm,
+        );
+        (audited, entries)
+    }
+
+    #[test]
+    fn test_write_physical_records_previous_bytes() {
+        let (drv, entries) = audited_driver();
+
+        drv.write_physical(0x4, &[0xCC, 0xCC]).unwrap();
+
+        let entry = &entries.borrow()[0];
+        assert_eq!(entry.driver, DriverType::KVM);
+        assert!(entry.success);
+        assert_eq!(
+            entry.operation,
+            AuditOperation::WritePhysical {
+                paddr: 0x4,
+                before: Some(vec![0x90, 0x90]),
+                after: vec![0xCC, 0xCC],
+            }
+        );
+        let json = entry.to_json().unwrap();
+        assert!(json.contains("\"driver\":\"KVM\",\"vm_name\":\"win10\","));
+        assert!(json.contains(
+            "\"operation\":\"write_physical\",\"paddr\":4,\"before\":\"9090\",\"after\":\"cccc\""
+        ));
+    }
+
+    #[test]
+    fn test_drop_records_guest_state_restoration() {
+        let (drv, entries) = audited_driver();
+
+        drop(drv);
+
+        let entry = &entries.borrow()[0];
+        assert_eq!(entry.operation, AuditOperation::RestoreGuestState);
+        assert!(entry.success);
+    }
+
+    #[test]
+    fn test_vm_is_identified_by_the_init_params() {
+        let init_params = DriverInitParams {
+            common: Some(CommonInitParams::default()),
+            xen: Some(XenInitParams::DomId(5)),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            AuditedVm::from_init_params(&init_params),
+            AuditedVm {
+                vm_name: None,
+                domid: Some(5),
+            }
+        );
+    }
+
+    #[test]
+    fn test_write_registers_records_changed_registers() {
+        let (drv, entries) = audited_driver();
+        let regs = Registers::X86(X86Registers {
+            rip: 0x1001,
+            ..Default::default()
+        });
+
+        drv.write_registers(0, regs).unwrap();
+
+        assert_eq!(
+            entries.borrow()[0].operation,
+            AuditOperation::WriteRegisters {
+                vcpu: 0,
+                changes: vec![RegisterChange {
+                    name: "rip",
+                    before: Some(0x1000),
+                    after: 0x1001,
+                }],
+            }
+        );
+    }
+
+    #[test]
+    fn test_write_registers_records_segment_registers() {
+        let (drv, entries) = audited_driver();
+        let regs = Registers::X86(X86Registers {
+            rip: 0x1000,
+            gs: SegmentReg {
+                base: 0xfffff800,
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+
+        drv.write_registers(0, regs).unwrap();
+
+        assert_eq!(
+            entries.borrow()[0].operation,
+            AuditOperation::WriteRegisters {
+                vcpu: 0,
+                changes: vec![RegisterChange {
+                    name: "gs.base",
+                    before: Some(0),
+                    after: 0xfffff800,
+                }],
+            }
+        );
+    }
+
+    #[test]
+    fn test_set_registers_reply_records_changed_registers() {
+        let (mut drv, entries) = audited_driver();
+        let event = Event {
+            vcpu: 1,
+            kind: EventType::Singlestep { gpa: 0x1000 },
+        };
+        let regs = Registers::X86(X86Registers {
+            rax: 0x2a,
+            rip: 0x1000,
+            ..Default::default()
+        });
+
+        drv.reply_event(event, EventReplyType::SetRegisters(regs))
+            .unwrap();
+
+        let entry = &entries.borrow()[0];
+        assert_eq!(
+            entry.operation,
+            AuditOperation::ReplyEvent {
+                vcpu: 1,
+                reply_type: "set_registers",
+                changes: vec![RegisterChange {
+                    name: "rax",
+                    before: Some(0),
+                    after: 0x2a,
+                }],
+            }
+        );
+        assert!(entry.to_json().unwrap().contains(
+            "\"operation\":\"reply_event\",\"vcpu\":1,\"reply_type\":\"set_registers\",\"changes\":[{\"name\":\"rax\",\"before\":0,\"after\":42}]"
+        ));
+    }
+
+    #[test]
+    fn test_skip_instruction_reply_records_rip() {
+        let (mut drv, entries) = audited_driver();
+        let event = Event {
+            vcpu: 0,
+            kind: EventType::Breakpoint {
+                gpa: 0x1000,
+                insn_len: 1,
+            },
+        };
+
+        drv.reply_event(event, EventReplyType::SkipInstruction)
+            .unwrap();
+
+        assert_eq!(
+            entries.borrow()[0].operation,
+            AuditOperation::ReplyEvent {
+                vcpu: 0,
+                reply_type: "skip_instruction",
+                changes: vec![RegisterChange {
+                    name: "rip",
+                    before: Some(0x1000),
+                    after: 0x1001,
+                }],
+            }
+        );
+    }
+}
@@ -0,0 +1,525 @@
+use std::collections::HashMap;
+use std::error::Error;
+
+use crate::api::events::{Event, EventReplyType, EventType, InterceptType};
+use crate::api::{Access, Introspectable, PAGE_SIZE};
+
+/// x86 INT3 opcode
+const INT3: u8 = 0xCC;
+
+#[derive(thiserror::Error, Debug)]
+pub enum BreakpointError {
+    #[error("a breakpoint is already set at 0x{0:x}")]
+    AlreadySet(u64),
+    #[error("no breakpoint set at 0x{0:x}")]
+    NotFound(u64),
+    #[error("failed to step over the breakpoint at 0x{0:x}, the breakpoint has been removed")]
+    StepOverFailed(u64, #[source] Box<dyn Error>),
+    #[error("failed to rearm the breakpoint at 0x{0:x}")]
+    RearmFailed(u64, #[source] Box<dyn Error>),
+}
+
+// a page made non executable while VCPUs step over one of its breakpoints
+#[derive(Debug)]
+struct HeldPage {
+    // access to restore, None if the page was already non executable
+    access: Option<Access>,
+    // number of VCPUs stepping over a breakpoint of this page
+    steppers: u32,
+    // execution faults of the other VCPUs, replied once the step over is done
+    held: Vec<Event>,
+}
+
+fn page_of(gpa: u64) -> u64 {
+    gpa & !(u64::from(PAGE_SIZE) - 1)
+}
+
+/// Software breakpoints management
+///
+/// Inserts INT3 instructions in the guest physical memory, and keeps track of the original bytes.
+/// When one of the managed breakpoints is hit, the original instruction is restored and the VCPU
+/// singlesteps over it, before the breakpoint is armed again.
+///
+/// While a VCPU steps over a breakpoint, its page is made non executable: the other VCPUs
+/// executing code from this page are held on a Pagefault event until the breakpoint is armed
+/// again, so that they can't run past it. The stepping VCPU's own execution faults are emulated.
+///
+/// Breakpoints which are not managed here belong to the guest, and are reinjected.
+///
+/// The driver must support the `Breakpoint`, `Pagefault` and `Singlestep` intercepts, and the
+/// `Breakpoint` and `Pagefault` intercepts must be enabled on every VCPU.
+/// If a breakpoint can't be stepped over, for example because the driver doesn't support
+/// singlestepping, it is removed and `handle_event` returns an error.
+///
+/// # Examples
+/// ```no_run
+/// use microvmi::api::breakpoints::BreakpointManager;
+/// use microvmi::api::events::InterceptType;
+/// use microvmi::init;
+///
+/// let mut drv = init(None, None).expect("Failed to init driver");
+/// drv.pause().expect("Failed to pause VM");
+/// for vcpu in 0..drv.get_vcpu_count().unwrap() {
+///     drv.toggle_intercept(vcpu, InterceptType::Breakpoint, true).unwrap();
+///     drv.toggle_intercept(vcpu, InterceptType::Pagefault, true).unwrap();
+/// }
+/// let mut bp = BreakpointManager::new();
+/// bp.insert(drv.as_ref(), 0x1000).expect("Failed to insert breakpoint");
+/// drv.resume().expect("Failed to resume VM");
+/// loop {
+///     if let Some(ev) = drv.listen(1000).unwrap() {
+///         let unhandled = bp
+///             .handle_event(drv.as_mut(), ev, |drv, ev| {
+///                 let regs = drv.read_registers(ev.vcpu)?;
+///                 println!("hit: {:?}", regs);
+///                 Ok(())
+///             })
+///             .unwrap();
+///         assert!(unhandled.is_none());
+///     }
+/// }
+/// ```
+#[derive(Debug, Default)]
+pub struct BreakpointManager {
+    // gpa -> original byte
+    breakpoints: HashMap<u64, u8>,
+    // VCPU -> gpa of the breakpoint being stepped over
+    stepping: HashMap<u16, u64>,
+    // page address -> page held during a step over
+    held_pages: HashMap<u64, HeldPage>,
+}
+
+impl BreakpointManager {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Insert a breakpoint
+    ///
+    /// # Arguments
+    /// * 'drv' - the driver used to modify the guest memory
+    /// * 'gpa' - physical address of the instruction to break on
+    ///
+    pub fn insert<I: Introspectable + ?Sized>(
+        &mut self,
+        drv: &I,
+        gpa: u64,
+    ) -> Result<(), Box<dyn Error>> {
+        if self.breakpoints.contains_key(&gpa) {
+            return Err(Box::new(BreakpointError::AlreadySet(gpa)));
+        }
+        let mut original = [0u8; 1];
+        let mut bytes_read = 0;
+        drv.read_physical(gpa, &mut original, &mut bytes_read)?;
+        drv.write_physical(gpa, &[INT3])?;
+        debug!("breakpoint inserted at 0x{:x}", gpa);
+        self.breakpoints.insert(gpa, original[0]);
+        Ok(())
+    }
+
+    /// Remove a breakpoint and restore the original instruction
+    ///
+    /// # Arguments
+    /// * 'drv' - the driver used to modify the guest memory
+    /// * 'gpa' - physical address of the breakpoint
+    ///
+    pub fn remove<I: Introspectable + ?Sized>(
+        &mut self,
+        drv: &I,
+        gpa: u64,
+    ) -> Result<(), Box<dyn Error>> {
+        let original = self
+            .breakpoints
+            .remove(&gpa)
+            .ok_or(BreakpointError::NotFound(gpa))?;
+        // already restored if a VCPU is stepping over it
+        if !self.is_stepping_over(gpa) {
+            drv.write_physical(gpa, &[original])?;
+        }
+        debug!("breakpoint removed at 0x{:x}", gpa);
+        Ok(())
+    }
+
+    /// Remove all breakpoints
+    pub fn remove_all<I: Introspectable + ?Sized>(
+        &mut self,
+        drv: &I,
+    ) -> Result<(), Box<dyn Error>> {
+        let addresses: Vec<u64> = self.breakpoints.keys().copied().collect();
+        for gpa in addresses {
+            self.remove(drv, gpa)?;
+        }
+        Ok(())
+    }
+
+    /// Whether a breakpoint is managed at the given physical address
+    pub fn contains(&self, gpa: u64) -> bool {
+        self.breakpoints.contains_key(&gpa)
+    }
+
+    /// Handle an event and reply to it if it concerns breakpoints
+    ///
+    /// Returns the event if it is unrelated to breakpoints, in which case the caller must handle
+    /// it and reply.
+    ///
+    /// # Arguments
+    /// * 'drv' - the driver which returned the event
+    /// * 'event' - the event returned by `listen`
+    /// * 'on_hit' - called when a managed breakpoint is hit, before the VCPU is resumed
+    ///
+    pub fn handle_event<I, F>(
+        &mut self,
+        drv: &mut I,
+        event: Event,
+        mut on_hit: F,
+    ) -> Result<Option<Event>, Box<dyn Error>>
+    where
+        I: Introspectable + ?Sized,
+        F: FnMut(&mut I, &Event) -> Result<(), Box<dyn Error>>,
+    {
+        match event.kind {
+            EventType::Breakpoint { gpa, .. } => {
+                let original = match self.breakpoints.get(&gpa) {
+                    Some(original) => *original,
+                    None => {
+                        debug!("guest breakpoint at 0x{:x}, reinjecting", gpa);
+                        drv.reply_event(event, EventReplyType::Reinject)?;
+                        return Ok(None);
+                    }
+                };
+                let hit_result = on_hit(drv, &event);
+                if let Err(e) = self.start_step_over(drv, event.vcpu, gpa, original) {
+                    // without a step over, the VCPU would hit the breakpoint again
+                    self.breakpoints.remove(&gpa);
+                    if let Err(e) = drv.write_physical(gpa, &[original]) {
+                        error!("failed to restore the instruction at 0x{:x}: {}", gpa, e);
+                    }
+                    drv.reply_event(event, EventReplyType::Retry)?;
+                    return Err(Box::new(BreakpointError::StepOverFailed(gpa, e)));
+                }
+                drv.reply_event(event, EventReplyType::Retry)?;
+                hit_result?;
+                Ok(None)
+            }
+            EventType::Singlestep { .. } => {
+                let gpa = match self.stepping.remove(&event.vcpu) {
+                    Some(gpa) => gpa,
+                    None => return Ok(Some(event)),
+                };
+                let result = self.end_step_over(drv, event.vcpu, gpa);
+                drv.reply_event(event, EventReplyType::Continue)?;
+                result?;
+                Ok(None)
+            }
+            EventType::Pagefault { gpa, access, .. } if access.contains(Access::X) => {
+                let held_page = match self.held_pages.get_mut(&page_of(gpa)) {
+                    Some(held_page) if held_page.access.is_some() => held_page,
+                    _ => return Ok(Some(event)),
+                };
+                if self.stepping.contains_key(&event.vcpu) {
+                    drv.reply_event(event, EventReplyType::Emulate)?;
+                } else {
+                    debug!("VCPU {} held until the step over is done", event.vcpu);
+                    held_page.held.push(event);
+                }
+                Ok(None)
+            }
+            _ => Ok(Some(event)),
+        }
+    }
+
+    // singlestep the VCPU over the original instruction, while the other VCPUs are held
+    fn start_step_over<I: Introspectable + ?Sized>(
+        &mut self,
+        drv: &mut I,
+        vcpu: u16,
+        gpa: u64,
+        original: u8,
+    ) -> Result<(), Box<dyn Error>> {
+        drv.toggle_intercept(vcpu, InterceptType::Singlestep, true)?;
+        let page = page_of(gpa);
+        let mut result = self.hold_page(drv, page);
+        if result.is_ok() {
+            // already restored if another VCPU is stepping over it
+            result = drv.write_physical(gpa, &[original]);
+            if result.is_err() {
+                if let Err(e) = self.release_page(drv, page) {
+                    error!("failed to release the page 0x{:x}: {}", page, e);
+                }
+            }
+        }
+        if let Err(e) = result {
+            if let Err(e) = drv.toggle_intercept(vcpu, InterceptType::Singlestep, false) {
+                error!("failed to disable singlestep on VCPU {}: {}", vcpu, e);
+            }
+            return Err(e);
+        }
+        self.held_pages.get_mut(&page).unwrap().steppers += 1;
+        self.stepping.insert(vcpu, gpa);
+        Ok(())
+    }
+
+    fn hold_page<I: Introspectable + ?Sized>(
+        &mut self,
+        drv: &I,
+        page: u64,
+    ) -> Result<(), Box<dyn Error>> {
+        if self.held_pages.contains_key(&page) {
+            return Ok(());
+        }
+        let access = drv.get_page_access(page)?;
+        let access = if access.contains(Access::X) {
+            drv.set_page_access(page, access - Access::X)?;
+            Some(access)
+        } else {
+            None
+        };
+        self.held_pages.insert(
+            page,
+            HeldPage {
+                access,
+                steppers: 0,
+                held: Vec::new(),
+            },
+        );
+        Ok(())
+    }
+
+    // restore the page access and resume the held VCPUs, once no VCPU steps over the page
+    fn release_page<I: Introspectable + ?Sized>(
+        &mut self,
+        drv: &mut I,
+        page: u64,
+    ) -> Result<(), Box<dyn Error>> {
+        match self.held_pages.get(&page) {
+            Some(held_page) if held_page.steppers == 0 => {}
+            _ => return Ok(()),
+        }
+        let held_page = self.held_pages.remove(&page).unwrap();
+        let mut result = match held_page.access {
+            Some(access) => drv.set_page_access(page, access),
+            None => Ok(()),
+        };
+        for held in held_page.held {
+            // execute the instruction again, now that the page is executable
+            result = result.and(drv.reply_event(held, EventReplyType::Retry));
+        }
+        result
+    }
+
+    // rearm the breakpoint and release the VCPUs held on its page
+    //
+    // The step over is completed even if a step fails, and the first error is returned.
+    // A breakpoint which can't be armed again stays managed.
+    fn end_step_over<I: Introspectable + ?Sized>(
+        &mut self,
+        drv: &mut I,
+        vcpu: u16,
+        gpa: u64,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut result = drv.toggle_intercept(vcpu, InterceptType::Singlestep, false);
+        // rearm, unless removed meanwhile or still being stepped over by another VCPU
+        if self.breakpoints.contains_key(&gpa) && !self.is_stepping_over(gpa) {
+            if let Err(e) = drv.write_physical(gpa, &[INT3]) {
+                error!("failed to rearm the breakpoint at 0x{:x}: {}", gpa, e);
+                result = result.and(Err(Box::new(BreakpointError::RearmFailed(gpa, e))));
+            }
+        }
+        let page = page_of(gpa);
+        if let Some(held_page) = self.held_pages.get_mut(&page) {
+            held_page.steppers -= 1;
+        }
+        result.and(self.release_page(drv, page))
+    }
+
+    fn is_stepping_over(&self, gpa: u64) -> bool {
+        self.stepping.values().any(|addr| *addr == gpa)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::driver::fake::FakeDriver;
+
+    fn fake_driver() -> FakeDriver {
+        FakeDriver::new(vec![0x90; 0x100])
+    }
+
+    fn breakpoint_event(vcpu: u16, gpa: u64) -> Event {
+        Event {
+            vcpu,
+            kind: EventType::Breakpoint { gpa, insn_len: 1 },
+        }
+    }
+
+    fn singlestep_event(vcpu: u16, gpa: u64) -> Event {
+        Event {
+            vcpu,
+            kind: EventType::Singlestep { gpa },
+        }
+    }
+
+    fn execute_fault_event(vcpu: u16, gpa: u64) -> Event {
+        Event {
+            vcpu,
+            kind: EventType::Pagefault {
+                gva: gpa,
+                gpa,
+                access: Access::X,
+                view: 0,
+            },
+        }
+    }
+
+    #[test]
+    fn test_insert_and_remove_restores_original_byte() {
+        let drv = fake_driver();
+        let mut bp = BreakpointManager::new();
+
+        bp.insert(&drv, 0x10).unwrap();
+        assert_eq!(drv.memory.borrow()[0x10], INT3);
+        assert!(bp.insert(&drv, 0x10).is_err());
+
+        bp.remove(&drv, 0x10).unwrap();
+        assert_eq!(drv.memory.borrow()[0x10], 0x90);
+        assert!(!bp.contains(0x10));
+    }
+
+    #[test]
+    fn test_hit_steps_over_and_rearms_breakpoint() {
+        let mut drv = fake_driver();
+        let mut bp = BreakpointManager::new();
+        bp.insert(&drv, 0x10).unwrap();
+        let mut hits = 0;
+
+        let result = bp
+            .handle_event(&mut drv, breakpoint_event(1, 0x10), |_, ev| {
+                assert_eq!(ev.vcpu, 1);
+                hits += 1;
+                Ok(())
+            })
+            .unwrap();
+
+        assert!(result.is_none());
+        assert_eq!(hits, 1);
+        assert_eq!(drv.memory.borrow()[0x10], 0x90);
+        assert_eq!(drv.singlestep, vec![(1, true)]);
+        assert_eq!(drv.page_access.borrow()[&0], Access::RW);
+        assert!(matches!(drv.replies[0], (1, EventReplyType::Retry)));
+
+        let result = bp
+            .handle_event(&mut drv, singlestep_event(1, 0x11), |_, _| Ok(()))
+            .unwrap();
+
+        assert!(result.is_none());
+        assert_eq!(drv.memory.borrow()[0x10], INT3);
+        assert_eq!(drv.singlestep, vec![(1, true), (1, false)]);
+        assert_eq!(drv.page_access.borrow()[&0], Access::RWX);
+        assert!(matches!(drv.replies[1], (1, EventReplyType::Continue)));
+    }
+
+    #[test]
+    fn test_other_vcpus_are_held_during_step_over() {
+        let mut drv = fake_driver();
+        let mut bp = BreakpointManager::new();
+        bp.insert(&drv, 0x10).unwrap();
+        bp.handle_event(&mut drv, breakpoint_event(0, 0x10), |_, _| Ok(()))
+            .unwrap();
+
+        // VCPU 1 reaches the breakpoint page
+        let result = bp
+            .handle_event(&mut drv, execute_fault_event(1, 0x10), |_, _| Ok(()))
+            .unwrap();
+        assert!(result.is_none());
+        assert_eq!(drv.replies.len(), 1);
+
+        // the stepping VCPU executes from the same page
+        let result = bp
+            .handle_event(&mut drv, execute_fault_event(0, 0x10), |_, _| Ok(()))
+            .unwrap();
+        assert!(result.is_none());
+        assert!(matches!(drv.replies[1], (0, EventReplyType::Emulate)));
+
+        bp.handle_event(&mut drv, singlestep_event(0, 0x11), |_, _| Ok(()))
+            .unwrap();
+
+        assert_eq!(drv.memory.borrow()[0x10], INT3);
+        assert!(matches!(drv.replies[2], (1, EventReplyType::Retry)));
+        assert!(matches!(drv.replies[3], (0, EventReplyType::Continue)));
+    }
+
+    #[test]
+    fn test_unrelated_execute_fault_is_returned() {
+        let mut drv = fake_driver();
+        let mut bp = BreakpointManager::new();
+        bp.insert(&drv, 0x10).unwrap();
+
+        let result = bp
+            .handle_event(&mut drv, execute_fault_event(1, 0x10), |_, _| Ok(()))
+            .unwrap();
+
+        assert!(result.is_some());
+        assert!(drv.replies.is_empty());
+    }
+
+    #[test]
+    fn test_step_over_failure_removes_breakpoint() {
+        let mut drv = FakeDriver {
+            no_singlestep: true,
+            ..fake_driver()
+        };
+        let mut bp = BreakpointManager::new();
+        bp.insert(&drv, 0x10).unwrap();
+
+        let err = bp
+            .handle_event(&mut drv, breakpoint_event(0, 0x10), |_, _| Ok(()))
+            .expect_err("Expected error, got ok instead!");
+
+        assert!(matches!(
+            err.downcast_ref::<BreakpointError>(),
+            Some(BreakpointError::StepOverFailed(0x10, _))
+        ));
+        assert!(!bp.contains(0x10));
+        assert_eq!(drv.memory.borrow()[0x10], 0x90);
+        assert!(drv.page_access.borrow().is_empty());
+        assert!(matches!(drv.replies[0], (0, EventReplyType::Retry)));
+    }
+
+    #[test]
+    fn test_rearm_failure_keeps_breakpoint() {
+        let mut drv = fake_driver();
+        let mut bp = BreakpointManager::new();
+        bp.insert(&drv, 0x10).unwrap();
+        bp.handle_event(&mut drv, breakpoint_event(0, 0x10), |_, _| Ok(()))
+            .unwrap();
+        drv.read_only = true;
+
+        let err = bp
+            .handle_event(&mut drv, singlestep_event(0, 0x11), |_, _| Ok(()))
+            .expect_err("Expected error, got ok instead!");
+
+        assert!(matches!(
+            err.downcast_ref::<BreakpointError>(),
+            Some(BreakpointError::RearmFailed(0x10, _))
+        ));
+        assert!(bp.contains(0x10));
+        assert_eq!(drv.page_access.borrow()[&0], Access::RWX);
+        assert!(matches!(drv.replies[1], (0, EventReplyType::Continue)));
+    }
+
+    #[test]
+    fn test_guest_breakpoint_is_reinjected() {
+        let mut drv = fake_driver();
+        let mut bp = BreakpointManager::new();
+
+        let result = bp
+            .handle_event(&mut drv, breakpoint_event(0, 0x20), |_, _| {
+                panic!("not a managed breakpoint")
+            })
+            .unwrap();
+
+        assert!(result.is_none());
+        assert!(matches!(drv.replies[0], (0, EventReplyType::Reinject)));
+    }
+}
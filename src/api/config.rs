@@ -0,0 +1,559 @@
+//! This module loads the driver initialization parameters from a configuration file and from the environment
+//!
+//! The configuration file is written in TOML, or in JSON if its extension is `.json`:
+//! ```toml
+//! driver = "kvm"
+//!
+//! [common]
+//! vm_name = "windows10"
+//! read_only = false
+//! audit_log = "/var/log/microvmi-audit.jsonl"
+//!
+//! [kvm]
+//! unix_socket = "/tmp/introspector"
+//! # or listen on a vsock port
+//! # vsock_port = 1234
+//!
+//! [xen]
+//! domid = 5
+//! # for domains which are not allowed to monitor the guest
+//! # monitor_ring = false
+//!
+//! [memflow]
+//! connector_name = "qemu"
+//! connector_args = ["arg1", "arg2"]
+//! os_name = "win32"
+//! os_args = ["arg1"]
+//!
+//! [remote]
+//! addr = "tcp://192.168.1.10:7475"
+//! token = "secret"
+//! ```
+//!
+//! Each setting can be overridden by an environment variable:
+//!
+//! | variable                          | setting                                   |
+//! |-----------------------------------|-------------------------------------------|
+//! | `MICROVMI_CONFIG`                 | path of the configuration file            |
+//! | `MICROVMI_DRIVER`                 | `driver`                                  |
+//! | `MICROVMI_VM_NAME`                | `common.vm_name`                          |
+//! | `MICROVMI_READ_ONLY`              | `common.read_only`                        |
+//! | `MICROVMI_AUDIT_LOG`              | `common.audit_log`                        |
+//! | `MICROVMI_KVM_UNIX_SOCKET`        | `kvm.unix_socket`                         |
+//! | `MICROVMI_KVM_VSOCK_PORT`         | `kvm.vsock_port`                          |
+//! | `MICROVMI_XEN_DOMID`              | `xen.domid`                               |
+//! | `MICROVMI_XEN_MONITOR_RING`       | `xen.monitor_ring`                        |
+//! | `MICROVMI_MEMFLOW_CONNECTOR_NAME` | `memflow.connector_name`                  |
+//! | `MICROVMI_MEMFLOW_CONNECTOR_ARGS` | `memflow.connector_args`, comma separated |
+//! | `MICROVMI_MEMFLOW_OS_NAME`        | `memflow.os_name`                         |
+//! | `MICROVMI_MEMFLOW_OS_ARGS`        | `memflow.os_args`, comma separated        |
+//! | `MICROVMI_REMOTE_ADDR`            | `remote.addr`                             |
+//! | `MICROVMI_REMOTE_TOKEN`           | `remote.token`                            |
+//!
+//! The precedence is: environment variables, then configuration file.
+//! `MICROVMI_PLUGIN_DIR` and `MICROVMI_SERVER_TOKEN` are read by the plugins and the server.
+//! Any other `MICROVMI_*` variable is refused, to catch the misspelled settings.
+
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use serde::Deserialize;
+
+use crate::api::params::{
+    CommonInitParams, DriverInitParams, KVMInitParams, MemflowConnectorParams, MemflowInitParams,
+    RemoteInitParams, XenInitParams, XenMonitorRing, VMADDR_CID_ANY,
+};
+use crate::api::DriverType;
+
+/// Environment variable holding the path of the configuration file
+pub const CONFIG_ENV_VAR: &str = "MICROVMI_CONFIG";
+const ENV_PREFIX: &str = "MICROVMI_";
+/// `MICROVMI_*` variables which are not driver settings
+const OTHER_ENV_VARS: [&str; 3] = [
+    CONFIG_ENV_VAR,
+    "MICROVMI_PLUGIN_DIR",
+    "MICROVMI_SERVER_TOKEN",
+];
+
+#[derive(thiserror::Error, Debug)]
+pub enum ConfigError {
+    #[error("failed to read configuration file {0}: {1}")]
+    ReadError(PathBuf, io::Error),
+    #[error("invalid configuration file {0}: {1}")]
+    ParseError(PathBuf, String),
+    #[error("invalid value {1:?} for {0}")]
+    InvalidValue(String, String),
+    #[error("{0} is set but no memflow connector name is specified")]
+    MissingMemflowConnectorName(String),
+    #[error("{0} and {1} cannot be both set")]
+    ConflictingSettings(String, String),
+    #[error("unknown variable {0}")]
+    UnknownVariable(String),
+}
+
+/// Driver settings, as found in a configuration file or in the environment
+///
+/// Unset settings are None.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Config {
+    pub driver_type: Option<DriverType>,
+    pub vm_name: Option<String>,
+    pub read_only: Option<bool>,
+    pub audit_log: Option<String>,
+    pub kvm_unix_socket: Option<String>,
+    pub kvm_vsock_port: Option<u32>,
+    pub xen_domid: Option<u32>,
+    pub xen_monitor_ring: Option<bool>,
+    pub memflow_connector_name: Option<String>,
+    pub memflow_connector_args: Option<Vec<String>>,
+    pub memflow_os_name: Option<String>,
+    pub memflow_os_args: Option<Vec<String>>,
+    pub remote_addr: Option<String>,
+    pub remote_token: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    driver: Option<String>,
+    #[serde(default)]
+    common: CommonSection,
+    #[serde(default)]
+    kvm: KVMSection,
+    #[serde(default)]
+    xen: XenSection,
+    #[serde(default)]
+    memflow: MemflowSection,
+    #[serde(default)]
+    remote: RemoteSection,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct CommonSection {
+    vm_name: Option<String>,
+    read_only: Option<bool>,
+    audit_log: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct KVMSection {
+    unix_socket: Option<String>,
+    vsock_port: Option<u32>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct XenSection {
+    domid: Option<u32>,
+    monitor_ring: Option<bool>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct MemflowSection {
+    connector_name: Option<String>,
+    connector_args: Option<Vec<String>>,
+    os_name: Option<String>,
+    os_args: Option<Vec<String>>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct RemoteSection {
+    addr: Option<String>,
+    token: Option<String>,
+}
+
+fn parse_driver_type(key: &str, value: &str) -> Result<DriverType, ConfigError> {
+    DriverType::from_str(value)
+        .map_err(|_| ConfigError::InvalidValue(key.to_string(), value.to_string()))
+}
+
+fn parse_bool(name: String, value: String) -> Result<bool, ConfigError> {
+    match value.to_lowercase().as_str() {
+        "true" | "1" => Ok(true),
+        "false" | "0" => Ok(false),
+        _ => Err(ConfigError::InvalidValue(name, value)),
+    }
+}
+
+impl Config {
+    /// Load the settings from a TOML or JSON configuration file
+    ///
+    /// # Arguments
+    /// * 'path' - path of the configuration file, parsed as JSON if its extension is `.json`
+    ///
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, ConfigError> {
+        let path = path.as_ref();
+        let content =
+            fs::read_to_string(path).map_err(|e| ConfigError::ReadError(path.to_path_buf(), e))?;
+        let parse_error = |e: String| ConfigError::ParseError(path.to_path_buf(), e);
+        let file: ConfigFile = if path.extension().is_some_and(|ext| ext == "json") {
+            serde_json::from_str(&content).map_err(|e| parse_error(e.to_string()))?
+        } else {
+            toml::from_str(&content).map_err(|e| parse_error(e.to_string()))?
+        };
+        Ok(Config {
+            driver_type: file
+                .driver
+                .map(|driver| parse_driver_type("driver", &driver))
+                .transpose()?,
+            vm_name: file.common.vm_name,
+            read_only: file.common.read_only,
+            audit_log: file.common.audit_log,
+            kvm_unix_socket: file.kvm.unix_socket,
+            kvm_vsock_port: file.kvm.vsock_port,
+            xen_domid: file.xen.domid,
+            xen_monitor_ring: file.xen.monitor_ring,
+            memflow_connector_name: file.memflow.connector_name,
+            memflow_connector_args: file.memflow.connector_args,
+            memflow_os_name: file.memflow.os_name,
+            memflow_os_args: file.memflow.os_args,
+            remote_addr: file.remote.addr,
+            remote_token: file.remote.token,
+        })
+    }
+
+    /// Load the settings from the `MICROVMI_*` environment variables
+    pub fn from_env() -> Result<Self, ConfigError> {
+        Config::from_vars(env::vars())
+    }
+
+    /// Load the settings from `MICROVMI_*` variables
+    ///
+    /// # Arguments
+    /// * 'vars' - variable names and values, the variables without the `MICROVMI_` prefix are ignored
+    ///
+    pub fn from_vars<I: IntoIterator<Item = (String, String)>>(
+        vars: I,
+    ) -> Result<Self, ConfigError> {
+        let mut config = Config::default();
+        for (name, value) in vars {
+            let setting = match name.strip_prefix(ENV_PREFIX) {
+                Some(setting) => setting,
+                None => continue,
+            };
+            match setting {
+                "DRIVER" => config.driver_type = Some(parse_driver_type(&name, &value)?),
+                "VM_NAME" => config.vm_name = Some(value),
+                "READ_ONLY" => config.read_only = Some(parse_bool(name, value)?),
+                "AUDIT_LOG" => config.audit_log = Some(value),
+                "KVM_UNIX_SOCKET" => config.kvm_unix_socket = Some(value),
+                "KVM_VSOCK_PORT" => {
+                    config.kvm_vsock_port = Some(
+                        value
+                            .parse()
+                            .map_err(|_| ConfigError::InvalidValue(name, value))?,
+                    )
+                }
+                "XEN_DOMID" => {
+                    config.xen_domid = Some(
+                        value
+                            .parse()
+                            .map_err(|_| ConfigError::InvalidValue(name, value))?,
+                    )
+                }
+                "XEN_MONITOR_RING" => config.xen_monitor_ring = Some(parse_bool(name, value)?),
+                "MEMFLOW_CONNECTOR_NAME" => config.memflow_connector_name = Some(value),
+                "MEMFLOW_CONNECTOR_ARGS" => {
+                    config.memflow_connector_args =
+                        Some(value.split(',').map(String::from).collect())
+                }
+                "MEMFLOW_OS_NAME" => config.memflow_os_name = Some(value),
+                "MEMFLOW_OS_ARGS" => {
+                    config.memflow_os_args = Some(value.split(',').map(String::from).collect())
+                }
+                "REMOTE_ADDR" => config.remote_addr = Some(value),
+                "REMOTE_TOKEN" => config.remote_token = Some(value),
+                _ if OTHER_ENV_VARS.contains(&name.as_str()) => {}
+                _ => return Err(ConfigError::UnknownVariable(name)),
+            }
+        }
+        Ok(config)
+    }
+
+    /// Load the configuration file, then override its settings with the environment
+    ///
+    /// # Arguments
+    /// * 'path' - optional path of the configuration file, otherwise taken from `MICROVMI_CONFIG` if set
+    ///
+    pub fn load(path: Option<&Path>) -> Result<Self, ConfigError> {
+        let path = path
+            .map(PathBuf::from)
+            .or_else(|| env::var_os(CONFIG_ENV_VAR).map(PathBuf::from));
+        let file_config = match path {
+            Some(path) => Config::from_file(path)?,
+            None => Config::default(),
+        };
+        Ok(file_config.merge(Config::from_env()?))
+    }
+
+    /// Merge two configurations, the settings of the other one take precedence
+    pub fn merge(self, other: Config) -> Config {
+        Config {
+            driver_type: other.driver_type.or(self.driver_type),
+            vm_name: other.vm_name.or(self.vm_name),
+            read_only: other.read_only.or(self.read_only),
+            audit_log: other.audit_log.or(self.audit_log),
+            kvm_unix_socket: other.kvm_unix_socket.or(self.kvm_unix_socket),
+            kvm_vsock_port: other.kvm_vsock_port.or(self.kvm_vsock_port),
+            xen_domid: other.xen_domid.or(self.xen_domid),
+            xen_monitor_ring: other.xen_monitor_ring.or(self.xen_monitor_ring),
+            memflow_connector_name: other.memflow_connector_name.or(self.memflow_connector_name),
+            memflow_connector_args: other.memflow_connector_args.or(self.memflow_connector_args),
+            memflow_os_name: other.memflow_os_name.or(self.memflow_os_name),
+            memflow_os_args: other.memflow_os_args.or(self.memflow_os_args),
+            remote_addr: other.remote_addr.or(self.remote_addr),
+            remote_token: other.remote_token.or(self.remote_token),
+        }
+    }
+
+    /// Build the driver type and initialization parameters
+    pub fn into_init_params(self) -> Result<(Option<DriverType>, DriverInitParams), ConfigError> {
+        let common =
+            if self.vm_name.is_some() || self.read_only.is_some() || self.audit_log.is_some() {
+                Some(CommonInitParams {
+                    vm_name: self.vm_name.unwrap_or_default(),
+                    read_only: self.read_only.unwrap_or_default(),
+                    audit_log: self.audit_log,
+                })
+            } else {
+                None
+            };
+        let memflow = match self.memflow_connector_name {
+            Some(connector_name) => Some(MemflowInitParams {
+                connector_name,
+                connector_args: self
+                    .memflow_connector_args
+                    .map(|args| MemflowConnectorParams::Default { args }),
+                os_name: self.memflow_os_name,
+                os_args: self.memflow_os_args,
+            }),
+            None => {
+                let orphan = [
+                    (
+                        "memflow.connector_args",
+                        self.memflow_connector_args.is_some(),
+                    ),
+                    ("memflow.os_name", self.memflow_os_name.is_some()),
+                    ("memflow.os_args", self.memflow_os_args.is_some()),
+                ]
+                .iter()
+                .find(|(_, is_set)| *is_set)
+                .map(|(setting, _)| String::from(*setting));
+                if let Some(setting) = orphan {
+                    return Err(ConfigError::MissingMemflowConnectorName(setting));
+                }
+                None
+            }
+        };
+        let kvm = match (self.kvm_unix_socket, self.kvm_vsock_port) {
+            (Some(_), Some(_)) => {
+                return Err(ConfigError::ConflictingSettings(
+                    String::from("kvm.unix_socket"),
+                    String::from("kvm.vsock_port"),
+                ))
+            }
+            (Some(path), None) => Some(KVMInitParams::UnixSocket { path }),
+            (None, Some(port)) => Some(KVMInitParams::Vsock {
+                cid: VMADDR_CID_ANY,
+                port,
+            }),
+            (None, None) => None,
+        };
+        let xen = match (self.xen_domid, self.xen_monitor_ring) {
+            (domid, Some(enabled)) => Some(XenInitParams::Options {
+                domid,
+                monitor_ring: XenMonitorRing::from(enabled),
+            }),
+            (domid, None) => domid.map(XenInitParams::DomId),
+        };
+        // a missing address or token is reported by the driver
+        let remote = if self.remote_addr.is_some() || self.remote_token.is_some() {
+            Some(RemoteInitParams {
+                addr: self.remote_addr.unwrap_or_default(),
+                token: self.remote_token.unwrap_or_default(),
+            })
+        } else {
+            None
+        };
+        let init_params = DriverInitParams {
+            common,
+            xen,
+            kvm,
+            memflow,
+            remote,
+            ..Default::default()
+        };
+        Ok((self.driver_type, init_params))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    fn vars(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+        vars.iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    fn write_config(name: &str, content: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("microvmi-{}-{}", std::process::id(), name));
+        fs::File::create(&path)
+            .unwrap()
+            .write_all(content.as_bytes())
+            .unwrap();
+        path
+    }
+
+    #[test]
+    fn test_toml_file() {
+        let path = write_config(
+            "config.toml",
+            "driver = \"kvm\"\n[common]\nvm_name = \"win10\"\n[kvm]\nunix_socket = \"/tmp/introspector\"\n",
+        );
+
+        let config = Config::from_file(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(config.driver_type, Some(DriverType::KVM));
+        assert_eq!(config.vm_name.as_deref(), Some("win10"));
+        assert_eq!(config.kvm_unix_socket.as_deref(), Some("/tmp/introspector"));
+    }
+
+    #[test]
+    fn test_json_file_unknown_key_is_reported() {
+        let path = write_config(
+            "config.json",
+            "{\"kvm\": {\"socket\": \"/tmp/introspector\"}}",
+        );
+
+        let error = Config::from_file(&path).unwrap_err();
+        fs::remove_file(&path).unwrap();
+
+        assert!(error.to_string().contains("socket"), "{}", error);
+    }
+
+    #[test]
+    fn test_env_overrides_file() {
+        let file_config = Config {
+            vm_name: Some(String::from("win10")),
+            read_only: Some(false),
+            ..Default::default()
+        };
+        let env_config = Config::from_vars(vars(&[
+            ("MICROVMI_READ_ONLY", "true"),
+            ("MICROVMI_MEMFLOW_CONNECTOR_NAME", "qemu"),
+            ("MICROVMI_MEMFLOW_CONNECTOR_ARGS", "a,b"),
+            ("PATH", "/usr/bin"),
+        ]))
+        .unwrap();
+
+        let (driver_type, init_params) = file_config.merge(env_config).into_init_params().unwrap();
+
+        assert_eq!(driver_type, None);
+        assert_eq!(
+            init_params.common,
+            Some(CommonInitParams {
+                vm_name: String::from("win10"),
+                read_only: true,
+                audit_log: None,
+            })
+        );
+        assert_eq!(
+            init_params.memflow.unwrap().connector_args,
+            Some(MemflowConnectorParams::Default {
+                args: vec![String::from("a"), String::from("b")]
+            })
+        );
+    }
+
+    #[test]
+    fn test_audit_log_alone_sets_common_params() {
+        let config =
+            Config::from_vars(vars(&[("MICROVMI_AUDIT_LOG", "/tmp/audit.jsonl")])).unwrap();
+
+        let (_, init_params) = config.into_init_params().unwrap();
+
+        assert_eq!(
+            init_params.common,
+            Some(CommonInitParams {
+                audit_log: Some(String::from("/tmp/audit.jsonl")),
+                ..Default::default()
+            })
+        );
+    }
+
+    #[test]
+    fn test_xen_monitor_ring_setting() {
+        let path = write_config("xen.toml", "[xen]\ndomid = 5\nmonitor_ring = false\n");
+
+        let file_config = Config::from_file(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        let (_, init_params) = file_config.into_init_params().unwrap();
+
+        assert_eq!(
+            init_params.xen,
+            Some(XenInitParams::Options {
+                domid: Some(5),
+                monitor_ring: XenMonitorRing::Disabled,
+            })
+        );
+    }
+
+    #[test]
+    fn test_invalid_env_value_is_reported() {
+        let error = Config::from_vars(vars(&[("MICROVMI_DRIVER", "hyperv")])).unwrap_err();
+
+        assert!(matches!(error, ConfigError::InvalidValue(name, _) if name == "MICROVMI_DRIVER"));
+    }
+
+    #[test]
+    fn test_remote_settings() {
+        let config = Config::from_vars(vars(&[
+            ("MICROVMI_DRIVER", "remote"),
+            ("MICROVMI_REMOTE_ADDR", "tcp://10.0.0.1:7475"),
+            ("MICROVMI_REMOTE_TOKEN", "secret"),
+        ]))
+        .unwrap();
+
+        let (driver_type, init_params) = config.into_init_params().unwrap();
+
+        assert_eq!(driver_type, Some(DriverType::Remote));
+        assert_eq!(
+            init_params.remote,
+            Some(RemoteInitParams {
+                addr: String::from("tcp://10.0.0.1:7475"),
+                token: String::from("secret"),
+            })
+        );
+    }
+
+    #[test]
+    fn test_other_component_variables_are_ignored() {
+        let config = Config::from_vars(vars(&[
+            ("MICROVMI_CONFIG", "/etc/microvmi.toml"),
+            ("MICROVMI_PLUGIN_DIR", "/opt/microvmi/plugins"),
+            ("MICROVMI_SERVER_TOKEN", "secret"),
+        ]))
+        .unwrap();
+
+        assert_eq!(Config::default(), config);
+    }
+
+    #[test]
+    fn test_unknown_variable_is_reported() {
+        let error = Config::from_vars(vars(&[("MICROVMI_VMNAME", "win10")])).unwrap_err();
+
+        assert!(matches!(error, ConfigError::UnknownVariable(name) if name == "MICROVMI_VMNAME"));
+    }
+}
@@ -1,8 +1,13 @@
+use crate::api::registers::Registers;
 use crate::api::Access;
+use serde::{Deserialize, Serialize};
 
+// C enumerators prefixed with the enum name: without it, the generated header declares
+// duplicate enumerators and doesn't compile (breaking change, see the C API documentation)
 /// Various types of intercepts handled by libmicrovmi
+/// cbindgen:prefix-with-name
 #[repr(C)]
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum InterceptType {
     /// Intercept when value of cr register is changed by the guest
     Cr(CrType),
@@ -11,11 +16,25 @@ pub enum InterceptType {
     /// Intercept when guest requests an access to a page for which the requested type of access is not granted. For example , guest tries to write on a read only page.
     Breakpoint,
     Pagefault,
+    /// Intercept when the guest executes the CPUID instruction
+    Cpuid,
+    /// Intercept when the guest loads or stores a descriptor table register (LGDT/LIDT/LLDT/LTR, SGDT/SIDT/SLDT/STR)
+    Descriptor,
+    /// Intercept when the guest writes an extended control register with XSETBV
+    Xsetbv,
+    /// Intercept when the guest executes an I/O port instruction (IN/OUT/INS/OUTS)
+    Io,
+    /// Intercept when the guest executes VMCALL to issue a hypercall
+    Hypercall,
+    /// Intercept after each instruction executed by the VCPU
+    Singlestep,
 }
 
+// C enumerators prefixed with the enum name, see InterceptType
 /// Various types of events along with their relevant attributes being handled by this driver
+/// cbindgen:prefix-with-name
 #[repr(C)]
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 pub enum EventType {
     ///Cr register interception
     Cr {
@@ -47,12 +66,72 @@ pub enum EventType {
         gpa: u64,
         /// Acsess responsible for thr pagefault
         access: Access,
+        /// View in which the pagefault occurred
+        view: u16,
     },
+    ///CPUID instruction interception
+    Cpuid {
+        /// value of EAX when CPUID was executed
+        leaf: u32,
+        /// value of ECX when CPUID was executed
+        subleaf: u32,
+        /// instruction length
+        insn_len: u8,
+    },
+    ///Descriptor table register access interception
+    Descriptor {
+        /// descriptor table register being accessed
+        descriptor: DescriptorType,
+        /// true for a load (LGDT/LIDT/LLDT/LTR), false for a store
+        write: bool,
+    },
+    ///XSETBV interception
+    Xsetbv {
+        /// index of the extended control register (0 for XCR0)
+        xcr: u32,
+        /// new value written by the guest
+        new: u64,
+    },
+    ///I/O port instruction interception
+    Io {
+        /// I/O port number
+        port: u16,
+        /// size of the access in bytes
+        size: u32,
+        /// true for an input (IN/INS), false for an output (OUT/OUTS)
+        input: bool,
+        /// true for a string instruction (INS/OUTS)
+        string: bool,
+    },
+    ///VMCALL interception
+    Hypercall {
+        /// hypercall number, as passed in RAX by the guest
+        nr: u64,
+    },
+    ///Singlestep interception
+    Singlestep {
+        /// Physical memory address of the next instruction
+        gpa: u64,
+    },
+}
+
+///Types of x86 descriptor table registers
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum DescriptorType {
+    ///Interrupt Descriptor Table Register
+    Idtr,
+    ///Global Descriptor Table Register
+    Gdtr,
+    ///Local Descriptor Table Register
+    Ldtr,
+    ///Task Register
+    Tr,
 }
 
 ///Types of x86 control registers are listed here
 #[repr(C)]
-#[derive(Debug, Copy, Clone, PartialEq)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum CrType {
     ///Has various control flags that modify the basic operation of the processor.
     Cr0,
@@ -64,6 +143,7 @@ pub enum CrType {
 
 ///This provides an abstraction of event which the hypervisor reports and using which we introspect the guest
 #[repr(C)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct Event {
     ///vcpu on which the event is detected
     pub vcpu: u16,
@@ -71,9 +151,26 @@ pub struct Event {
     pub kind: EventType,
 }
 
+// C enumerators prefixed with the enum name, see InterceptType
 ///Reply provided to the hypervisor after detecting an event
+/// cbindgen:prefix-with-name
 #[repr(C)]
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
+#[allow(clippy::large_enum_variant)]
 pub enum EventReplyType {
+    /// Let the hypervisor apply its default action and resume the VCPU
     Continue,
+    /// Deliver the intercepted breakpoint to the guest, as if it was not monitored
+    Reinject,
+    /// Resume the VCPU after the instruction which triggered the event
+    SkipInstruction,
+    /// Emulate the faulting instruction, ignoring the page access restrictions
+    Emulate,
+    /// Resume the VCPU with the given register values
+    ///
+    /// Only the general purpose registers, RIP and RFLAGS can be changed, other modified
+    /// registers are refused with an error.
+    SetRegisters(Registers),
+    /// Resume the VCPU and execute the same instruction again
+    Retry,
 }
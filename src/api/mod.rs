@@ -1,16 +1,35 @@
 use enum_iterator::IntoEnumIterator;
+use serde::{Deserialize, Serialize};
 use std::error::Error;
 
 use events::{Event, EventReplyType, InterceptType};
+use os::{ModuleInfo, ProcessInfo};
+use pause::PauseGuard;
 use registers::Registers;
 
+use crate::errors::UnsupportedOperation;
+
+pub mod audit;
+pub mod breakpoints;
+pub mod config;
 pub mod events;
+pub mod os;
 pub mod params;
+pub mod pause;
+pub mod plugin;
+pub mod protocol;
 pub mod registers;
+pub mod url;
+pub mod vms;
 
 #[allow(clippy::bad_bit_mask)]
 mod flags {
+    use serde::{Deserialize, Serialize};
+
     bitflags! {
+        // passed to the C API and to the plugins
+        #[repr(transparent)]
+        #[derive(Serialize, Deserialize)]
         pub struct Access: u32 {
             const NIL=0b00000000;
             const R=0b00000001;
@@ -27,12 +46,14 @@ pub use flags::Access;
 
 ///Represents the available hypervisor VMI drivers supported by libmicrovmi
 #[repr(C)]
-#[derive(Debug, Copy, Clone, PartialEq, IntoEnumIterator)]
+#[derive(Debug, Copy, Clone, PartialEq, IntoEnumIterator, Serialize, Deserialize)]
 pub enum DriverType {
     KVM,
     Memflow,
     VirtualBox,
     Xen,
+    // appended to keep the values of the C API, and tried last by init
+    Remote,
 }
 
 // impl TryInto<DriverInitParam> for DriverInitParamFFI {
@@ -123,6 +144,117 @@ pub trait Introspectable {
         unimplemented!();
     }
 
+    ///set page access on a range of pages
+    ///
+    /// Drivers use as few hypervisor calls as their library allows, which may still be one per page.
+    /// By default, set_page_access is called on each page.
+    ///
+    /// # Arguments
+    /// * 'start' - physical address within the first page of the range
+    /// * 'end' - physical address following the range (excluded)
+    /// * 'access' - access flags to be set on every page of the range
+    ///
+    fn set_page_access_range(
+        &self,
+        start: u64,
+        end: u64,
+        access: Access,
+    ) -> Result<(), Box<dyn Error>> {
+        let first_page = start & !(u64::from(PAGE_SIZE) - 1);
+        for paddr in (first_page..end).step_by(PAGE_SIZE as usize) {
+            self.set_page_access(paddr, access)?;
+        }
+        Ok(())
+    }
+
+    ///set page access on multiple pages
+    ///
+    /// Contiguous pages sharing the same access are grouped when the driver supports it.
+    /// By default, set_page_access is called on each page.
+    ///
+    /// # Arguments
+    /// * 'pages' - list of physical addresses and the access flags to be set on their page
+    ///
+    fn set_page_access_multi(&self, pages: &[(u64, Access)]) -> Result<(), Box<dyn Error>> {
+        for (paddr, access) in pages {
+            self.set_page_access(*paddr, *access)?;
+        }
+        Ok(())
+    }
+
+    /// Create a new view on the guest physical memory (alternate EPT / altp2m)
+    ///
+    /// Returns the identifier of the new view. View 0 is the default view used by the guest.
+    /// Returns an `UnsupportedOperation` error if the driver has no views.
+    ///
+    fn create_view(&mut self) -> Result<u16, Box<dyn Error>> {
+        Err(Box::new(UnsupportedOperation {
+            driver_type: self.get_driver_type(),
+            operation: "view creation",
+        }))
+    }
+
+    /// Destroy a view created by create_view
+    ///
+    /// # Arguments
+    /// * 'view' - identifier of the view to destroy
+    ///
+    fn destroy_view(&mut self, _view: u16) -> Result<(), Box<dyn Error>> {
+        Err(Box::new(UnsupportedOperation {
+            driver_type: self.get_driver_type(),
+            operation: "view destruction",
+        }))
+    }
+
+    /// Switch the view used by a VCPU
+    ///
+    /// On Xen, the VCPU must have a pending event: the switch is applied with
+    /// the reply to that event, by reply_event.
+    ///
+    /// # Arguments
+    /// * 'vcpu' - vcpu id for which the view should be switched
+    /// * 'view' - identifier of the view to use
+    ///
+    fn switch_view(&mut self, _vcpu: u16, _view: u16) -> Result<(), Box<dyn Error>> {
+        Err(Box::new(UnsupportedOperation {
+            driver_type: self.get_driver_type(),
+            operation: "view switch",
+        }))
+    }
+
+    ///set page access in a given view
+    ///
+    /// # Arguments
+    /// * 'view' - identifier of the view
+    /// * 'paddr' - physical address of the page whose access we want to set
+    /// * 'access' - access flags to be set on the given page
+    ///
+    fn set_page_access_in_view(
+        &self,
+        _view: u16,
+        _paddr: u64,
+        _access: Access,
+    ) -> Result<(), Box<dyn Error>> {
+        Err(Box::new(UnsupportedOperation {
+            driver_type: self.get_driver_type(),
+            operation: "page access in a view",
+        }))
+    }
+
+    /// Remap a guest physical page to another one, in a given view
+    ///
+    /// # Arguments
+    /// * 'view' - identifier of the view
+    /// * 'from' - physical address of the page to remap
+    /// * 'to' - physical address of the page which will be seen instead
+    ///
+    fn remap_gfn(&mut self, _view: u16, _from: u64, _to: u64) -> Result<(), Box<dyn Error>> {
+        Err(Box::new(UnsupportedOperation {
+            driver_type: self.get_driver_type(),
+            operation: "GFN remapping",
+        }))
+    }
+
     /// Write register values
     ///
     /// # Arguments
@@ -135,18 +267,54 @@ pub trait Introspectable {
 
     /// Used to pause the VM
     ///
+    /// Pause requests are counted: the VM is resumed once each of them has been matched by a resume.
+    /// See [pause_guard](trait.Introspectable.html#method.pause_guard) to resume automatically.
+    ///
     fn pause(&mut self) -> Result<(), Box<dyn Error>> {
         unimplemented!();
     }
 
     /// Used to resume the VM
     ///
+    /// The VM stays paused while other pause requests are pending.
+    ///
     fn resume(&mut self) -> Result<(), Box<dyn Error>> {
         unimplemented!();
     }
 
+    /// Inject an interrupt or exception into the guest
+    ///
+    /// The event is delivered on the next VM entry of the given VCPU.
+    /// A typical use is to inject a page fault (vector 14) to have the guest
+    /// bring a paged-out page back into memory.
+    ///
+    /// # Arguments
+    /// * 'vcpu' - vcpu id on which the interrupt should be injected
+    /// * 'vector' - interrupt vector (e.g 14 for #PF)
+    /// * 'error_code' - optional error code pushed on the stack, for exceptions that have one
+    /// * 'cr2' - value of CR2 for a page fault, ignored otherwise
+    ///
+    /// Returns an `UnsupportedOperation` error if the driver cannot inject events.
+    ///
+    fn inject_interrupt(
+        &mut self,
+        _vcpu: u16,
+        _vector: u8,
+        _error_code: Option<u32>,
+        _cr2: u64,
+    ) -> Result<(), Box<dyn Error>> {
+        Err(Box::new(UnsupportedOperation {
+            driver_type: self.get_driver_type(),
+            operation: "interrupt injection",
+        }))
+    }
+
     /// Used to enable/disable an event interception
     ///
+    /// Intercepts are enabled per VCPU. When the hypervisor can only monitor the whole domain,
+    /// the driver filters out the events of the other VCPUs, except for breakpoints and pagefaults
+    /// which are delivered for every VCPU as soon as one of them enabled the intercept.
+    ///
     /// # Arguments
     /// * 'vcpu' - vcpu id for which we are to enable/disable intercept monitoring
     /// * 'intercept_type' - to specify event type for which to raise flag
@@ -161,6 +329,31 @@ pub trait Introspectable {
         unimplemented!();
     }
 
+    /// Return the intercepts currently enabled on a VCPU
+    ///
+    /// # Arguments
+    /// * 'vcpu' - vcpu id for which the active intercepts are returned
+    ///
+    /// Returns an `UnsupportedOperation` error if the driver does not track its intercepts.
+    ///
+    fn get_active_intercepts(&self, _vcpu: u16) -> Result<Vec<InterceptType>, Box<dyn Error>> {
+        Err(Box::new(UnsupportedOperation {
+            driver_type: self.get_driver_type(),
+            operation: "active intercepts",
+        }))
+    }
+
+    /// Revert the changes made to the guest through this driver
+    ///
+    /// Disables the active intercepts, puts back the page access the guest had before
+    /// it was modified, and resumes the VCPUs waiting for an event reply.
+    /// Drivers also restore the guest state when they are dropped, including on panic unwinding.
+    /// Does nothing for drivers which cannot modify the guest.
+    ///
+    fn restore_guest_state(&mut self) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+
     /// Listen and return the next event, or None
     ///
     /// # Arguments
@@ -184,6 +377,116 @@ pub trait Introspectable {
         unimplemented!();
     }
 
+    /// List the guest processes
+    ///
+    /// Requires a driver initialized with an OS layer.
+    /// Returns an `UnsupportedOperation` error if the driver has no OS layer.
+    ///
+    fn list_processes(&self) -> Result<Vec<ProcessInfo>, Box<dyn Error>> {
+        Err(Box::new(UnsupportedOperation {
+            driver_type: self.get_driver_type(),
+            operation: "process listing",
+        }))
+    }
+
+    /// List the modules loaded in a guest process
+    ///
+    /// Requires a driver initialized with an OS layer
+    ///
+    /// # Arguments
+    /// * 'pid' - the process ID
+    ///
+    /// Returns an `UnsupportedOperation` error if the driver has no OS layer.
+    ///
+    fn list_modules(&self, _pid: u32) -> Result<Vec<ModuleInfo>, Box<dyn Error>> {
+        Err(Box::new(UnsupportedOperation {
+            driver_type: self.get_driver_type(),
+            operation: "module listing",
+        }))
+    }
+
+    /// read the virtual memory of a guest process, starting from vaddr, into buf
+    ///
+    /// Requires a driver initialized with an OS layer
+    ///
+    /// # Arguments
+    /// * 'pid' - the process ID
+    /// * 'vaddr' - the virtual address to read from
+    /// * 'buf' - the data read from memory
+    /// * 'bytes_read' - the number of bytes read
+    ///
+    /// Returns an `UnsupportedOperation` error if the driver has no OS layer.
+    ///
+    fn read_virtual(
+        &self,
+        _pid: u32,
+        _vaddr: u64,
+        _buf: &mut [u8],
+        _bytes_read: &mut u64,
+    ) -> Result<(), Box<dyn Error>> {
+        Err(Box::new(UnsupportedOperation {
+            driver_type: self.get_driver_type(),
+            operation: "virtual memory read",
+        }))
+    }
+
     /// Return the concrete DriverType
     fn get_driver_type(&self) -> DriverType;
 }
+
+impl dyn Introspectable {
+    /// Pause the VM until the returned guard is dropped
+    ///
+    /// The guard gives access to the driver, and resumes the VM when it goes out of scope,
+    /// including on panic unwinding.
+    /// Guards can be nested, the VM is only resumed when the outermost one is dropped.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use microvmi::init;
+    ///
+    /// let mut drv = init(None, None).expect("Failed to init driver");
+    /// {
+    ///     let guard = drv.pause_guard().expect("Failed to pause VM");
+    ///     let regs = guard.read_registers(0).expect("Failed to read registers");
+    ///     println!("{:?}", regs);
+    /// }
+    /// // resumed
+    /// ```
+    pub fn pause_guard(&mut self) -> Result<PauseGuard<'_, dyn Introspectable>, Box<dyn Error>> {
+        PauseGuard::new(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::driver::fake::FakeDriver;
+
+    #[test]
+    fn test_set_page_access_range_defaults_to_each_page() {
+        let drv = FakeDriver::default();
+
+        drv.set_page_access_range(0x1234, 0x3001, Access::R)
+            .unwrap();
+
+        let page_access = drv.page_access.borrow();
+        assert_eq!(3, page_access.len());
+        assert_eq!(Access::R, page_access[&0x1000]);
+        assert_eq!(Access::R, page_access[&0x2000]);
+        assert_eq!(Access::R, page_access[&0x3000]);
+    }
+
+    #[test]
+    fn test_set_page_access_multi_defaults_to_each_page() {
+        let drv = FakeDriver::default();
+
+        drv.set_page_access_multi(&[(0x1000, Access::R), (0x5000, Access::RW)])
+            .unwrap();
+
+        let page_access = drv.page_access.borrow();
+        assert_eq!(2, page_access.len());
+        assert_eq!(Access::R, page_access[&0x1000]);
+        assert_eq!(Access::RW, page_access[&0x5000]);
+    }
+}
@@ -0,0 +1,28 @@
+//! This module describes the guest operating system objects
+//!
+//! They are only available from drivers initialized with an OS layer,
+//! such as the memflow driver given a `MemflowInitParams.os_name`.
+
+use serde::{Deserialize, Serialize};
+
+/// A guest process
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProcessInfo {
+    pub pid: u32,
+    pub name: String,
+    /// path of the executable
+    pub path: String,
+    pub command_line: String,
+    /// directory table base, the physical address of the process page tables
+    pub dtb: u64,
+}
+
+/// A module (executable or library) loaded in a guest process
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ModuleInfo {
+    pub name: String,
+    pub path: String,
+    /// virtual address where the module is loaded
+    pub base: u64,
+    pub size: u64,
+}
@@ -3,68 +3,137 @@
 //! The [`DriverInitParams`](struct.DriverInitParams.html) is used to pass additional driver initialization parameters.
 //! You might want to check it's documentation for examples on how to initialize your driver.
 
+use serde::{Deserialize, Serialize};
+
 /// Xen initialization parameters
-#[derive(Debug, Clone, PartialEq)]
-pub enum XenInitParams {}
+///
+/// Without them, the domain is found by looking up `CommonInitParams.vm_name` in xenstore.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum XenInitParams {
+    /// attach to the domain ID directly, without xenstore access
+    DomId(u32),
+    /// optional domain ID, otherwise `CommonInitParams.vm_name` is looked up in xenstore
+    Options {
+        domid: Option<u32>,
+        monitor_ring: XenMonitorRing,
+    },
+}
+
+/// Xen monitor ring mode
+#[derive(Debug, Copy, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub enum XenMonitorRing {
+    /// enable the vm_event monitor ring, required by the events and intercepts
+    #[default]
+    Enabled,
+    /// no monitor ring, for domains which are not allowed to monitor the guest:
+    /// only memory, registers, page access and pause are available
+    Disabled,
+}
+
+impl From<bool> for XenMonitorRing {
+    fn from(enabled: bool) -> Self {
+        if enabled {
+            XenMonitorRing::Enabled
+        } else {
+            XenMonitorRing::Disabled
+        }
+    }
+}
+
+/// vsock CID matching any address
+pub const VMADDR_CID_ANY: u32 = u32::MAX;
 
 /// KVM initialization parameters
-#[derive(Debug, Clone, PartialEq)]
+///
+/// The transport on which the introspector accepts the KVMi connection of QEMU
+///
+/// The kvmi library has no TCP nor file descriptor transport.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum KVMInitParams {
-    UnixSocket { path: String },
+    UnixSocket {
+        path: String,
+    },
+    /// listen on a vsock port, when the introspector runs in another VM
+    ///
+    /// only `VMADDR_CID_ANY` is supported as the local CID
+    Vsock {
+        cid: u32,
+        port: u32,
+    },
 }
 
 /// Memflow connector parameters
 ///
 /// This enumeration reflects the possibilities to initialize Memflow
 /// - default: will simply forward the string arguments to the connector
-// TODO
-// - [`qemu`](https://github.com/memflow/memflow-qemu)
-// - [`kvm`](https://github.com/memflow/memflow-kvm)
-// - [`pcileech`](https://github.com/memflow/memflow-pcileech)
-// - [`coredump`](https://github.com/memflow/memflow-coredump)
-#[derive(Debug, Clone, PartialEq)]
+/// - [`qemu`](https://github.com/memflow/memflow-qemu)
+/// - [`kvm`](https://github.com/memflow/memflow-kvm)
+/// - [`pcileech`](https://github.com/memflow/memflow-pcileech)
+/// - [`coredump`](https://github.com/memflow/memflow-coredump)
+///
+/// The typed variants are only accepted by their connector.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum MemflowConnectorParams {
     // allow to pass an abritrary list of Strings as parameters
-    Default { args: Vec<String> },
-    // TODO
-    // // optional vm_name, otherwise will search for the first QEMU process
-    // QEMUProcFs {
-    //     vm_name: Option<String>,
-    // },
-    // KVM {
-    //     pid: u32,
-    // },
-    // // default value for device: "FPGA"
-    // PCILeech {
-    //     device: Option<String>,
-    //     memmap: Option<String>,
-    // },
-    // Coredump {
-    //     filepath: String,
-    // },
+    Default {
+        args: Vec<String>,
+    },
+    /// optional vm_name, otherwise `CommonInitParams.vm_name`, otherwise the first QEMU process
+    QEMUProcFs {
+        vm_name: Option<String>,
+    },
+    /// pid of the QEMU process
+    KVM {
+        pid: u32,
+    },
+    /// default value for device: "FPGA"
+    PCILeech {
+        device: Option<String>,
+        memmap: Option<String>,
+    },
+    Coredump {
+        filepath: String,
+    },
 }
 
 /// Memflow initialization parameters
-#[derive(Debug, Default, Clone, PartialEq)]
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
 pub struct MemflowInitParams {
     /// connector name
     pub connector_name: String,
     /// optional connector initialization parameters
     pub connector_args: Option<MemflowConnectorParams>,
+    /// optional OS plugin name (e.g. `win32`), giving access to the guest processes
+    pub os_name: Option<String>,
+    /// optional OS plugin arguments, as `key=value` strings
+    pub os_args: Option<Vec<String>>,
 }
 
 /// VirtualBox initialization parameters
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum VBoxInitParams {}
 
+/// Remote initialization parameters
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RemoteInitParams {
+    /// address of the microvmi-server: `tcp://<host>:<port>` or `unix://<path>`
+    pub addr: String,
+    /// token shared with the server
+    pub token: String,
+}
+
 /// Common initialization parameters
 ///
 /// These parameters are shared by two or more drivers, and are stored in this struct
 /// to avoid duplication and simplify the API
 #[repr(C)]
-#[derive(Default, Debug, Clone, PartialEq)]
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct CommonInitParams {
     pub vm_name: String,
+    /// refuse every operation modifying the guest (memory, registers, page access, intercepts, event replies)
+    pub read_only: bool,
+    /// append every operation modifying the guest to this JSON lines audit log
+    pub audit_log: Option<String>,
 }
 
 /// This struct is used to specify the initialization parameters for all drivers
@@ -73,40 +142,70 @@ pub struct CommonInitParams {
 ///
 /// ```no_run
 /// // Xen
-/// // common.vm_name: mandatory
-/// use microvmi::api::params::{DriverInitParams, CommonInitParams, KVMInitParams, MemflowInitParams};
+/// // common.vm_name: mandatory, unless xen specifies a domain ID
+/// use microvmi::api::params::{DriverInitParams, CommonInitParams, KVMInitParams, MemflowInitParams, XenInitParams};
+/// let init_params = DriverInitParams {
+///     common: Some(CommonInitParams { vm_name: String::from("windows10"), ..Default::default() }),
+///     ..Default::default()
+/// };
+/// // xen: optional, attach to a domain ID
 /// let init_params = DriverInitParams {
-///     common: Some(CommonInitParams { vm_name: String::from("windows10")}),
+///     xen: Some(XenInitParams::DomId(5)),
 ///     ..Default::default()
 /// };
 /// // KVM
 /// // common.vm_name: mandatory
 /// // kvm.unix_socket: mandatory
 /// let init_params = DriverInitParams {
-///     common: Some(CommonInitParams { vm_name: String::from("windows10")}),
+///     common: Some(CommonInitParams { vm_name: String::from("windows10"), ..Default::default() }),
 ///     kvm: Some(KVMInitParams::UnixSocket { path: String::from("/tmp/introspector")}),
 ///     ..Default::default()
 /// };
 /// // VirtualBox
 /// // common.vm_name: mandatory
 /// let init_params = DriverInitParams {
-///     common: Some(CommonInitParams { vm_name: String::from("windows10")}),
+///     common: Some(CommonInitParams { vm_name: String::from("windows10"), ..Default::default() }),
+///     ..Default::default()
+/// };
+/// // read-only access, for forensics
+/// // common.read_only: optional
+/// let init_params = DriverInitParams {
+///     common: Some(CommonInitParams { vm_name: String::from("windows10"), read_only: true, ..Default::default() }),
+///     ..Default::default()
+/// };
+/// // audit log of the operations modifying the guest
+/// // common.audit_log: optional
+/// let init_params = DriverInitParams {
+///     common: Some(CommonInitParams {
+///         vm_name: String::from("windows10"),
+///         audit_log: Some(String::from("/var/log/microvmi-audit.jsonl")),
+///         ..Default::default()
+///     }),
 ///     ..Default::default()
 /// };
 /// // Memflow
 /// // memflow.connector_name: mandatory
 /// // memflow.connector_args: optional
 /// let init_params = DriverInitParams {
-///     memflow: Some(MemflowInitParams { connector_name: String::from("qemu"), ///
+///     memflow: Some(MemflowInitParams { connector_name: String::from("qemu"),
 ///         ..Default::default()}),
 ///     ..Default::default()
 /// };
+/// // Remote
+/// // remote.addr and remote.token: mandatory
+/// use microvmi::api::params::RemoteInitParams;
+/// let init_params = DriverInitParams {
+///     remote: Some(RemoteInitParams { addr: String::from("tcp://192.168.1.10:7475"),
+///         token: String::from("secret")}),
+///     ..Default::default()
+/// };
 /// ```
-#[derive(Default, Debug, Clone, PartialEq)]
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct DriverInitParams {
     pub common: Option<CommonInitParams>,
     pub xen: Option<XenInitParams>,
     pub kvm: Option<KVMInitParams>,
     pub memflow: Option<MemflowInitParams>,
     pub virtualbox: Option<VBoxInitParams>,
+    pub remote: Option<RemoteInitParams>,
 }
@@ -0,0 +1,81 @@
+use std::error::Error;
+use std::ops::{Deref, DerefMut};
+
+use crate::api::Introspectable;
+
+/// Keeps the VM paused while it is alive
+///
+/// Created by [PauseGuard::new](struct.PauseGuard.html#method.new) or `pause_guard()`,
+/// the guard dereferences to the driver, and resumes the VM on drop.
+/// Since drivers count pause requests, nested guards don't resume the VM under each other.
+#[derive(Debug)]
+pub struct PauseGuard<'a, I: Introspectable + ?Sized> {
+    drv: &'a mut I,
+}
+
+impl<'a, I: Introspectable + ?Sized> PauseGuard<'a, I> {
+    /// Pause the VM and return a guard which resumes it on drop
+    ///
+    /// # Arguments
+    /// * 'drv' - the driver used to pause and resume the VM
+    ///
+    pub fn new(drv: &'a mut I) -> Result<Self, Box<dyn Error>> {
+        drv.pause()?;
+        Ok(PauseGuard { drv })
+    }
+}
+
+impl<I: Introspectable + ?Sized> Deref for PauseGuard<'_, I> {
+    type Target = I;
+
+    fn deref(&self) -> &I {
+        self.drv
+    }
+}
+
+impl<I: Introspectable + ?Sized> DerefMut for PauseGuard<'_, I> {
+    fn deref_mut(&mut self) -> &mut I {
+        self.drv
+    }
+}
+
+impl<I: Introspectable + ?Sized> Drop for PauseGuard<'_, I> {
+    fn drop(&mut self) {
+        if let Err(e) = self.drv.resume() {
+            error!("Failed to resume VM: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::driver::fake::FakeDriver;
+
+    #[test]
+    fn test_guard_resumes_on_drop() {
+        let mut drv = FakeDriver::default();
+        {
+            let guard = PauseGuard::new(&mut drv).unwrap();
+            assert_eq!(guard.pause_count.get(), 1);
+        }
+        assert_eq!(drv.pause_count.get(), 0);
+        assert_eq!(drv.resumes.get(), 1);
+    }
+
+    #[test]
+    fn test_nested_guards_on_trait_object() {
+        let fake = FakeDriver::default();
+        let pause_count = fake.pause_count.clone();
+        let mut drv: Box<dyn Introspectable> = Box::new(fake);
+        {
+            let mut guard = drv.pause_guard().unwrap();
+            {
+                let _inner = guard.pause_guard().unwrap();
+                assert_eq!(pause_count.get(), 2);
+            }
+            assert_eq!(pause_count.get(), 1);
+        }
+        assert_eq!(pause_count.get(), 0);
+    }
+}
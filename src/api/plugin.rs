@@ -0,0 +1,537 @@
+//! This module describes the C ABI of the driver plugins
+//!
+//! A driver plugin is a shared object, loaded at runtime from the plugin directory
+//! (`MICROVMI_PLUGIN_DIR`, or [DEFAULT_PLUGIN_DIR](constant.DEFAULT_PLUGIN_DIR.html)),
+//! which provides a driver that has not been compiled into libmicrovmi.
+//!
+//! The shared object exports a `microvmi_plugin_descriptor` function returning a [PluginDescriptor](struct.PluginDescriptor.html).
+//! Each entry of the [PluginVtable](struct.PluginVtable.html) mirrors a method of [Introspectable](../trait.Introspectable.html):
+//! it returns NULL on success, or an error message to be released with `free_error`.
+//! Entries left to NULL are reported as unsupported operations.
+//! The optional `list_vms` entry of the descriptor reports the VMs seen by the plugin, for [list_vms](../../fn.list_vms.html).
+//!
+//! A Rust plugin implements the [Plugin](trait.Plugin.html) trait, and exports it with [export_plugin](../../macro.export_plugin.html):
+//!
+//! ```ignore
+//! use std::error::Error;
+//! use microvmi::api::params::DriverInitParams;
+//! use microvmi::api::plugin::Plugin;
+//! use microvmi::api::vms::VmInfo;
+//! use microvmi::api::DriverType;
+//!
+//! struct XenPlugin;
+//!
+//! impl Plugin for XenPlugin {
+//!     type Driver = MyXenDriver;
+//!     const DRIVER_TYPE: DriverType = DriverType::Xen;
+//!
+//!     fn probe() -> bool {
+//!         std::path::Path::new("/proc/xen").exists()
+//!     }
+//!
+//!     fn init(init_params: DriverInitParams) -> Result<MyXenDriver, Box<dyn Error>> {
+//!         MyXenDriver::new(init_params)
+//!     }
+//!
+//!     fn list_vms() -> Result<Vec<VmInfo>, Box<dyn Error>> {
+//!         my_xen_list_domains()
+//!     }
+//! }
+//!
+//! microvmi::export_plugin!(XenPlugin);
+//! ```
+
+use std::error::Error;
+use std::ffi::{c_void, CStr, CString};
+use std::os::raw::c_char;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::ptr::null_mut;
+use std::slice;
+
+use crate::api::events::{Event, EventReplyType, InterceptType};
+use crate::api::params::DriverInitParams;
+use crate::api::registers::Registers;
+use crate::api::vms::VmInfo;
+use crate::api::{Access, DriverType, Introspectable};
+use crate::errors::UnsupportedOperation;
+
+/// Version of the plugin ABI, bumped on every incompatible change of the descriptor
+/// or of the types passed through it
+pub const PLUGIN_ABI_VERSION: u32 = 2;
+/// Name of the function exported by the plugins
+pub const PLUGIN_ENTRYPOINT: &str = "microvmi_plugin_descriptor";
+/// Plugin directory used when `MICROVMI_PLUGIN_DIR` is not set
+pub const DEFAULT_PLUGIN_DIR: &str = "/usr/lib/microvmi/plugins";
+
+/// Error message returned by a plugin function, NULL on success
+pub type PluginResult = *mut c_char;
+
+/// Type of the `microvmi_plugin_descriptor` function
+pub type PluginEntrypoint = unsafe extern "C" fn() -> PluginDescriptor;
+
+/// Description of a driver plugin
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct PluginDescriptor {
+    /// must be PLUGIN_ABI_VERSION
+    pub abi_version: u32,
+    /// the driver provided by the plugin
+    pub driver_type: DriverType,
+    /// whether the hypervisor interface is reachable
+    pub probe: unsafe extern "C" fn() -> bool,
+    /// initialize a driver from its JSON-encoded `DriverInitParams`, and store it into driver
+    pub init:
+        unsafe extern "C" fn(init_params: *const c_char, driver: *mut *mut c_void) -> PluginResult,
+    /// release a driver returned by init
+    pub destroy: unsafe extern "C" fn(driver: *mut c_void),
+    /// release an error message, or a VM list, returned by the plugin
+    pub free_error: unsafe extern "C" fn(error: *mut c_char),
+    /// store the JSON-encoded list of `VmInfo` seen by the plugin into vms, NULL if unsupported
+    pub list_vms: Option<unsafe extern "C" fn(vms: *mut *mut c_char) -> PluginResult>,
+    pub vtable: PluginVtable,
+}
+
+/// Driver operations, mirroring the [Introspectable](../trait.Introspectable.html) methods
+///
+/// Access flags are passed as their bits.
+#[repr(C)]
+#[derive(Copy, Clone, Default)]
+pub struct PluginVtable {
+    pub get_vcpu_count: Option<unsafe extern "C" fn(*mut c_void, *mut u16) -> PluginResult>,
+    pub read_physical:
+        Option<unsafe extern "C" fn(*mut c_void, u64, *mut u8, usize, *mut u64) -> PluginResult>,
+    pub write_physical:
+        Option<unsafe extern "C" fn(*mut c_void, u64, *const u8, usize) -> PluginResult>,
+    pub get_max_physical_addr: Option<unsafe extern "C" fn(*mut c_void, *mut u64) -> PluginResult>,
+    pub read_registers:
+        Option<unsafe extern "C" fn(*mut c_void, u16, *mut Registers) -> PluginResult>,
+    pub write_registers: Option<unsafe extern "C" fn(*mut c_void, u16, Registers) -> PluginResult>,
+    pub get_page_access: Option<unsafe extern "C" fn(*mut c_void, u64, *mut u32) -> PluginResult>,
+    pub set_page_access: Option<unsafe extern "C" fn(*mut c_void, u64, u32) -> PluginResult>,
+    pub set_page_access_range:
+        Option<unsafe extern "C" fn(*mut c_void, u64, u64, u32) -> PluginResult>,
+    /// addresses and access flags of the pages, both of the given length
+    pub set_page_access_multi:
+        Option<unsafe extern "C" fn(*mut c_void, *const u64, *const u32, usize) -> PluginResult>,
+    pub create_view: Option<unsafe extern "C" fn(*mut c_void, *mut u16) -> PluginResult>,
+    pub destroy_view: Option<unsafe extern "C" fn(*mut c_void, u16) -> PluginResult>,
+    pub switch_view: Option<unsafe extern "C" fn(*mut c_void, u16, u16) -> PluginResult>,
+    pub set_page_access_in_view:
+        Option<unsafe extern "C" fn(*mut c_void, u16, u64, u32) -> PluginResult>,
+    pub remap_gfn: Option<unsafe extern "C" fn(*mut c_void, u16, u64, u64) -> PluginResult>,
+    pub pause: Option<unsafe extern "C" fn(*mut c_void) -> PluginResult>,
+    pub resume: Option<unsafe extern "C" fn(*mut c_void) -> PluginResult>,
+    /// vcpu, vector, has_error_code, error_code, cr2
+    pub inject_interrupt:
+        Option<unsafe extern "C" fn(*mut c_void, u16, u8, bool, u32, u64) -> PluginResult>,
+    pub toggle_intercept:
+        Option<unsafe extern "C" fn(*mut c_void, u16, InterceptType, bool) -> PluginResult>,
+    /// vcpu, buffer, buffer capacity, and the total number of active intercepts,
+    /// which may exceed the capacity
+    pub get_active_intercepts: Option<
+        unsafe extern "C" fn(
+            *mut c_void,
+            u16,
+            *mut InterceptType,
+            usize,
+            *mut usize,
+        ) -> PluginResult,
+    >,
+    pub restore_guest_state: Option<unsafe extern "C" fn(*mut c_void) -> PluginResult>,
+    /// timeout, event, and whether an event has been stored
+    pub listen:
+        Option<unsafe extern "C" fn(*mut c_void, u32, *mut Event, *mut bool) -> PluginResult>,
+    pub reply_event:
+        Option<unsafe extern "C" fn(*mut c_void, Event, EventReplyType) -> PluginResult>,
+    pub read_virtual: Option<
+        unsafe extern "C" fn(*mut c_void, u32, u64, *mut u8, usize, *mut u64) -> PluginResult,
+    >,
+}
+
+/// A driver shipped as a plugin
+///
+/// The guest OS layer (list_processes, list_modules) is not part of the plugin ABI.
+pub trait Plugin {
+    type Driver: Introspectable + 'static;
+    /// the driver provided by the plugin
+    const DRIVER_TYPE: DriverType;
+
+    /// Whether the hypervisor interface is reachable
+    fn probe() -> bool;
+
+    /// Initialize the driver
+    fn init(init_params: DriverInitParams) -> Result<Self::Driver, Box<dyn Error>>;
+
+    /// List the VMs the driver can see
+    fn list_vms() -> Result<Vec<VmInfo>, Box<dyn Error>> {
+        Err(Box::new(UnsupportedOperation {
+            driver_type: Self::DRIVER_TYPE,
+            operation: "VM listing",
+        }))
+    }
+}
+
+/// Export a [Plugin](api/plugin/trait.Plugin.html) implementation as the plugin entrypoint
+///
+/// The crate must be built as a `cdylib`.
+#[macro_export]
+macro_rules! export_plugin {
+    ($plugin:ty) => {
+        #[no_mangle]
+        pub extern "C" fn microvmi_plugin_descriptor() -> $crate::api::plugin::PluginDescriptor {
+            $crate::api::plugin::descriptor::<$plugin>()
+        }
+    };
+}
+
+/// Build the descriptor of a plugin
+pub fn descriptor<P: Plugin>() -> PluginDescriptor {
+    PluginDescriptor {
+        abi_version: PLUGIN_ABI_VERSION,
+        driver_type: P::DRIVER_TYPE,
+        probe: probe::<P>,
+        init: init::<P>,
+        destroy: destroy::<P::Driver>,
+        free_error,
+        list_vms: Some(list_vms::<P>),
+        vtable: vtable::<P::Driver>(),
+    }
+}
+
+fn vtable<D: Introspectable>() -> PluginVtable {
+    PluginVtable {
+        get_vcpu_count: Some(get_vcpu_count::<D>),
+        read_physical: Some(read_physical::<D>),
+        write_physical: Some(write_physical::<D>),
+        get_max_physical_addr: Some(get_max_physical_addr::<D>),
+        read_registers: Some(read_registers::<D>),
+        write_registers: Some(write_registers::<D>),
+        get_page_access: Some(get_page_access::<D>),
+        set_page_access: Some(set_page_access::<D>),
+        set_page_access_range: Some(set_page_access_range::<D>),
+        set_page_access_multi: Some(set_page_access_multi::<D>),
+        create_view: Some(create_view::<D>),
+        destroy_view: Some(destroy_view::<D>),
+        switch_view: Some(switch_view::<D>),
+        set_page_access_in_view: Some(set_page_access_in_view::<D>),
+        remap_gfn: Some(remap_gfn::<D>),
+        pause: Some(pause::<D>),
+        resume: Some(resume::<D>),
+        inject_interrupt: Some(inject_interrupt::<D>),
+        toggle_intercept: Some(toggle_intercept::<D>),
+        get_active_intercepts: Some(get_active_intercepts::<D>),
+        restore_guest_state: Some(restore_guest_state::<D>),
+        listen: Some(listen::<D>),
+        reply_event: Some(reply_event::<D>),
+        read_virtual: Some(read_virtual::<D>),
+    }
+}
+
+/// Run a driver operation, turning its error, or its panic, into an error message
+///
+/// Panics must not unwind into the host, and the unimplemented operations panic.
+fn call<F: FnOnce() -> Result<(), Box<dyn Error>>>(f: F) -> PluginResult {
+    let message = match catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(())) => return null_mut(),
+        Ok(Err(e)) => e.to_string(),
+        Err(panic) => match panic.downcast_ref::<&str>() {
+            Some(s) => format!("plugin panicked: {}", s),
+            None => match panic.downcast_ref::<String>() {
+                Some(s) => format!("plugin panicked: {}", s),
+                None => String::from("plugin panicked"),
+            },
+        },
+    };
+    CString::new(message.replace('\0', ""))
+        .expect("nul bytes have been removed")
+        .into_raw()
+}
+
+unsafe fn driver<'a, D>(ctx: *mut c_void) -> &'a mut D {
+    &mut *(ctx as *mut D)
+}
+
+unsafe extern "C" fn probe<P: Plugin>() -> bool {
+    catch_unwind(P::probe).unwrap_or(false)
+}
+
+unsafe extern "C" fn init<P: Plugin>(
+    init_params: *const c_char,
+    driver: *mut *mut c_void,
+) -> PluginResult {
+    call(|| {
+        let init_params: DriverInitParams =
+            serde_json::from_str(CStr::from_ptr(init_params).to_str()?)?;
+        let drv = P::init(init_params)?;
+        driver.write(Box::into_raw(Box::new(drv)) as *mut c_void);
+        Ok(())
+    })
+}
+
+unsafe extern "C" fn list_vms<P: Plugin>(vms: *mut *mut c_char) -> PluginResult {
+    call(|| {
+        let json = CString::new(serde_json::to_string(&P::list_vms()?)?)?;
+        vms.write(json.into_raw());
+        Ok(())
+    })
+}
+
+unsafe extern "C" fn destroy<D>(ctx: *mut c_void) {
+    if !ctx.is_null() {
+        let drv = Box::from_raw(ctx as *mut D);
+        // the driver restores the guest state when dropped
+        catch_unwind(AssertUnwindSafe(move || drop(drv))).ok();
+    }
+}
+
+unsafe extern "C" fn free_error(error: *mut c_char) {
+    if !error.is_null() {
+        drop(CString::from_raw(error));
+    }
+}
+
+unsafe extern "C" fn get_vcpu_count<D: Introspectable>(
+    ctx: *mut c_void,
+    count: *mut u16,
+) -> PluginResult {
+    call(|| {
+        count.write(driver::<D>(ctx).get_vcpu_count()?);
+        Ok(())
+    })
+}
+
+unsafe extern "C" fn read_physical<D: Introspectable>(
+    ctx: *mut c_void,
+    paddr: u64,
+    buf: *mut u8,
+    size: usize,
+    bytes_read: *mut u64,
+) -> PluginResult {
+    call(|| {
+        driver::<D>(ctx).read_physical(
+            paddr,
+            slice::from_raw_parts_mut(buf, size),
+            &mut *bytes_read,
+        )
+    })
+}
+
+unsafe extern "C" fn write_physical<D: Introspectable>(
+    ctx: *mut c_void,
+    paddr: u64,
+    buf: *const u8,
+    size: usize,
+) -> PluginResult {
+    call(|| driver::<D>(ctx).write_physical(paddr, slice::from_raw_parts(buf, size)))
+}
+
+unsafe extern "C" fn get_max_physical_addr<D: Introspectable>(
+    ctx: *mut c_void,
+    addr: *mut u64,
+) -> PluginResult {
+    call(|| {
+        addr.write(driver::<D>(ctx).get_max_physical_addr()?);
+        Ok(())
+    })
+}
+
+unsafe extern "C" fn read_registers<D: Introspectable>(
+    ctx: *mut c_void,
+    vcpu: u16,
+    registers: *mut Registers,
+) -> PluginResult {
+    call(|| {
+        registers.write(driver::<D>(ctx).read_registers(vcpu)?);
+        Ok(())
+    })
+}
+
+unsafe extern "C" fn write_registers<D: Introspectable>(
+    ctx: *mut c_void,
+    vcpu: u16,
+    registers: Registers,
+) -> PluginResult {
+    call(|| driver::<D>(ctx).write_registers(vcpu, registers))
+}
+
+unsafe extern "C" fn get_page_access<D: Introspectable>(
+    ctx: *mut c_void,
+    paddr: u64,
+    access: *mut u32,
+) -> PluginResult {
+    call(|| {
+        access.write(driver::<D>(ctx).get_page_access(paddr)?.bits());
+        Ok(())
+    })
+}
+
+unsafe extern "C" fn set_page_access<D: Introspectable>(
+    ctx: *mut c_void,
+    paddr: u64,
+    access: u32,
+) -> PluginResult {
+    call(|| driver::<D>(ctx).set_page_access(paddr, Access::from_bits_truncate(access)))
+}
+
+unsafe extern "C" fn set_page_access_range<D: Introspectable>(
+    ctx: *mut c_void,
+    start: u64,
+    end: u64,
+    access: u32,
+) -> PluginResult {
+    call(|| driver::<D>(ctx).set_page_access_range(start, end, Access::from_bits_truncate(access)))
+}
+
+unsafe extern "C" fn set_page_access_multi<D: Introspectable>(
+    ctx: *mut c_void,
+    paddrs: *const u64,
+    access: *const u32,
+    len: usize,
+) -> PluginResult {
+    call(|| {
+        let pages: Vec<(u64, Access)> = slice::from_raw_parts(paddrs, len)
+            .iter()
+            .zip(slice::from_raw_parts(access, len))
+            .map(|(paddr, access)| (*paddr, Access::from_bits_truncate(*access)))
+            .collect();
+        driver::<D>(ctx).set_page_access_multi(&pages)
+    })
+}
+
+unsafe extern "C" fn create_view<D: Introspectable>(
+    ctx: *mut c_void,
+    view: *mut u16,
+) -> PluginResult {
+    call(|| {
+        view.write(driver::<D>(ctx).create_view()?);
+        Ok(())
+    })
+}
+
+unsafe extern "C" fn destroy_view<D: Introspectable>(ctx: *mut c_void, view: u16) -> PluginResult {
+    call(|| driver::<D>(ctx).destroy_view(view))
+}
+
+unsafe extern "C" fn switch_view<D: Introspectable>(
+    ctx: *mut c_void,
+    vcpu: u16,
+    view: u16,
+) -> PluginResult {
+    call(|| driver::<D>(ctx).switch_view(vcpu, view))
+}
+
+unsafe extern "C" fn set_page_access_in_view<D: Introspectable>(
+    ctx: *mut c_void,
+    view: u16,
+    paddr: u64,
+    access: u32,
+) -> PluginResult {
+    call(|| {
+        driver::<D>(ctx).set_page_access_in_view(view, paddr, Access::from_bits_truncate(access))
+    })
+}
+
+unsafe extern "C" fn remap_gfn<D: Introspectable>(
+    ctx: *mut c_void,
+    view: u16,
+    from: u64,
+    to: u64,
+) -> PluginResult {
+    call(|| driver::<D>(ctx).remap_gfn(view, from, to))
+}
+
+unsafe extern "C" fn pause<D: Introspectable>(ctx: *mut c_void) -> PluginResult {
+    call(|| driver::<D>(ctx).pause())
+}
+
+unsafe extern "C" fn resume<D: Introspectable>(ctx: *mut c_void) -> PluginResult {
+    call(|| driver::<D>(ctx).resume())
+}
+
+unsafe extern "C" fn inject_interrupt<D: Introspectable>(
+    ctx: *mut c_void,
+    vcpu: u16,
+    vector: u8,
+    has_error_code: bool,
+    error_code: u32,
+    cr2: u64,
+) -> PluginResult {
+    let error_code = if has_error_code {
+        Some(error_code)
+    } else {
+        None
+    };
+    call(|| driver::<D>(ctx).inject_interrupt(vcpu, vector, error_code, cr2))
+}
+
+unsafe extern "C" fn toggle_intercept<D: Introspectable>(
+    ctx: *mut c_void,
+    vcpu: u16,
+    intercept_type: InterceptType,
+    enabled: bool,
+) -> PluginResult {
+    call(|| driver::<D>(ctx).toggle_intercept(vcpu, intercept_type, enabled))
+}
+
+unsafe extern "C" fn get_active_intercepts<D: Introspectable>(
+    ctx: *mut c_void,
+    vcpu: u16,
+    buf: *mut InterceptType,
+    capacity: usize,
+    len: *mut usize,
+) -> PluginResult {
+    call(|| {
+        let intercepts = driver::<D>(ctx).get_active_intercepts(vcpu)?;
+        for (i, intercept) in intercepts.iter().take(capacity).enumerate() {
+            buf.add(i).write(*intercept);
+        }
+        len.write(intercepts.len());
+        Ok(())
+    })
+}
+
+unsafe extern "C" fn restore_guest_state<D: Introspectable>(ctx: *mut c_void) -> PluginResult {
+    call(|| driver::<D>(ctx).restore_guest_state())
+}
+
+unsafe extern "C" fn listen<D: Introspectable>(
+    ctx: *mut c_void,
+    timeout: u32,
+    event: *mut Event,
+    has_event: *mut bool,
+) -> PluginResult {
+    call(|| {
+        let next = driver::<D>(ctx).listen(timeout)?;
+        has_event.write(next.is_some());
+        if let Some(ev) = next {
+            event.write(ev);
+        }
+        Ok(())
+    })
+}
+
+unsafe extern "C" fn reply_event<D: Introspectable>(
+    ctx: *mut c_void,
+    event: Event,
+    reply_type: EventReplyType,
+) -> PluginResult {
+    call(|| driver::<D>(ctx).reply_event(event, reply_type))
+}
+
+unsafe extern "C" fn read_virtual<D: Introspectable>(
+    ctx: *mut c_void,
+    pid: u32,
+    vaddr: u64,
+    buf: *mut u8,
+    size: usize,
+    bytes_read: *mut u64,
+) -> PluginResult {
+    call(|| {
+        driver::<D>(ctx).read_virtual(
+            pid,
+            vaddr,
+            slice::from_raw_parts_mut(buf, size),
+            &mut *bytes_read,
+        )
+    })
+}
@@ -0,0 +1,432 @@
+//! This module describes the protocol spoken by `microvmi-server`
+//!
+//! The server wraps a locally initialized driver, and serves the [Introspectable](../trait.Introspectable.html)
+//! methods over a TCP or UNIX stream socket.
+//!
+//! # Framing
+//!
+//! Every message is a frame:
+//!
+//! | field         | size              | content                                        |
+//! |---------------|-------------------|------------------------------------------------|
+//! | header length | 4 bytes           | unsigned, little endian                        |
+//! | header        | header length     | a [Request](enum.Request.html) or a [Response](enum.Response.html), encoded in JSON |
+//! | data length   | 4 bytes           | unsigned, little endian                        |
+//! | data          | data length       | raw bytes: the memory written or read, empty otherwise |
+//!
+//! # Session
+//!
+//! 1. the client sends `Request::Hello`, with the protocol version and the shared token, and no data
+//! 2. the server answers `Response::Hello` with the driver type, or `Response::Error` and closes the connection
+//! 3. the client sends requests, each answered by exactly one response, in order
+//!
+//! A failed operation is answered by `Response::Error`, the session goes on.
+//!
+//! The client does not have to wait for a response before sending the next request:
+//! the requests are pipelined, and answered in order.
+//!
+//! # Events
+//!
+//! Instead of polling with `Request::Listen`, the client can send `Request::Subscribe`.
+//! The server then pushes each event as a `Response::PushedEvent` frame, between the responses.
+//! If the driver fails to listen, the server pushes a `Response::PushError` and ends the subscription.
+
+use std::error::Error;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::TcpStream;
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::time::Duration;
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::api::events::{Event, EventReplyType, InterceptType};
+use crate::api::os::{ModuleInfo, ProcessInfo};
+use crate::api::registers::Registers;
+use crate::api::{Access, DriverType};
+
+/// Version of the protocol, checked by `Request::Hello`
+pub const PROTOCOL_VERSION: u32 = 1;
+/// Largest header accepted
+pub const MAX_HEADER_SIZE: u32 = 1 << 20;
+/// Largest `Request::Hello` header accepted, before the client is authenticated
+pub const MAX_HELLO_SIZE: u32 = 4096;
+/// Largest data accepted, also the largest memory read or write in a single request
+pub const MAX_DATA_SIZE: u32 = 64 << 20;
+
+#[derive(thiserror::Error, Debug)]
+pub enum ProtocolError {
+    #[error("connection error: {0}")]
+    Io(#[from] io::Error),
+    #[error("invalid message: {0}")]
+    InvalidMessage(#[from] serde_json::Error),
+    #[error("frame {0} size {1} exceeds the limit of {2} bytes")]
+    FrameTooLarge(&'static str, usize, u32),
+}
+
+/// Client messages, one per [Introspectable](../trait.Introspectable.html) method
+///
+/// `WritePhysical` carries the memory to be written in the frame data.
+#[derive(Debug, Serialize, Deserialize)]
+pub enum Request {
+    Hello {
+        version: u32,
+        token: String,
+    },
+    GetVcpuCount,
+    ReadPhysical {
+        paddr: u64,
+        size: u32,
+    },
+    WritePhysical {
+        paddr: u64,
+    },
+    GetMaxPhysicalAddr,
+    ReadRegisters {
+        vcpu: u16,
+    },
+    WriteRegisters {
+        vcpu: u16,
+        registers: Registers,
+    },
+    GetPageAccess {
+        paddr: u64,
+    },
+    SetPageAccess {
+        paddr: u64,
+        access: Access,
+    },
+    SetPageAccessRange {
+        start: u64,
+        end: u64,
+        access: Access,
+    },
+    SetPageAccessMulti {
+        pages: Vec<(u64, Access)>,
+    },
+    CreateView,
+    DestroyView {
+        view: u16,
+    },
+    SwitchView {
+        vcpu: u16,
+        view: u16,
+    },
+    SetPageAccessInView {
+        view: u16,
+        paddr: u64,
+        access: Access,
+    },
+    RemapGfn {
+        view: u16,
+        from: u64,
+        to: u64,
+    },
+    Pause,
+    Resume,
+    InjectInterrupt {
+        vcpu: u16,
+        vector: u8,
+        error_code: Option<u32>,
+        cr2: u64,
+    },
+    ToggleIntercept {
+        vcpu: u16,
+        intercept_type: InterceptType,
+        enabled: bool,
+    },
+    GetActiveIntercepts {
+        vcpu: u16,
+    },
+    RestoreGuestState,
+    Listen {
+        timeout: u32,
+    },
+    ReplyEvent {
+        event: Event,
+        reply_type: EventReplyType,
+    },
+    ListProcesses,
+    ListModules {
+        pid: u32,
+    },
+    ReadVirtual {
+        pid: u32,
+        vaddr: u64,
+        size: u32,
+    },
+    /// start or stop pushing the events
+    Subscribe {
+        enabled: bool,
+    },
+}
+
+/// Server messages
+///
+/// `Read` carries the memory read in the frame data.
+#[derive(Debug, Serialize, Deserialize)]
+#[allow(clippy::large_enum_variant)]
+pub enum Response {
+    Hello {
+        driver_type: DriverType,
+    },
+    /// the operation succeeded, without returning a value
+    Done,
+    VcpuCount(u16),
+    Read {
+        bytes_read: u64,
+    },
+    MaxPhysicalAddr(u64),
+    Registers(Registers),
+    PageAccess(Access),
+    View(u16),
+    Intercepts(Vec<InterceptType>),
+    Event(Option<Event>),
+    Processes(Vec<ProcessInfo>),
+    Modules(Vec<ModuleInfo>),
+    /// the operation failed, with the error message
+    Error(String),
+    /// an event pushed to a subscribed client, not a response
+    PushedEvent(Event),
+    /// the subscription ended because the driver failed to listen, not a response
+    PushError(String),
+}
+
+impl Response {
+    /// Turn an operation result into a response
+    pub fn from_result<T, F: FnOnce(T) -> Response>(
+        result: Result<T, Box<dyn Error>>,
+        f: F,
+    ) -> Response {
+        match result {
+            Ok(value) => f(value),
+            Err(e) => Response::Error(e.to_string()),
+        }
+    }
+}
+
+/// A stream socket carrying the protocol
+pub trait Stream: Read + Write {
+    /// Set the read timeout, see [try_read_frame](fn.try_read_frame.html)
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+}
+
+impl Stream for TcpStream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+}
+
+#[cfg(unix)]
+impl Stream for UnixStream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        UnixStream::set_read_timeout(self, timeout)
+    }
+}
+
+/// Write a frame
+pub fn write_frame<W: Write, T: Serialize>(
+    writer: &mut W,
+    header: &T,
+    data: &[u8],
+) -> Result<(), ProtocolError> {
+    let header = serde_json::to_vec(header)?;
+    let header_len = frame_len("header", header.len(), MAX_HEADER_SIZE)?;
+    let data_len = frame_len("data", data.len(), MAX_DATA_SIZE)?;
+    // a single write, to avoid small TCP segments
+    let mut frame = Vec::with_capacity(8 + header.len() + data.len());
+    frame.extend_from_slice(&header_len.to_le_bytes());
+    frame.extend_from_slice(&header);
+    frame.extend_from_slice(&data_len.to_le_bytes());
+    frame.extend_from_slice(data);
+    writer.write_all(&frame)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Read a frame, returning its header and data
+///
+/// Waits for the frame, even if the stream has a read timeout.
+pub fn read_frame<R: Read, T: DeserializeOwned>(
+    reader: &mut R,
+) -> Result<(T, Vec<u8>), ProtocolError> {
+    let mut len = [0u8; 4];
+    read_fully(reader, &mut len)?;
+    read_frame_content(reader, len)
+}
+
+/// Read the `Request::Hello` frame of a client
+///
+/// Unlike read_frame, the read timeout of the stream is not ignored,
+/// and a frame carrying data is refused before its data is read.
+pub fn read_hello_frame<R: Read>(reader: &mut R) -> Result<Request, ProtocolError> {
+    let mut len = [0u8; 4];
+    reader.read_exact(&mut len)?;
+    let header_len = frame_len("header", u32::from_le_bytes(len) as usize, MAX_HELLO_SIZE)?;
+    let mut header = vec![0u8; header_len as usize];
+    reader.read_exact(&mut header)?;
+    reader.read_exact(&mut len)?;
+    frame_len("data", u32::from_le_bytes(len) as usize, 0)?;
+    Ok(serde_json::from_slice(&header)?)
+}
+
+/// Read a frame if one starts before the read timeout of the stream, or return None
+///
+/// Once the frame has started, the rest of it is waited for.
+pub fn try_read_frame<R: Read, T: DeserializeOwned>(
+    reader: &mut R,
+) -> Result<Option<(T, Vec<u8>)>, ProtocolError> {
+    let mut len = [0u8; 4];
+    loop {
+        match reader.read(&mut len[..1]) {
+            Ok(0) => return Err(io::Error::from(ErrorKind::UnexpectedEof).into()),
+            Ok(_) => break,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) if is_timeout(&e) => return Ok(None),
+            Err(e) => return Err(e.into()),
+        }
+    }
+    read_fully(reader, &mut len[1..])?;
+    Ok(Some(read_frame_content(reader, len)?))
+}
+
+fn read_frame_content<R: Read, T: DeserializeOwned>(
+    reader: &mut R,
+    header_len: [u8; 4],
+) -> Result<(T, Vec<u8>), ProtocolError> {
+    let header = read_part(reader, "header", header_len, MAX_HEADER_SIZE)?;
+    let mut data_len = [0u8; 4];
+    read_fully(reader, &mut data_len)?;
+    let data = read_part(reader, "data", data_len, MAX_DATA_SIZE)?;
+    Ok((serde_json::from_slice(&header)?, data))
+}
+
+fn is_timeout(e: &io::Error) -> bool {
+    matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut)
+}
+
+/// read_exact, ignoring the read timeouts
+fn read_fully<R: Read>(reader: &mut R, mut buf: &mut [u8]) -> io::Result<()> {
+    while !buf.is_empty() {
+        match reader.read(buf) {
+            Ok(0) => return Err(io::Error::from(ErrorKind::UnexpectedEof)),
+            Ok(n) => buf = &mut buf[n..],
+            Err(e) if e.kind() == ErrorKind::Interrupted || is_timeout(&e) => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+fn frame_len(part: &'static str, len: usize, max: u32) -> Result<u32, ProtocolError> {
+    if len > max as usize {
+        return Err(ProtocolError::FrameTooLarge(part, len, max));
+    }
+    Ok(len as u32)
+}
+
+fn read_part<R: Read>(
+    reader: &mut R,
+    part: &'static str,
+    len: [u8; 4],
+    max: u32,
+) -> Result<Vec<u8>, ProtocolError> {
+    let len = frame_len(part, u32::from_le_bytes(len) as usize, max)?;
+    let mut buf = vec![0u8; len as usize];
+    read_fully(reader, &mut buf)?;
+    Ok(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::events::EventType;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_frame_roundtrip() {
+        let mut stream = Vec::new();
+        write_frame(
+            &mut stream,
+            &Request::WritePhysical { paddr: 0x1000 },
+            b"data",
+        )
+        .unwrap();
+        write_frame(
+            &mut stream,
+            &Response::Event(Some(Event {
+                vcpu: 1,
+                kind: EventType::Pagefault {
+                    gva: 0x7ff0,
+                    gpa: 0x1000,
+                    access: Access::RW,
+                    view: 0,
+                },
+            })),
+            &[],
+        )
+        .unwrap();
+
+        let mut reader = Cursor::new(stream);
+        let (request, data): (Request, Vec<u8>) = read_frame(&mut reader).unwrap();
+        assert!(matches!(request, Request::WritePhysical { paddr: 0x1000 }));
+        assert_eq!(b"data", data.as_slice());
+        let (response, data): (Response, Vec<u8>) = read_frame(&mut reader).unwrap();
+        match response {
+            Response::Event(Some(Event {
+                vcpu: 1,
+                kind: EventType::Pagefault { access, .. },
+            })) => assert_eq!(Access::RW, access),
+            _ => panic!("unexpected response {:?}", response),
+        }
+        assert!(data.is_empty());
+    }
+
+    /// Reader timing out before each byte
+    struct SlowReader {
+        data: Cursor<Vec<u8>>,
+        ready: bool,
+    }
+
+    impl Read for SlowReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.ready = !self.ready;
+            if !self.ready {
+                return Err(io::Error::from(ErrorKind::WouldBlock));
+            }
+            let len = buf.len().min(1);
+            self.data.read(&mut buf[..len])
+        }
+    }
+
+    #[test]
+    fn test_frame_is_read_across_timeouts() {
+        let mut stream = Vec::new();
+        write_frame(&mut stream, &Request::Subscribe { enabled: true }, &[]).unwrap();
+        let mut reader = SlowReader {
+            data: Cursor::new(stream),
+            ready: true,
+        };
+
+        let first: Option<(Request, Vec<u8>)> = try_read_frame(&mut reader).unwrap();
+        assert!(first.is_none());
+        let second: Option<(Request, Vec<u8>)> = try_read_frame(&mut reader).unwrap();
+        assert!(matches!(
+            second,
+            Some((Request::Subscribe { enabled: true }, _))
+        ));
+    }
+
+    #[test]
+    fn test_oversized_frame_is_refused() {
+        let mut stream = Vec::new();
+        stream.extend_from_slice(&(MAX_HEADER_SIZE + 1).to_le_bytes());
+        let result: Result<(Request, Vec<u8>), ProtocolError> =
+            read_frame(&mut Cursor::new(stream));
+        assert!(matches!(
+            result,
+            Err(ProtocolError::FrameTooLarge("header", _, MAX_HEADER_SIZE))
+        ));
+    }
+}
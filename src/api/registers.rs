@@ -1,6 +1,8 @@
+use serde::{Deserialize, Serialize};
+
 ///an x86 segment register
 #[repr(C)]
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Serialize, Deserialize)]
 pub struct SegmentReg {
     ///Stores the base address of a code segment
     pub base: u64,
@@ -13,7 +15,7 @@ pub struct SegmentReg {
 /// x86 System Table Registers
 /// (GDTR, IDTR)
 #[repr(C)]
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Serialize, Deserialize)]
 pub struct SystemTableReg {
     /// 32/64 bits linear base address
     pub base: u64,
@@ -23,7 +25,7 @@ pub struct SystemTableReg {
 
 ///Represents all x86 registers on a specific VCPU
 #[repr(C)]
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Serialize, Deserialize)]
 pub struct X86Registers {
     /// 8 byte general purpose register.
     pub rax: u64,
@@ -106,7 +108,145 @@ pub struct X86Registers {
 }
 
 #[repr(C)]
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 pub enum Registers {
     X86(X86Registers),
 }
+
+/// Registers applied by the drivers when replying to an event with SetRegisters
+pub const X86_REPLY_REGISTERS: [&str; 18] = [
+    "rax", "rbx", "rcx", "rdx", "rsi", "rdi", "rsp", "rbp", "r8", "r9", "r10", "r11", "r12", "r13",
+    "r14", "r15", "rip", "rflags",
+];
+
+impl SegmentReg {
+    fn values(&self, names: [&'static str; 3]) -> [(&'static str, u64); 3] {
+        [
+            (names[0], self.base),
+            (names[1], self.limit.into()),
+            (names[2], self.selector.into()),
+        ]
+    }
+}
+
+impl SystemTableReg {
+    fn values(&self, names: [&'static str; 2]) -> [(&'static str, u64); 2] {
+        [(names[0], self.base), (names[1], self.limit.into())]
+    }
+}
+
+impl X86Registers {
+    /// The name and value of every register, segment and system table registers being split
+    /// into their fields (e.g. `cs.base`, `cs.limit`, `cs.selector`)
+    pub fn values(&self) -> Vec<(&'static str, u64)> {
+        let mut values = vec![
+            ("rax", self.rax),
+            ("rbx", self.rbx),
+            ("rcx", self.rcx),
+            ("rdx", self.rdx),
+            ("rsi", self.rsi),
+            ("rdi", self.rdi),
+            ("rsp", self.rsp),
+            ("rbp", self.rbp),
+            ("r8", self.r8),
+            ("r9", self.r9),
+            ("r10", self.r10),
+            ("r11", self.r11),
+            ("r12", self.r12),
+            ("r13", self.r13),
+            ("r14", self.r14),
+            ("r15", self.r15),
+            ("rip", self.rip),
+            ("rflags", self.rflags),
+            ("cr0", self.cr0),
+            ("cr2", self.cr2),
+            ("cr3", self.cr3),
+            ("cr4", self.cr4),
+            ("sysenter_cs", self.sysenter_cs),
+            ("sysenter_esp", self.sysenter_esp),
+            ("sysenter_eip", self.sysenter_eip),
+            ("msr_efer", self.msr_efer),
+            ("msr_star", self.msr_star),
+            ("msr_lstar", self.msr_lstar),
+            ("efer", self.efer),
+            ("apic_base", self.apic_base),
+        ];
+        values.extend_from_slice(&self.cs.values(["cs.base", "cs.limit", "cs.selector"]));
+        values.extend_from_slice(&self.ds.values(["ds.base", "ds.limit", "ds.selector"]));
+        values.extend_from_slice(&self.es.values(["es.base", "es.limit", "es.selector"]));
+        values.extend_from_slice(&self.fs.values(["fs.base", "fs.limit", "fs.selector"]));
+        values.extend_from_slice(&self.gs.values(["gs.base", "gs.limit", "gs.selector"]));
+        values.extend_from_slice(&self.ss.values(["ss.base", "ss.limit", "ss.selector"]));
+        values.extend_from_slice(&self.tr.values(["tr.base", "tr.limit", "tr.selector"]));
+        values.extend_from_slice(&self.ldt.values(["ldt.base", "ldt.limit", "ldt.selector"]));
+        values.extend_from_slice(&self.idt.values(["idt.base", "idt.limit"]));
+        values.extend_from_slice(&self.gdt.values(["gdt.base", "gdt.limit"]));
+        values
+    }
+
+    /// Names of the registers whose value differ from `other`
+    pub fn changed(&self, other: &X86Registers) -> Vec<&'static str> {
+        self.values()
+            .into_iter()
+            .zip(other.values())
+            .filter(|((_, value), (_, other_value))| value != other_value)
+            .map(|((name, _), _)| name)
+            .collect()
+    }
+
+    /// Registers set to a new value which a SetRegisters event reply can't apply
+    pub fn unapplied_on_reply(&self, current: &X86Registers) -> Vec<&'static str> {
+        self.changed(current)
+            .into_iter()
+            .filter(|name| !X86_REPLY_REGISTERS.contains(name))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_values_cover_segments_and_tables() {
+        let names: Vec<&str> = X86Registers::default()
+            .values()
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+
+        for name in &[
+            "fs.base",
+            "gs.selector",
+            "cs.limit",
+            "idt.base",
+            "gdt.limit",
+        ] {
+            assert!(names.contains(name), "{} is missing", name);
+        }
+    }
+
+    #[test]
+    fn test_unapplied_on_reply() {
+        let current = X86Registers::default();
+        let requested = X86Registers {
+            rax: 1,
+            rip: 0x1000,
+            cr3: 0x2000,
+            gs: SegmentReg {
+                base: 0x3000,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        assert_eq!(
+            requested.changed(&current),
+            vec!["rax", "rip", "cr3", "gs.base"]
+        );
+        assert_eq!(
+            requested.unapplied_on_reply(&current),
+            vec!["cr3", "gs.base"]
+        );
+    }
+}
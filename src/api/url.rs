@@ -0,0 +1,324 @@
+//! This module parses the libmicrovmi connection URLs
+//!
+//! A URL describes the driver to initialize and its initialization parameters:
+//! `vmi://<hypervisor>/?param1=value1&param2=value2`
+//!
+//! The hypervisor is optional, and is one of the [DriverType](../enum.DriverType.html) variants (case insensitive).
+//! The parameters are:
+//! - `vm_name`: [CommonInitParams](../params/struct.CommonInitParams.html) VM name
+//! - `read_only`: `true` or `false`
+//! - `audit_log`: path of the JSON lines audit log of the operations modifying the guest
+//! - `kvm_unix_socket`: KVMi unix socket path
+//! - `kvm_vsock_port`: KVMi vsock port, instead of the unix socket
+//! - `xen_domid`: Xen domain ID, instead of looking up the VM name in xenstore
+//! - `xen_monitor_ring`: `true` or `false`, whether the Xen driver enables the vm_event monitor ring
+//! - `memflow_connector_name`: memflow connector name
+//! - `memflow_connector_args`: memflow connector argument, can be repeated
+//! - `memflow_os_name`: memflow OS plugin name
+//! - `memflow_os_args`: memflow OS plugin argument, can be repeated
+//! - `remote_addr`: microvmi-server address, `tcp://<host>:<port>` or `unix://<path>`
+//! - `remote_token`: token shared with the microvmi-server
+//!
+//! # Examples
+//! ```
+//! use microvmi::api::url::parse_url;
+//! use microvmi::api::DriverType;
+//!
+//! let (driver_type, init_params) =
+//!     parse_url("vmi://kvm/?vm_name=windows10&kvm_unix_socket=/tmp/introspector").unwrap();
+//! assert_eq!(driver_type, Some(DriverType::KVM));
+//! assert_eq!(init_params.unwrap().common.unwrap().vm_name, "windows10");
+//! ```
+
+use std::str::FromStr;
+
+use crate::api::params::{
+    CommonInitParams, DriverInitParams, KVMInitParams, MemflowConnectorParams, MemflowInitParams,
+    RemoteInitParams, XenInitParams, XenMonitorRing, VMADDR_CID_ANY,
+};
+use crate::api::DriverType;
+
+/// URL scheme handled by libmicrovmi
+pub const SCHEME: &str = "vmi";
+
+#[derive(thiserror::Error, Debug, PartialEq)]
+pub enum UrlError {
+    #[error("invalid URL scheme in {0}, expected {}://", SCHEME)]
+    InvalidScheme(String),
+    #[error("invalid driver type {0}, expected one of: KVM, Memflow, VirtualBox, Xen, Remote")]
+    InvalidDriverType(String),
+    #[error("malformed URL parameter {0}")]
+    MalformedParameter(String),
+    #[error("unknown driver initialization parameter: {0}")]
+    UnknownParameter(String),
+    #[error("invalid value {1} for parameter {0}")]
+    InvalidValue(String, String),
+    #[error("memflow connector or OS args received but no connector name specified")]
+    MissingMemflowConnectorName,
+}
+
+impl FromStr for DriverType {
+    type Err = UrlError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "kvm" => Ok(DriverType::KVM),
+            "memflow" => Ok(DriverType::Memflow),
+            "virtualbox" => Ok(DriverType::VirtualBox),
+            "xen" => Ok(DriverType::Xen),
+            "remote" => Ok(DriverType::Remote),
+            _ => Err(UrlError::InvalidDriverType(s.to_string())),
+        }
+    }
+}
+
+/// Parse a connection URL into a driver type and its initialization parameters
+///
+/// Both are None when they are not specified in the URL.
+///
+/// # Arguments
+/// * 'url' - the connection URL, `vmi://<hypervisor>/?param1=value1&param2=value2`
+///
+pub fn parse_url(url: &str) -> Result<(Option<DriverType>, Option<DriverInitParams>), UrlError> {
+    let rest = url
+        .strip_prefix(SCHEME)
+        .and_then(|rest| rest.strip_prefix("://"))
+        .ok_or_else(|| UrlError::InvalidScheme(url.to_string()))?;
+    let (location, query) = match rest.find('?') {
+        Some(index) => (&rest[..index], Some(&rest[index + 1..])),
+        None => (rest, None),
+    };
+    // the path is ignored
+    let hypervisor = location.split('/').next().unwrap_or_default();
+    let driver_type = if hypervisor.is_empty() {
+        None
+    } else {
+        Some(DriverType::from_str(hypervisor)?)
+    };
+    let init_params = match query {
+        Some(query) if !query.is_empty() => Some(parse_query(query)?),
+        _ => None,
+    };
+    Ok((driver_type, init_params))
+}
+
+fn parse_query(query: &str) -> Result<DriverInitParams, UrlError> {
+    let mut vm_name = None;
+    let mut read_only = None;
+    let mut audit_log = None;
+    let mut kvm = None;
+    let mut xen_domid = None;
+    let mut xen_monitor_ring = None;
+    let mut connector_name = None;
+    let mut connector_args: Option<Vec<String>> = None;
+    let mut os_name = None;
+    let mut os_args: Option<Vec<String>> = None;
+    let mut remote_addr = None;
+    let mut remote_token = None;
+    for param in query.split('&') {
+        let (key, value) = param
+            .split_once('=')
+            .ok_or_else(|| UrlError::MalformedParameter(param.to_string()))?;
+        let key = percent_decode(key)?;
+        let value = percent_decode(value)?;
+        match key.as_str() {
+            "vm_name" => vm_name = Some(value),
+            "read_only" => read_only = Some(parse_bool(key, value)?),
+            "audit_log" => audit_log = Some(value),
+            "kvm_unix_socket" => kvm = Some(KVMInitParams::UnixSocket { path: value }),
+            "kvm_vsock_port" => match value.parse::<u32>() {
+                Ok(port) => {
+                    kvm = Some(KVMInitParams::Vsock {
+                        cid: VMADDR_CID_ANY,
+                        port,
+                    })
+                }
+                Err(_) => return Err(UrlError::InvalidValue(key, value)),
+            },
+            "xen_domid" => match value.parse::<u32>() {
+                Ok(domid) => xen_domid = Some(domid),
+                Err(_) => return Err(UrlError::InvalidValue(key, value)),
+            },
+            "xen_monitor_ring" => xen_monitor_ring = Some(parse_bool(key, value)?),
+            "memflow_connector_name" => connector_name = Some(value),
+            "memflow_connector_args" => connector_args.get_or_insert_with(Vec::new).push(value),
+            "memflow_os_name" => os_name = Some(value),
+            "memflow_os_args" => os_args.get_or_insert_with(Vec::new).push(value),
+            "remote_addr" => remote_addr = Some(value),
+            "remote_token" => remote_token = Some(value),
+            _ => return Err(UrlError::UnknownParameter(key)),
+        }
+    }
+    let common = if vm_name.is_some() || read_only.is_some() || audit_log.is_some() {
+        Some(CommonInitParams {
+            vm_name: vm_name.unwrap_or_default(),
+            read_only: read_only.unwrap_or_default(),
+            audit_log,
+        })
+    } else {
+        None
+    };
+    let memflow = match connector_name {
+        Some(connector_name) => Some(MemflowInitParams {
+            connector_name,
+            connector_args: connector_args.map(|args| MemflowConnectorParams::Default { args }),
+            os_name,
+            os_args,
+        }),
+        None if connector_args.is_some() || os_name.is_some() || os_args.is_some() => {
+            return Err(UrlError::MissingMemflowConnectorName)
+        }
+        None => None,
+    };
+    let xen = match (xen_domid, xen_monitor_ring) {
+        (domid, Some(enabled)) => Some(XenInitParams::Options {
+            domid,
+            monitor_ring: XenMonitorRing::from(enabled),
+        }),
+        (domid, None) => domid.map(XenInitParams::DomId),
+    };
+    // a missing address or token is reported by the driver
+    let remote = if remote_addr.is_some() || remote_token.is_some() {
+        Some(RemoteInitParams {
+            addr: remote_addr.unwrap_or_default(),
+            token: remote_token.unwrap_or_default(),
+        })
+    } else {
+        None
+    };
+    Ok(DriverInitParams {
+        common,
+        xen,
+        kvm,
+        memflow,
+        remote,
+        ..Default::default()
+    })
+}
+
+fn parse_bool(key: String, value: String) -> Result<bool, UrlError> {
+    match value.as_str() {
+        "true" | "1" => Ok(true),
+        "false" | "0" => Ok(false),
+        _ => Err(UrlError::InvalidValue(key, value)),
+    }
+}
+
+/// Decode the percent-encoded characters, and `+` as a space
+fn percent_decode(s: &str) -> Result<String, UrlError> {
+    let malformed = || UrlError::MalformedParameter(s.to_string());
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' => {
+                let hex = s.get(i + 1..i + 3).ok_or_else(malformed)?;
+                decoded.push(u8::from_str_radix(hex, 16).map_err(|_| malformed())?);
+                i += 3;
+            }
+            b'+' => {
+                decoded.push(b' ');
+                i += 1;
+            }
+            byte => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8(decoded).map_err(|_| malformed())
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use super::*;
+
+    #[test_case("vmi://KVM/", DriverType::KVM; "uppercase")]
+    #[test_case("vmi://memflow", DriverType::Memflow; "without path")]
+    #[test_case("vmi://VirtualBox/vm_name", DriverType::VirtualBox; "camel case")]
+    #[test_case("vmi://xen/?vm_name=win10", DriverType::Xen; "with parameters")]
+    #[test_case("vmi://remote/?remote_addr=tcp://10.0.0.1:7475", DriverType::Remote; "remote")]
+    fn test_parse_driver_type(url: &str, expected: DriverType) {
+        let (driver_type, _) = parse_url(url).unwrap();
+
+        assert_eq!(driver_type, Some(expected));
+    }
+
+    #[test]
+    fn test_parse_without_driver_type_nor_params() {
+        assert_eq!(parse_url("vmi:///").unwrap(), (None, None));
+    }
+
+    #[test]
+    fn test_parse_every_parameter() {
+        let url =
+            "vmi:///?vm_name=windows%2010+20H1&read_only=true&kvm_unix_socket=/tmp/introspector\
+            &memflow_connector_name=qemu&memflow_connector_args=a&memflow_connector_args=b\
+            &memflow_os_name=win32";
+
+        let (_, init_params) = parse_url(url).unwrap();
+
+        assert_eq!(
+            init_params.unwrap(),
+            DriverInitParams {
+                common: Some(CommonInitParams {
+                    vm_name: String::from("windows 10 20H1"),
+                    read_only: true,
+                    audit_log: None,
+                }),
+                kvm: Some(KVMInitParams::UnixSocket {
+                    path: String::from("/tmp/introspector"),
+                }),
+                memflow: Some(MemflowInitParams {
+                    connector_name: String::from("qemu"),
+                    connector_args: Some(MemflowConnectorParams::Default {
+                        args: vec![String::from("a"), String::from("b")],
+                    }),
+                    os_name: Some(String::from("win32")),
+                    os_args: None,
+                }),
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_remote_parameters() {
+        let (_, init_params) =
+            parse_url("vmi://remote/?remote_addr=unix:///run/microvmi.sock&remote_token=s3cr%3Dt")
+                .unwrap();
+
+        assert_eq!(
+            init_params.unwrap().remote,
+            Some(RemoteInitParams {
+                addr: String::from("unix:///run/microvmi.sock"),
+                token: String::from("s3cr=t"),
+            })
+        );
+    }
+
+    #[test_case("vmi://xen/?xen_domid=5", XenInitParams::DomId(5); "domid")]
+    #[test_case("vmi://xen/?xen_domid=5&xen_monitor_ring=false", XenInitParams::Options { domid: Some(5), monitor_ring: XenMonitorRing::Disabled }; "monitor ring disabled")]
+    #[test_case("vmi://xen/?vm_name=win10&xen_monitor_ring=1", XenInitParams::Options { domid: None, monitor_ring: XenMonitorRing::Enabled }; "monitor ring without domid")]
+    fn test_parse_xen_parameters(url: &str, expected: XenInitParams) {
+        let (_, init_params) = parse_url(url).unwrap();
+
+        assert_eq!(init_params.unwrap().xen, Some(expected));
+    }
+
+    #[test_case("http:///", UrlError::InvalidScheme(String::from("http:///")); "invalid scheme")]
+    #[test_case("vmi://hyperv/", UrlError::InvalidDriverType(String::from("hyperv")); "invalid driver")]
+    #[test_case("vmi://?foo=bar", UrlError::UnknownParameter(String::from("foo")); "unknown parameter")]
+    #[test_case("vmi://?vm_name", UrlError::MalformedParameter(String::from("vm_name")); "missing value")]
+    #[test_case("vmi://?read_only=yes", UrlError::InvalidValue(String::from("read_only"), String::from("yes")); "invalid value")]
+    #[test_case("vmi://kvm/?kvm_vsock_port=vsock", UrlError::InvalidValue(String::from("kvm_vsock_port"), String::from("vsock")); "invalid vsock port")]
+    #[test_case("vmi://xen/?xen_domid=-1", UrlError::InvalidValue(String::from("xen_domid"), String::from("-1")); "invalid domid")]
+    #[test_case("vmi://xen/?xen_monitor_ring=off", UrlError::InvalidValue(String::from("xen_monitor_ring"), String::from("off")); "invalid monitor ring")]
+    #[test_case("vmi://?memflow_connector_args=a", UrlError::MissingMemflowConnectorName; "memflow args without name")]
+    fn test_parse_error(url: &str, expected: UrlError) {
+        assert_eq!(parse_url(url).unwrap_err(), expected);
+    }
+}
@@ -0,0 +1,23 @@
+//! This module describes the drivers and VMs found by the enumeration API
+//!
+//! See [available_drivers](../../fn.available_drivers.html) and [list_vms](../../fn.list_vms.html).
+
+use serde::{Deserialize, Serialize};
+
+use crate::api::DriverType;
+
+/// A compiled driver, and whether its hypervisor interface is reachable
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct DriverProbe {
+    pub driver_type: DriverType,
+    pub reachable: bool,
+}
+
+/// A VM seen by a driver
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VmInfo {
+    /// name to use as `CommonInitParams.vm_name`
+    pub name: String,
+    /// Xen domain ID, or QEMU process ID
+    pub id: Option<u32>,
+}
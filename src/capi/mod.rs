@@ -1,14 +1,16 @@
 use bitflags::_core::ptr::null_mut;
-use cty::{c_char, size_t, uint16_t, uint64_t, uint8_t};
+use cty::{c_char, size_t, uint16_t, uint32_t, uint64_t, uint8_t};
 
-use std::ffi::{c_void, CString};
+use std::ffi::{c_void, CStr, CString};
+use std::panic::{catch_unwind, AssertUnwindSafe};
 use std::slice;
 
+use crate::api::events::{Event, EventReplyType, InterceptType};
 use crate::api::params::DriverInitParams;
 use crate::api::registers::Registers;
-use crate::api::{DriverType, Introspectable};
+use crate::api::{Access, DriverType, Introspectable};
 use crate::capi::params::DriverInitParamsFFI;
-use crate::init;
+use crate::{init, init_from_url};
 use std::convert::TryFrom;
 
 pub mod params;
@@ -39,41 +41,57 @@ pub unsafe extern "C" fn microvmi_init(
     init_params: *const DriverInitParamsFFI,
     init_error: *mut *const c_char,
 ) -> *mut c_void {
-    // check driver type
-    let optional_driver_type: Option<DriverType> = if driver_type.is_null() {
-        None
-    } else {
-        Some(driver_type.read())
-    };
-    // check init params
-    let optional_init_params = if init_params.is_null() {
-        None
-    } else {
-        Some(
-            DriverInitParams::try_from(init_params.read())
-                .expect("Failed to convert DriverInitParam C struct to Rust"),
-        )
-    };
-
-    match init(optional_driver_type, optional_init_params) {
-        Ok(driver) => Box::into_raw(Box::new(driver)) as *mut c_void,
-        Err(err) => {
-            if !init_error.is_null() {
-                (*init_error) = CString::new(format!("{}", err))
-                    .expect("Failed to convert MicrovmiError to CString")
-                    .into_raw();
-            };
-            null_mut()
+    into_context("microvmi_init", init_error, || {
+        // check driver type
+        let optional_driver_type: Option<DriverType> = if driver_type.is_null() {
+            None
+        } else {
+            Some(driver_type.read())
+        };
+        // check init params
+        let optional_init_params = if init_params.is_null() {
+            None
+        } else {
+            Some(
+                DriverInitParams::try_from(init_params.read())
+                    .map_err(|e| format!("invalid init params: {}", e))?,
+            )
+        };
+        init(optional_driver_type, optional_init_params).map_err(|e| e.to_string())
+    })
+}
+
+/// Initializes a driver from a connection URL
+///
+/// See microvmi_init for the error handling.
+///
+/// # Safety
+///
+/// url must be a valid C string, a NULL url is refused.
+/// The init_error pointer should be freed with rs_cstring_free()
+#[no_mangle]
+pub unsafe extern "C" fn microvmi_init_from_url(
+    url: *const c_char,
+    init_error: *mut *const c_char,
+) -> *mut c_void {
+    into_context("microvmi_init_from_url", init_error, || {
+        if url.is_null() {
+            return Err(String::from("NULL url"));
         }
-    }
+        let url = CStr::from_ptr(url).to_str().map_err(|e| e.to_string())?;
+        init_from_url(url).map_err(|e| e.to_string())
+    })
 }
 
 #[allow(clippy::missing_safety_doc)]
 #[no_mangle]
 pub unsafe extern "C" fn microvmi_destroy(context: *mut c_void) {
     if !context.is_null() {
-        let _ = get_driver_box(context);
-        // box destructor is called
+        catch_panic("microvmi_destroy", || {
+            let _ = get_driver_box(context);
+            // box destructor is called
+            true
+        });
     }
 }
 
@@ -84,7 +102,7 @@ pub unsafe extern "C" fn microvmi_pause(context: *mut c_void) -> bool {
         return false;
     }
     let driver = get_driver_mut_ptr(context);
-    (*driver).pause().is_ok()
+    catch_panic("microvmi_pause", || (*driver).pause().is_ok())
 }
 
 #[allow(clippy::missing_safety_doc)]
@@ -94,7 +112,7 @@ pub unsafe extern "C" fn microvmi_resume(context: *mut c_void) -> bool {
         return false;
     }
     let driver = get_driver_mut_ptr(context);
-    (*driver).resume().is_ok()
+    catch_panic("microvmi_resume", || (*driver).resume().is_ok())
 }
 
 #[allow(clippy::missing_safety_doc)]
@@ -106,24 +124,25 @@ pub unsafe extern "C" fn microvmi_read_physical(
     size: size_t,
     bytes_read: *mut uint64_t,
 ) -> bool {
-    if context.is_null() {
+    if context.is_null() || buffer.is_null() {
         return false;
     }
     let driver = get_driver_mut_ptr(context);
-
-    let mut bytes_read_local = 0;
-    let res = (*driver)
-        .read_physical(
-            physical_address,
-            slice::from_raw_parts_mut(buffer, size),
-            &mut bytes_read_local,
-        )
-        .is_ok();
-    // update bytes_read if not NULL
-    if !bytes_read.is_null() {
-        bytes_read.write(bytes_read_local);
-    }
-    res
+    catch_panic("microvmi_read_physical", || {
+        let mut bytes_read_local = 0;
+        let res = (*driver)
+            .read_physical(
+                physical_address,
+                slice::from_raw_parts_mut(buffer, size),
+                &mut bytes_read_local,
+            )
+            .is_ok();
+        // update bytes_read if not NULL
+        if !bytes_read.is_null() {
+            bytes_read.write(bytes_read_local);
+        }
+        res
+    })
 }
 
 #[allow(clippy::missing_safety_doc)]
@@ -134,14 +153,16 @@ pub unsafe extern "C" fn microvmi_write_physical(
     buffer: *mut uint8_t,
     size: size_t,
 ) -> bool {
-    if context.is_null() {
+    if context.is_null() || buffer.is_null() {
         return false;
     }
     let driver = get_driver_mut_ptr(context);
-    let slice_buf = slice::from_raw_parts_mut(buffer, size);
-    (*driver)
-        .write_physical(physical_address, slice_buf)
-        .is_ok()
+    catch_panic("microvmi_write_physical", || {
+        let slice_buf = slice::from_raw_parts_mut(buffer, size);
+        (*driver)
+            .write_physical(physical_address, slice_buf)
+            .is_ok()
+    })
 }
 
 #[allow(clippy::missing_safety_doc)]
@@ -150,17 +171,19 @@ pub unsafe extern "C" fn microvmi_get_max_physical_addr(
     context: *mut c_void,
     address_ptr: *mut uint64_t,
 ) -> bool {
-    if context.is_null() {
+    if context.is_null() || address_ptr.is_null() {
         return false;
     }
     let driver = get_driver_mut_ptr(context);
-    match (*driver).get_max_physical_addr() {
-        Ok(max_addr) => {
-            address_ptr.write(max_addr);
-            true
+    catch_panic("microvmi_get_max_physical_addr", || {
+        match (*driver).get_max_physical_addr() {
+            Ok(max_addr) => {
+                address_ptr.write(max_addr);
+                true
+            }
+            Err(_) => false,
         }
-        Err(_) => false,
-    }
+    })
 }
 
 #[allow(clippy::missing_safety_doc)]
@@ -169,26 +192,211 @@ pub unsafe extern "C" fn microvmi_read_registers(
     context: *mut c_void,
     vcpu: uint16_t,
     registers: *mut Registers,
+) -> bool {
+    if context.is_null() || registers.is_null() {
+        return false;
+    }
+    let driver = get_driver_mut_ptr(context);
+    catch_panic("microvmi_read_registers", || {
+        match (*driver).read_registers(vcpu) {
+            Ok(regs) => {
+                registers.write(regs);
+                true
+            }
+            Err(_) => false,
+        }
+    })
+}
+
+#[allow(clippy::missing_safety_doc)]
+#[no_mangle]
+pub unsafe extern "C" fn microvmi_get_vcpu_count(
+    context: *mut c_void,
+    vcpu_count: *mut uint16_t,
+) -> bool {
+    if context.is_null() || vcpu_count.is_null() {
+        return false;
+    }
+    let driver = get_driver_mut_ptr(context);
+    catch_panic("microvmi_get_vcpu_count", || {
+        match (*driver).get_vcpu_count() {
+            Ok(count) => {
+                vcpu_count.write(count);
+                true
+            }
+            Err(_) => false,
+        }
+    })
+}
+
+#[allow(clippy::missing_safety_doc)]
+#[no_mangle]
+pub unsafe extern "C" fn microvmi_write_registers(
+    context: *mut c_void,
+    vcpu: uint16_t,
+    registers: *const Registers,
+) -> bool {
+    if context.is_null() || registers.is_null() {
+        return false;
+    }
+    let driver = get_driver_mut_ptr(context);
+    catch_panic("microvmi_write_registers", || {
+        (*driver).write_registers(vcpu, registers.read()).is_ok()
+    })
+}
+
+#[allow(clippy::missing_safety_doc)]
+#[no_mangle]
+pub unsafe extern "C" fn microvmi_get_page_access(
+    context: *mut c_void,
+    physical_address: uint64_t,
+    access: *mut Access,
+) -> bool {
+    if context.is_null() || access.is_null() {
+        return false;
+    }
+    let driver = get_driver_mut_ptr(context);
+    catch_panic("microvmi_get_page_access", || {
+        match (*driver).get_page_access(physical_address) {
+            Ok(page_access) => {
+                access.write(page_access);
+                true
+            }
+            Err(_) => false,
+        }
+    })
+}
+
+#[allow(clippy::missing_safety_doc)]
+#[no_mangle]
+pub unsafe extern "C" fn microvmi_set_page_access(
+    context: *mut c_void,
+    physical_address: uint64_t,
+    access: Access,
+) -> bool {
+    if context.is_null() {
+        return false;
+    }
+    let driver = get_driver_mut_ptr(context);
+    catch_panic("microvmi_set_page_access", || {
+        (*driver).set_page_access(physical_address, access).is_ok()
+    })
+}
+
+#[allow(clippy::missing_safety_doc)]
+#[no_mangle]
+pub unsafe extern "C" fn microvmi_toggle_intercept(
+    context: *mut c_void,
+    vcpu: uint16_t,
+    intercept_type: InterceptType,
+    enabled: bool,
 ) -> bool {
     if context.is_null() {
         return false;
     }
     let driver = get_driver_mut_ptr(context);
-    match (*driver).read_registers(vcpu) {
-        Ok(regs) => {
-            registers.write(regs);
+    catch_panic("microvmi_toggle_intercept", || {
+        (*driver)
+            .toggle_intercept(vcpu, intercept_type, enabled)
+            .is_ok()
+    })
+}
+
+/// Wait for the next event, for at most timeout milliseconds
+///
+/// received is set to whether an event has been written to event.
+/// The event must be replied to with microvmi_reply_event().
+#[allow(clippy::missing_safety_doc)]
+#[no_mangle]
+pub unsafe extern "C" fn microvmi_listen(
+    context: *mut c_void,
+    timeout: uint32_t,
+    event: *mut Event,
+    received: *mut bool,
+) -> bool {
+    if context.is_null() || event.is_null() {
+        return false;
+    }
+    let driver = get_driver_mut_ptr(context);
+    catch_panic("microvmi_listen", || match (*driver).listen(timeout) {
+        Ok(event_option) => {
+            if !received.is_null() {
+                received.write(event_option.is_some());
+            }
+            if let Some(ev) = event_option {
+                event.write(ev);
+            }
             true
         }
         Err(_) => false,
+    })
+}
+
+#[allow(clippy::missing_safety_doc)]
+#[no_mangle]
+pub unsafe extern "C" fn microvmi_reply_event(
+    context: *mut c_void,
+    event: Event,
+    reply_type: EventReplyType,
+) -> bool {
+    if context.is_null() {
+        return false;
     }
+    let driver = get_driver_mut_ptr(context);
+    catch_panic("microvmi_reply_event", || {
+        (*driver).reply_event(event, reply_type).is_ok()
+    })
 }
 
-/// return the concrete DriverType for the given Microvmi driver
+/// write the concrete DriverType of the given Microvmi driver to driver_type
 #[allow(clippy::missing_safety_doc)]
 #[no_mangle]
-pub unsafe extern "C" fn microvmi_get_driver_type(context: *mut c_void) -> DriverType {
-    let drv = get_driver_mut_ptr(context);
-    (*drv).get_driver_type()
+pub unsafe extern "C" fn microvmi_get_driver_type(
+    context: *mut c_void,
+    driver_type: *mut DriverType,
+) -> bool {
+    if context.is_null() || driver_type.is_null() {
+        return false;
+    }
+    let driver = get_driver_mut_ptr(context);
+    catch_panic("microvmi_get_driver_type", || {
+        driver_type.write((*driver).get_driver_type());
+        true
+    })
+}
+
+/// Run a driver operation, returning false if it panicked
+///
+/// Panics must not unwind into the C caller.
+fn catch_panic<F: FnOnce() -> bool>(name: &str, f: F) -> bool {
+    catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|_| {
+        error!("{} panicked", name);
+        false
+    })
+}
+
+/// Box the driver returned by init into a context, or write its error to init_error
+///
+/// A panic of the driver initialization is returned as an error.
+unsafe fn into_context<F>(name: &str, init_error: *mut *const c_char, init: F) -> *mut c_void
+where
+    F: FnOnce() -> Result<Box<dyn Introspectable>, String>,
+{
+    let result =
+        catch_unwind(AssertUnwindSafe(init)).unwrap_or_else(|_| Err(format!("{} panicked", name)));
+    match result {
+        Ok(driver) => Box::into_raw(Box::new(driver)) as *mut c_void,
+        Err(err) => {
+            if !init_error.is_null() {
+                // an interior NUL byte truncates the message
+                let err = err.split('\0').next().unwrap_or_default();
+                (*init_error) = CString::new(err)
+                    .expect("Failed to convert MicrovmiError to CString")
+                    .into_raw();
+            };
+            null_mut()
+        }
+    }
 }
 
 unsafe fn get_driver_mut_ptr(context: *mut c_void) -> *mut dyn Introspectable {
@@ -214,3 +422,69 @@ pub unsafe extern "C" fn rs_cstring_free(s: *mut c_char) {
     }
     drop(CString::from_raw(s));
 }
+
+#[cfg(test)]
+mod tests {
+    use std::ptr::null_mut;
+
+    use super::*;
+    use crate::driver::fake::FakeDriver;
+
+    fn context() -> *mut c_void {
+        // get_vcpu_count is left to the panicking default
+        let driver = FakeDriver::default();
+        driver.page_access.borrow_mut().insert(0x1000, Access::RW);
+        let driver: Box<dyn Introspectable> = Box::new(driver);
+        Box::into_raw(Box::new(driver)) as *mut c_void
+    }
+
+    #[test]
+    fn test_driver_panic_does_not_unwind_into_the_caller() {
+        let context = context();
+        let mut vcpu_count = 0;
+
+        let result = unsafe { microvmi_get_vcpu_count(context, &mut vcpu_count) };
+
+        unsafe { microvmi_destroy(context) };
+        assert!(!result);
+    }
+
+    #[test]
+    fn test_null_out_pointers_are_refused() {
+        let context = context();
+        let mut access = Access::NIL;
+
+        let mut driver_type = DriverType::Xen;
+
+        unsafe {
+            assert!(!microvmi_get_vcpu_count(context, null_mut()));
+            assert!(!microvmi_get_max_physical_addr(context, null_mut()));
+            assert!(!microvmi_read_registers(context, 0, null_mut()));
+            assert!(!microvmi_read_physical(
+                context,
+                0,
+                null_mut(),
+                4,
+                null_mut()
+            ));
+            assert!(!microvmi_get_driver_type(context, null_mut()));
+            assert!(microvmi_get_driver_type(context, &mut driver_type));
+            assert!(!microvmi_get_page_access(context, 0x1000, null_mut()));
+            assert!(microvmi_get_page_access(context, 0x1000, &mut access));
+            microvmi_destroy(context);
+        }
+        assert_eq!(driver_type, DriverType::KVM);
+        assert_eq!(access, Access::RW);
+    }
+
+    #[test]
+    fn test_null_url_is_refused() {
+        let mut init_error: *const c_char = std::ptr::null();
+
+        let driver = unsafe { microvmi_init_from_url(std::ptr::null(), &mut init_error) };
+
+        assert!(driver.is_null());
+        let message = unsafe { CString::from_raw(init_error as *mut c_char) };
+        assert_eq!(message.to_str().unwrap(), "NULL url");
+    }
+}
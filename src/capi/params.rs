@@ -1,5 +1,6 @@
 use crate::api::params::{
     CommonInitParams, DriverInitParams, KVMInitParams, MemflowConnectorParams, MemflowInitParams,
+    RemoteInitParams, XenInitParams, XenMonitorRing,
 };
 use std::convert::TryFrom;
 use std::ffi::{CStr, IntoStringError};
@@ -10,6 +11,22 @@ use std::os::raw::c_char;
 #[derive(Debug, Clone)]
 pub struct CommonInitParamsFFI {
     pub vm_name: *mut c_char,
+    pub read_only: bool,
+    /// path of the JSON lines audit log, can be NULL
+    pub audit_log: *mut c_char,
+}
+
+/// equivalent of `XenInitParams` with C compatibility
+///
+/// A zeroed struct leaves the Xen parameters unset
+#[repr(C)]
+#[derive(Debug, Clone)]
+pub struct XenInitParamsFFI {
+    /// whether domid is set, otherwise `vm_name` is looked up in xenstore
+    pub has_domid: bool,
+    pub domid: u32,
+    /// for domains which are not allowed to monitor the guest
+    pub disable_monitor_ring: bool,
 }
 
 /// equivalent of `KVMInitParams` with C compatibility
@@ -17,9 +34,13 @@ pub struct CommonInitParamsFFI {
 #[derive(Debug, Clone)]
 pub enum KVMInitParamsFFI {
     UnixSocket { path: *mut c_char },
+    VSock { cid: u32, port: u32 },
 }
 
+// C enumerators prefixed with the enum name: without it, the generated header declares
+// duplicate enumerators and doesn't compile (breaking change, see the C API documentation)
 /// equivalent of `MemflowConnectorParams`with C compatiblity
+/// cbindgen:prefix-with-name
 #[repr(C)]
 #[derive(Debug, Clone)]
 pub enum MemflowConnectorParamsFFI {
@@ -27,6 +48,30 @@ pub enum MemflowConnectorParamsFFI {
         args_arr: *mut *mut c_char,
         args_arr_len: usize,
     },
+    /// vm_name can be NULL
+    QEMUProcFs {
+        vm_name: *mut c_char,
+    },
+    KVM {
+        pid: u32,
+    },
+    /// device and memmap can be NULL
+    PCILeech {
+        device: *mut c_char,
+        memmap: *mut c_char,
+    },
+    Coredump {
+        filepath: *mut c_char,
+    },
+}
+
+/// convert a nullable C string to an owned String
+fn optional_string(s: *mut c_char) -> Result<Option<String>, IntoStringError> {
+    if s.is_null() {
+        Ok(None)
+    } else {
+        Ok(Some(unsafe { CStr::from_ptr(s) }.to_owned().into_string()?))
+    }
 }
 
 /// equivalent of `MemflowInitParams` with C compatibility
@@ -37,6 +82,21 @@ pub struct MemflowInitParamsFFI {
     pub connector_name: *mut c_char,
     /// optional connector initialization parameters
     pub connector_args: MemflowConnectorParamsFFI,
+    /// optional OS plugin name, can be NULL
+    pub os_name: *mut c_char,
+    /// optional OS plugin arguments
+    pub os_args_arr: *mut *mut c_char,
+    pub os_args_arr_len: usize,
+}
+
+/// equivalent of `RemoteInitParams` with C compatibility
+///
+/// addr and token can be NULL, when the Remote driver is not used
+#[repr(C)]
+#[derive(Debug, Clone)]
+pub struct RemoteInitParamsFFI {
+    pub addr: *mut c_char,
+    pub token: *mut c_char,
 }
 
 /// equivalent of `DriverInitParam` with C compatibility
@@ -44,8 +104,10 @@ pub struct MemflowInitParamsFFI {
 #[derive(Debug, Clone)]
 pub struct DriverInitParamsFFI {
     pub common: CommonInitParamsFFI,
+    pub xen: XenInitParamsFFI,
     pub kvm: KVMInitParamsFFI,
     pub memflow: MemflowInitParamsFFI,
+    pub remote: RemoteInitParamsFFI,
 }
 
 // convert from FFI type to Rust type
@@ -63,22 +125,45 @@ impl TryFrom<DriverInitParamsFFI> for DriverInitParams {
                     .into_string()?,
             )
         };
-        let common = vm_name.map(|v| CommonInitParams { vm_name: v });
+        let read_only = value.common.read_only;
+        let audit_log = optional_string(value.common.audit_log)?;
+        let common = if vm_name.is_some() || read_only || audit_log.is_some() {
+            Some(CommonInitParams {
+                vm_name: vm_name.unwrap_or_default(),
+                read_only,
+                audit_log,
+            })
+        } else {
+            None
+        };
+        // build xen params
+        let domid = if value.xen.has_domid {
+            Some(value.xen.domid)
+        } else {
+            None
+        };
+        let xen = if value.xen.disable_monitor_ring {
+            Some(XenInitParams::Options {
+                domid,
+                monitor_ring: XenMonitorRing::Disabled,
+            })
+        } else {
+            domid.map(XenInitParams::DomId)
+        };
         // build kvm params
-        let kvm_socket = match value.kvm {
+        let kvm = match value.kvm {
             KVMInitParamsFFI::UnixSocket { path } => {
-                if path.is_null() {
-                    None
-                } else {
-                    Some(unsafe { CStr::from_ptr(path) }.to_owned().into_string()?)
-                }
+                optional_string(path)?.map(|path| KVMInitParams::UnixSocket { path })
             }
+            KVMInitParamsFFI::VSock { cid, port } => Some(KVMInitParams::Vsock { cid, port }),
         };
-        let kvm = kvm_socket.map(|v| KVMInitParams::UnixSocket { path: v });
         // build memflow params
         let MemflowInitParamsFFI {
             connector_name,
             connector_args,
+            os_name,
+            os_args_arr,
+            os_args_arr_len,
         } = value.memflow;
         let memflow = if connector_name.is_null() {
             None
@@ -104,18 +189,62 @@ impl TryFrom<DriverInitParamsFFI> for DriverInitParams {
                         Some(MemflowConnectorParams::Default { args: vec![] })
                     }
                 }
+                MemflowConnectorParamsFFI::QEMUProcFs { vm_name } => {
+                    Some(MemflowConnectorParams::QEMUProcFs {
+                        vm_name: optional_string(vm_name)?,
+                    })
+                }
+                MemflowConnectorParamsFFI::KVM { pid } => Some(MemflowConnectorParams::KVM { pid }),
+                MemflowConnectorParamsFFI::PCILeech { device, memmap } => {
+                    Some(MemflowConnectorParams::PCILeech {
+                        device: optional_string(device)?,
+                        memmap: optional_string(memmap)?,
+                    })
+                }
+                // a NULL file path is refused by the driver
+                MemflowConnectorParamsFFI::Coredump { filepath } => {
+                    Some(MemflowConnectorParams::Coredump {
+                        filepath: optional_string(filepath)?.unwrap_or_default(),
+                    })
+                }
             };
             Some(MemflowInitParams {
                 connector_name: unsafe { CStr::from_ptr(connector_name) }
                     .to_owned()
                     .into_string()?,
                 connector_args: args,
+                os_name: optional_string(os_name)?,
+                os_args: if !os_args_arr.is_null() && os_args_arr_len > 0 {
+                    let os_args =
+                        unsafe { std::slice::from_raw_parts(os_args_arr, os_args_arr_len) };
+                    Some(
+                        os_args
+                            .iter()
+                            .map(|s| unsafe { CStr::from_ptr(*s) }.to_owned().into_string())
+                            .collect::<Result<Vec<String>, IntoStringError>>()?,
+                    )
+                } else {
+                    None
+                },
             })
         };
+        // build remote params
+        let addr = optional_string(value.remote.addr)?;
+        let token = optional_string(value.remote.token)?;
+        let remote = if addr.is_some() || token.is_some() {
+            Some(RemoteInitParams {
+                addr: addr.unwrap_or_default(),
+                token: token.unwrap_or_default(),
+            })
+        } else {
+            None
+        };
         Ok(DriverInitParams {
             common,
+            xen,
             kvm,
             memflow,
+            remote,
             ..Default::default()
         })
     }
@@ -0,0 +1,150 @@
+//! In-memory driver shared by the unit tests
+//!
+//! Operations which are not listed here are left to the `Introspectable` defaults.
+
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::error::Error;
+use std::rc::Rc;
+
+use crate::api::events::{Event, EventReplyType, InterceptType};
+use crate::api::registers::{Registers, X86Registers};
+use crate::api::{Access, DriverType, Introspectable};
+
+pub struct FakeDriver {
+    pub driver_type: DriverType,
+    pub memory: RefCell<Vec<u8>>,
+    /// writes are refused when set
+    pub read_only: bool,
+    pub rip: Cell<u64>,
+    /// page access by physical address, RWX when missing
+    pub page_access: RefCell<HashMap<u64, Access>>,
+    /// singlestep toggles, as (vcpu, enabled)
+    pub singlestep: Vec<(u16, bool)>,
+    pub no_singlestep: bool,
+    pub replies: Vec<(u16, EventReplyType)>,
+    // shared with the test, to be checked through trait objects
+    pub pause_count: Rc<Cell<u32>>,
+    pub resumes: Rc<Cell<u32>>,
+}
+
+impl FakeDriver {
+    pub fn new(memory: Vec<u8>) -> Self {
+        FakeDriver {
+            memory: RefCell::new(memory),
+            ..Default::default()
+        }
+    }
+}
+
+impl Default for FakeDriver {
+    fn default() -> Self {
+        FakeDriver {
+            driver_type: DriverType::KVM,
+            memory: RefCell::default(),
+            read_only: false,
+            rip: Cell::default(),
+            page_access: RefCell::default(),
+            singlestep: Vec::new(),
+            no_singlestep: false,
+            replies: Vec::new(),
+            pause_count: Rc::default(),
+            resumes: Rc::default(),
+        }
+    }
+}
+
+impl Introspectable for FakeDriver {
+    fn read_physical(
+        &self,
+        paddr: u64,
+        buf: &mut [u8],
+        bytes_read: &mut u64,
+    ) -> Result<(), Box<dyn Error>> {
+        let start = paddr as usize;
+        let memory = self.memory.borrow();
+        let data = memory
+            .get(start..start + buf.len())
+            .ok_or("out of bounds")?;
+        buf.copy_from_slice(data);
+        *bytes_read = buf.len() as u64;
+        Ok(())
+    }
+
+    fn write_physical(&self, paddr: u64, buf: &[u8]) -> Result<(), Box<dyn Error>> {
+        if self.read_only {
+            return Err("read only".into());
+        }
+        let start = paddr as usize;
+        let mut memory = self.memory.borrow_mut();
+        let data = memory
+            .get_mut(start..start + buf.len())
+            .ok_or("out of bounds")?;
+        data.copy_from_slice(buf);
+        Ok(())
+    }
+
+    fn read_registers(&self, _vcpu: u16) -> Result<Registers, Box<dyn Error>> {
+        Ok(Registers::X86(X86Registers {
+            rip: self.rip.get(),
+            ..Default::default()
+        }))
+    }
+
+    fn write_registers(&self, _vcpu: u16, reg: Registers) -> Result<(), Box<dyn Error>> {
+        let Registers::X86(x86) = reg;
+        self.rip.set(x86.rip);
+        Ok(())
+    }
+
+    fn get_page_access(&self, paddr: u64) -> Result<Access, Box<dyn Error>> {
+        Ok(*self
+            .page_access
+            .borrow()
+            .get(&paddr)
+            .unwrap_or(&Access::RWX))
+    }
+
+    fn set_page_access(&self, paddr: u64, access: Access) -> Result<(), Box<dyn Error>> {
+        self.page_access.borrow_mut().insert(paddr, access);
+        Ok(())
+    }
+
+    fn pause(&mut self) -> Result<(), Box<dyn Error>> {
+        self.pause_count.set(self.pause_count.get() + 1);
+        Ok(())
+    }
+
+    fn resume(&mut self) -> Result<(), Box<dyn Error>> {
+        self.pause_count.set(self.pause_count.get() - 1);
+        self.resumes.set(self.resumes.get() + 1);
+        Ok(())
+    }
+
+    fn toggle_intercept(
+        &mut self,
+        vcpu: u16,
+        intercept_type: InterceptType,
+        enabled: bool,
+    ) -> Result<(), Box<dyn Error>> {
+        assert!(matches!(intercept_type, InterceptType::Singlestep));
+        if self.no_singlestep {
+            return Err("singlestep is not supported".into());
+        }
+        self.singlestep.push((vcpu, enabled));
+        Ok(())
+    }
+
+    fn reply_event(
+        &mut self,
+        event: Event,
+        reply_type: EventReplyType,
+    ) -> Result<(), Box<dyn Error>> {
+        self.replies.push((event.vcpu, reply_type));
+        Ok(())
+    }
+
+    fn get_driver_type(&self) -> DriverType {
+        self.driver_type
+    }
+}
@@ -1,6 +1,7 @@
 use std::convert::TryFrom;
 use std::convert::TryInto;
 use std::error::Error;
+use std::path::Path;
 
 use kvmi::constants::PAGE_SIZE;
 #[cfg(test)] // only needed for tests
@@ -11,9 +12,14 @@ use kvmi::{
 };
 
 use crate::api::events::{CrType, Event, EventReplyType, EventType, InterceptType};
-use crate::api::params::{DriverInitParams, KVMInitParams};
+use crate::api::params::{DriverInitParams, KVMInitParams, VMADDR_CID_ANY};
 use crate::api::registers::{Registers, SegmentReg, SystemTableReg, X86Registers};
+use crate::api::vms::VmInfo;
 use crate::api::{Access, DriverType, Introspectable};
+use crate::driver::pause::PauseCounter;
+use crate::driver::qemu::list_qemu_vms;
+use crate::driver::state::GuestState;
+use crate::errors::InitFailureReason;
 
 impl TryFrom<Access> for KVMiPageAccess {
     type Error = &'static str;
@@ -94,9 +100,10 @@ impl From<X86Registers> for kvm_regs {
 #[derive(Debug)]
 pub struct Kvm<T: KVMIntrospectable> {
     kvmi: T,
-    expect_pause_ev: u32,
+    pause_count: PauseCounter,
     // VCPU -> KVMiEvent
     vec_events: Vec<Option<KVMiEvent>>,
+    state: GuestState,
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -105,42 +112,124 @@ pub enum KVMDriverError {
     MissingVMName,
     #[error("KVM driver initialization requires an additional socket parameter")]
     MissingSocketParameter,
+    #[error("KVMi transport over {0} is not supported by the kvmi library")]
+    UnsupportedTransport(&'static str),
+    #[error("intercept {0:?} is not supported by the KVM driver")]
+    UnsupportedIntercept(InterceptType),
+    #[error("event reply {0} is not supported by the KVM driver for this event")]
+    UnsupportedReply(String),
+    #[error("registers {0:?} can't be set by an event reply on the KVM driver")]
+    UnsupportedRegisters(Vec<&'static str>),
+    #[error("no pending event on VCPU {0}")]
+    NoPendingEvent(u16),
+    #[error("{0} is not supported by the KVM driver")]
+    UnsupportedOperation(&'static str),
+}
+
+impl KVMDriverError {
+    pub(crate) fn init_failure_reason(&self) -> InitFailureReason {
+        match self {
+            KVMDriverError::MissingVMName
+            | KVMDriverError::MissingSocketParameter
+            | KVMDriverError::UnsupportedTransport(_) => InitFailureReason::MissingParameter,
+            _ => InitFailureReason::Other,
+        }
+    }
+}
+
+const KVM_DEVICE: &str = "/dev/kvm";
+
+/// Whether the KVM device is present
+pub fn probe() -> bool {
+    Path::new(KVM_DEVICE).exists()
+}
+
+/// List the named QEMU processes
+///
+/// The KVMi socket of each VM is not discovered.
+pub fn list_vms() -> Result<Vec<VmInfo>, Box<dyn Error>> {
+    Ok(list_qemu_vms()?)
+}
+
+/// Map the KVMi transport to the kvmi socket type
+fn socket_type(params: KVMInitParams) -> Result<SocketType, KVMDriverError> {
+    match params {
+        KVMInitParams::UnixSocket { path } => Ok(SocketType::UnixSocket(path)),
+        // libkvmi listens on any CID
+        KVMInitParams::Vsock {
+            cid: VMADDR_CID_ANY,
+            port,
+        } => Ok(SocketType::VSock(port)),
+        KVMInitParams::Vsock { .. } => Err(KVMDriverError::UnsupportedTransport(
+            "vsock on a specific CID",
+        )),
+    }
 }
 
 impl<T: KVMIntrospectable> Kvm<T> {
     pub fn new(mut kvmi: T, init_params: DriverInitParams) -> Result<Self, Box<dyn Error>> {
-        let domain_name = init_params
-            .common
-            .ok_or(KVMDriverError::MissingVMName)?
-            .vm_name;
-        let KVMInitParams::UnixSocket { path } = init_params
+        let common = init_params.common.ok_or(KVMDriverError::MissingVMName)?;
+        let domain_name = common.vm_name;
+        let transport = init_params
             .kvm
             .ok_or(KVMDriverError::MissingSocketParameter)?;
-        debug!("init on {} (socket: {})", domain_name, path);
-        let unix_socket = SocketType::UnixSocket(path);
-        kvmi.init(unix_socket)?;
+        debug!("init on {} (transport: {:?})", domain_name, transport);
+        kvmi.init(socket_type(transport)?)?;
         let mut kvm = Kvm {
             kvmi,
-            expect_pause_ev: 0,
+            pause_count: PauseCounter::default(),
             vec_events: Vec::new(),
+            state: GuestState::default(),
         };
 
         // set vec_events size
         let vcpu_count = kvm.get_vcpu_count()?;
         kvm.vec_events.resize_with(vcpu_count.into(), || None);
+        kvm.state = GuestState::new(vcpu_count);
 
         // enable CR event intercept by default
         // (interception will take place when CR register will be specified)
-        for vcpu in 0..vcpu_count {
-            kvm.kvmi.control_events(vcpu, KVMiInterceptType::Cr, true)?;
-            kvm.kvmi
-                .control_events(vcpu, KVMiInterceptType::Msr, true)?;
-            kvm.kvmi
-                .control_events(vcpu, KVMiInterceptType::Pagefault, true)?;
+        // no event can be replied to in read-only mode
+        if !common.read_only {
+            for vcpu in 0..vcpu_count {
+                kvm.kvmi.control_events(vcpu, KVMiInterceptType::Cr, true)?;
+                kvm.kvmi
+                    .control_events(vcpu, KVMiInterceptType::Msr, true)?;
+                kvm.kvmi
+                    .control_events(vcpu, KVMiInterceptType::Pagefault, true)?;
+            }
         }
 
         Ok(kvm)
     }
+
+    fn set_kvmi_page_access_range(
+        &self,
+        start: u64,
+        end: u64,
+        access: KVMiPageAccess,
+        view: u16,
+    ) -> Result<(), Box<dyn Error>> {
+        // libkvmi's kvmi_set_page_access takes an array of pages, but the kvmi crate always
+        // passes a single one and keeps the libkvmi handle private: one command per page
+        let first_page = start & !(PAGE_SIZE as u64 - 1);
+        for paddr in (first_page..end).step_by(PAGE_SIZE) {
+            self.kvmi.set_page_access(paddr, access, view)?;
+        }
+        Ok(())
+    }
+
+    // KVMi has no command to read the page access: the pages are unrestricted
+    // until the introspector changes them
+    fn record_original_access(
+        &self,
+        view: u16,
+        start: u64,
+        end: u64,
+    ) -> Result<(), Box<dyn Error>> {
+        self.state
+            .record_original_access(view, start, end, |_| Ok(Access::RWX))
+    }
 }
 
 impl<T: KVMIntrospectable> Introspectable for Kvm<T> {
@@ -232,27 +321,78 @@ impl<T: KVMIntrospectable> Introspectable for Kvm<T> {
     }
 
     fn set_page_access(&self, paddr: u64, access: Access) -> Result<(), Box<dyn Error>> {
+        self.record_original_access(0, paddr, paddr + 1)?;
         self.kvmi.set_page_access(paddr, access.try_into()?, 0)?;
         Ok(())
     }
 
-    fn pause(&mut self) -> Result<(), Box<dyn Error>> {
-        debug!("pause");
-        // already paused ?
-        if self.expect_pause_ev > 0 {
-            return Ok(());
+    fn set_page_access_range(
+        &self,
+        start: u64,
+        end: u64,
+        access: Access,
+    ) -> Result<(), Box<dyn Error>> {
+        self.record_original_access(0, start, end)?;
+        self.set_kvmi_page_access_range(start, end, access.try_into()?, 0)
+    }
+
+    // one command per page, see set_kvmi_page_access_range
+    fn set_page_access_multi(&self, pages: &[(u64, Access)]) -> Result<(), Box<dyn Error>> {
+        for (paddr, access) in pages {
+            self.record_original_access(0, *paddr, *paddr + 1)?;
+            self.kvmi
+                .set_page_access(*paddr, (*access).try_into()?, 0)?;
         }
+        Ok(())
+    }
+
+    // the kvmi crate only exposes the view of set_page_access,
+    // view management commands are not available yet
+    fn create_view(&mut self) -> Result<u16, Box<dyn Error>> {
+        Err(Box::new(KVMDriverError::UnsupportedOperation(
+            "view creation",
+        )))
+    }
+
+    fn destroy_view(&mut self, _view: u16) -> Result<(), Box<dyn Error>> {
+        Err(Box::new(KVMDriverError::UnsupportedOperation(
+            "view destruction",
+        )))
+    }
+
+    fn switch_view(&mut self, _vcpu: u16, _view: u16) -> Result<(), Box<dyn Error>> {
+        Err(Box::new(KVMDriverError::UnsupportedOperation(
+            "view switching",
+        )))
+    }
 
-        self.kvmi.pause()?;
-        self.expect_pause_ev = self.kvmi.get_vcpu_count()?;
-        debug!("expected pause events: {}", self.expect_pause_ev);
+    fn set_page_access_in_view(
+        &self,
+        view: u16,
+        paddr: u64,
+        access: Access,
+    ) -> Result<(), Box<dyn Error>> {
+        self.record_original_access(view, paddr, paddr + 1)?;
+        self.kvmi.set_page_access(paddr, access.try_into()?, view)?;
         Ok(())
     }
 
+    fn remap_gfn(&mut self, _view: u16, _from: u64, _to: u64) -> Result<(), Box<dyn Error>> {
+        Err(Box::new(KVMDriverError::UnsupportedOperation(
+            "GFN remapping",
+        )))
+    }
+
+    fn pause(&mut self) -> Result<(), Box<dyn Error>> {
+        debug!("pause");
+        let kvmi = &self.kvmi;
+        Ok(self.pause_count.pause(|| kvmi.pause())?)
+    }
+
     fn resume(&mut self) -> Result<(), Box<dyn Error>> {
         debug!("resume");
-        self.kvmi.resume()?;
-        Ok(())
+        let kvmi = &mut self.kvmi;
+        Ok(self.pause_count.resume(|| kvmi.resume())?)
     }
 
     fn toggle_intercept(
@@ -268,22 +408,56 @@ impl<T: KVMIntrospectable> Introspectable for Kvm<T> {
                     CrType::Cr3 => KVMiCr::Cr3,
                     CrType::Cr4 => KVMiCr::Cr4,
                 };
-                Ok(self.kvmi.control_cr(vcpu, kvmi_cr, enabled)?)
+                self.kvmi.control_cr(vcpu, kvmi_cr, enabled)?
             }
             InterceptType::Msr(micro_msr_type) => {
-                Ok(self.kvmi.control_msr(vcpu, micro_msr_type, enabled)?)
+                self.kvmi.control_msr(vcpu, micro_msr_type, enabled)?
             }
             InterceptType::Breakpoint => {
-                Ok(self
-                    .kvmi
-                    .control_events(vcpu, KVMiInterceptType::Breakpoint, enabled)?)
+                self.kvmi
+                    .control_events(vcpu, KVMiInterceptType::Breakpoint, enabled)?
             }
             InterceptType::Pagefault => {
-                Ok(self
-                    .kvmi
-                    .control_events(vcpu, KVMiInterceptType::Pagefault, enabled)?)
+                self.kvmi
+                    .control_events(vcpu, KVMiInterceptType::Pagefault, enabled)?
+            }
+            // the corresponding KVMi events are not exposed by the kvmi crate yet
+            InterceptType::Cpuid
+            | InterceptType::Descriptor
+            | InterceptType::Xsetbv
+            | InterceptType::Io
+            | InterceptType::Hypercall
+            | InterceptType::Singlestep => {
+                return Err(Box::new(KVMDriverError::UnsupportedIntercept(
+                    intercept_type,
+                )))
+            }
+        }
+        self.state.set_intercept(vcpu, intercept_type, enabled);
+        Ok(())
+    }
+
+    fn get_active_intercepts(&self, vcpu: u16) -> Result<Vec<InterceptType>, Box<dyn Error>> {
+        Ok(self.state.active_intercepts(vcpu))
+    }
+
+    fn restore_guest_state(&mut self) -> Result<(), Box<dyn Error>> {
+        debug!("restore guest state");
+        for vcpu in 0..self.get_vcpu_count()? {
+            for intercept_type in self.state.active_intercepts(vcpu) {
+                self.toggle_intercept(vcpu, intercept_type, false)?;
             }
         }
+        for (view, start, end, access) in self.state.take_original_access() {
+            self.set_kvmi_page_access_range(start, end, access.try_into()?, view)?;
+        }
+        // resume the VCPUs waiting for a reply
+        for kvmi_event_opt in self.vec_events.iter_mut() {
+            if let Some(kvmi_event) = kvmi_event_opt.take() {
+                self.kvmi.reply(&kvmi_event, KVMiEventReply::Continue)?;
+            }
+        }
+        Ok(())
     }
 
     fn listen(&mut self, timeout: u32) -> Result<Option<Event>, Box<dyn Error>> {
@@ -311,10 +485,11 @@ impl<T: KVMIntrospectable> Introspectable for Kvm<T> {
                         gpa,
                         insn_len,
                     },
-                    KVMiEventType::Pagefault {gva, gpa, access, view: _} =>  EventType::Pagefault {
+                    KVMiEventType::Pagefault {gva, gpa, access, view} =>  EventType::Pagefault {
                         gva,
                         gpa,
                         access: access.into(),
+                        view,
                     },
                     KVMiEventType::PauseVCPU => panic!("Unexpected PauseVCPU event. It should have been popped by resume VM. (Did you forget to resume your VM ?)"),
                 };
@@ -336,13 +511,66 @@ impl<T: KVMIntrospectable> Introspectable for Kvm<T> {
         event: Event,
         reply_type: EventReplyType,
     ) -> Result<(), Box<dyn Error>> {
-        let kvm_reply_type = match reply_type {
-            EventReplyType::Continue => KVMiEventReply::Continue,
-        };
         // get KVMiEvent associated with this VCPU
         let vcpu_index: usize = event.vcpu.into();
-        let kvmi_event = &mut self.vec_events[vcpu_index].take().unwrap();
-        Ok(self.kvmi.reply(kvmi_event, kvm_reply_type)?)
+        let ev_type = self.vec_events[vcpu_index]
+            .as_ref()
+            .ok_or(KVMDriverError::NoPendingEvent(event.vcpu))?
+            .ev_type;
+        // KVMi semantics:
+        // - Continue: default action (breakpoint reinjected, faulting instruction emulated)
+        // - Retry: reenter the guest and execute the current instruction again
+        let kvm_reply_type = match (reply_type, ev_type) {
+            (EventReplyType::Continue, _) => KVMiEventReply::Continue,
+            (EventReplyType::Retry, _) => KVMiEventReply::Retry,
+            (EventReplyType::Reinject, KVMiEventType::Breakpoint { .. }) => {
+                KVMiEventReply::Continue
+            }
+            (EventReplyType::Emulate, KVMiEventType::Pagefault { .. }) => KVMiEventReply::Continue,
+            (EventReplyType::SkipInstruction, KVMiEventType::Breakpoint { insn_len, .. }) => {
+                let (mut regs, _, _) = self.kvmi.get_registers(event.vcpu)?;
+                regs.rip += u64::from(insn_len);
+                self.kvmi.set_registers(event.vcpu, &regs)?;
+                KVMiEventReply::Retry
+            }
+            (EventReplyType::SetRegisters(Registers::X86(regs)), ev_type) => {
+                // only the general purpose registers, RIP and RFLAGS are applied by KVMi
+                let Registers::X86(current) = self.read_registers(event.vcpu)?;
+                let unapplied = regs.unapplied_on_reply(&current);
+                if !unapplied.is_empty() {
+                    return Err(Box::new(KVMDriverError::UnsupportedRegisters(unapplied)));
+                }
+                self.kvmi.set_registers(event.vcpu, &regs.into())?;
+                match ev_type {
+                    // Continue would reinject / emulate
+                    KVMiEventType::Breakpoint { .. } | KVMiEventType::Pagefault { .. } => {
+                        KVMiEventReply::Retry
+                    }
+                    _ => KVMiEventReply::Continue,
+                }
+            }
+            (reply_type, _) => {
+                return Err(Box::new(KVMDriverError::UnsupportedReply(format!(
+                    "{:?}",
+                    reply_type
+                ))))
+            }
+        };
+        let kvmi_event = self.vec_events[vcpu_index].take().unwrap();
+        Ok(self.kvmi.reply(&kvmi_event, kvm_reply_type)?)
+    }
+
+    // KVMI_VCPU_INJECT_EXCEPTION is not exposed by the kvmi crate yet
+    fn inject_interrupt(
+        &mut self,
+        _vcpu: u16,
+        _vector: u8,
+        _error_code: Option<u32>,
+        _cr2: u64,
+    ) -> Result<(), Box<dyn Error>> {
+        Err(Box::new(KVMDriverError::UnsupportedOperation(
+            "interrupt injection",
+        )))
     }
 
     fn get_driver_type(&self) -> DriverType {
@@ -353,17 +581,30 @@ impl<T: KVMIntrospectable> Introspectable for Kvm<T> {
 impl<T: KVMIntrospectable> Drop for Kvm<T> {
     fn drop(&mut self) {
         debug!("KVM driver close");
+        if let Err(e) = self.restore_guest_state() {
+            error!("Failed to restore guest state: {}", e);
+        }
         // disable all control register interception
-        for vcpu in 0..self.get_vcpu_count().unwrap() {
-            self.kvmi
-                .control_events(vcpu, KVMiInterceptType::Cr, false)
-                .unwrap();
-            self.kvmi
-                .control_events(vcpu, KVMiInterceptType::Msr, false)
-                .unwrap();
-            self.kvmi
-                .control_events(vcpu, KVMiInterceptType::Pagefault, false)
-                .unwrap();
+        let vcpu_count = match self.get_vcpu_count() {
+            Ok(vcpu_count) => vcpu_count,
+            Err(e) => {
+                error!("Failed to get VCPU count: {}", e);
+                return;
+            }
+        };
+        for vcpu in 0..vcpu_count {
+            for intercept_type in [
+                KVMiInterceptType::Cr,
+                KVMiInterceptType::Msr,
+                KVMiInterceptType::Pagefault,
+            ] {
+                if let Err(e) = self.kvmi.control_events(vcpu, intercept_type, false) {
+                    error!(
+                        "Failed to disable {:?} events on VCPU {}: {}",
+                        intercept_type, vcpu, e
+                    );
+                }
+            }
         }
     }
 }
@@ -374,9 +615,10 @@ mod tests {
 
     use kvmi::{kvm_regs, kvm_sregs, KvmMsrs};
     use mockall::mock;
-    use mockall::predicate::{eq, function};
+    use mockall::predicate::{always, eq, function};
     use test_case::test_case;
 
+    use crate::api::breakpoints::{BreakpointError, BreakpointManager};
     use crate::api::params::CommonInitParams;
 
     use super::*;
@@ -384,18 +626,17 @@ mod tests {
     #[test]
     fn test_fail_to_create_kvm_driver_if_kvmi_init_returns_error() {
         let mut kvmi_mock = MockKVMi::default();
-        kvmi_mock.expect_init().returning(|_| {
-            Err(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                "something went wrong",
-            ))
-        });
+        kvmi_mock
+            .expect_init()
+            .returning(|_| Err(std::io::Error::other("something went wrong")));
 
         let result = Kvm::new(
             kvmi_mock,
             DriverInitParams {
                 common: Some(CommonInitParams {
                     vm_name: String::from("some_vm"),
+                    read_only: false,
+                    audit_log: None,
                 }),
                 kvm: Some(KVMInitParams::UnixSocket {
                     path: "/tmp/introspector".to_string(),
@@ -407,6 +648,40 @@ mod tests {
         assert!(result.is_err(), "Expected error, got ok instead!");
     }
 
+    #[test]
+    fn test_vsock_transport_maps_to_kvmi_vsock() {
+        let socket = socket_type(KVMInitParams::Vsock {
+            cid: VMADDR_CID_ANY,
+            port: 1234,
+        });
+
+        assert!(matches!(socket, Ok(SocketType::VSock(1234))));
+    }
+
+    #[test]
+    fn test_unsupported_transport_is_refused_before_kvmi_init() {
+        let mut kvmi_mock = MockKVMi::default();
+        kvmi_mock.expect_init().times(0);
+
+        let result = Kvm::new(
+            kvmi_mock,
+            DriverInitParams {
+                common: Some(CommonInitParams {
+                    vm_name: String::from("some_vm"),
+                    read_only: false,
+                    audit_log: None,
+                }),
+                kvm: Some(KVMInitParams::Vsock { cid: 3, port: 1234 }),
+                ..Default::default()
+            },
+        );
+
+        assert!(matches!(
+            result.err().unwrap().downcast_ref::<KVMDriverError>(),
+            Some(KVMDriverError::UnsupportedTransport(_))
+        ));
+    }
+
     #[test_case(1; "single vcpu")]
     #[test_case(2; "two vcpus")]
     #[test_case(16; "sixteen vcpus")]
@@ -478,6 +753,8 @@ mod tests {
             DriverInitParams {
                 common: Some(CommonInitParams {
                     vm_name: String::from("some_vm"),
+                    read_only: false,
+                    audit_log: None,
                 }),
                 kvm: Some(KVMInitParams::UnixSocket {
                     path: "/tmp/introspector".to_string(),
@@ -489,6 +766,383 @@ mod tests {
         assert!(result.is_ok(), "Expected ok, got error instead!");
     }
 
+    #[test_case(InterceptType::Cpuid; "cpuid")]
+    #[test_case(InterceptType::Descriptor; "descriptor")]
+    #[test_case(InterceptType::Xsetbv; "xsetbv")]
+    #[test_case(InterceptType::Io; "io")]
+    #[test_case(InterceptType::Hypercall; "hypercall")]
+    #[test_case(InterceptType::Singlestep; "singlestep")]
+    fn test_toggle_unsupported_intercept_returns_error(intercept_type: InterceptType) {
+        let mut kvmi_mock = MockKVMi::default();
+        kvmi_mock.expect_get_vcpu_count().returning(|| Ok(0));
+        let mut kvm = Kvm {
+            kvmi: kvmi_mock,
+            pause_count: PauseCounter::default(),
+            vec_events: Vec::new(),
+            state: GuestState::default(),
+        };
+
+        let result = kvm.toggle_intercept(0, intercept_type, true);
+
+        assert!(result.is_err(), "Expected error, got ok instead!");
+    }
+
+    #[test]
+    fn test_nested_pause_resumes_once() {
+        let mut kvmi_mock = MockKVMi::default();
+        kvmi_mock.expect_get_vcpu_count().returning(|| Ok(0));
+        kvmi_mock.expect_pause().times(1).returning(|| Ok(()));
+        let mut kvm = Kvm {
+            kvmi: kvmi_mock,
+            pause_count: PauseCounter::default(),
+            vec_events: Vec::new(),
+            state: GuestState::default(),
+        };
+
+        kvm.pause().unwrap();
+        kvm.pause().unwrap();
+        kvm.resume().unwrap();
+        kvm.kvmi.checkpoint();
+        kvm.kvmi.expect_get_vcpu_count().returning(|| Ok(0));
+        kvm.kvmi.expect_resume().times(1).returning(|| Ok(()));
+        kvm.resume().unwrap();
+
+        assert_eq!(kvm.pause_count.count(), 0);
+    }
+
+    #[test]
+    fn test_toggle_intercept_updates_active_intercepts() {
+        let mut kvmi_mock = MockKVMi::default();
+        kvmi_mock.expect_get_vcpu_count().returning(|| Ok(0));
+        kvmi_mock
+            .expect_control_msr()
+            .with(eq(1), eq(0xc0000082), always())
+            .times(2)
+            .returning(|_, _, _| Ok(()));
+        let mut kvm = Kvm {
+            kvmi: kvmi_mock,
+            pause_count: PauseCounter::default(),
+            vec_events: Vec::new(),
+            state: GuestState::new(2),
+        };
+
+        kvm.toggle_intercept(1, InterceptType::Msr(0xc0000082), true)
+            .unwrap();
+
+        assert!(kvm.get_active_intercepts(0).unwrap().is_empty());
+        assert_eq!(
+            kvm.get_active_intercepts(1).unwrap(),
+            vec![InterceptType::Msr(0xc0000082)]
+        );
+
+        kvm.toggle_intercept(1, InterceptType::Msr(0xc0000082), false)
+            .unwrap();
+
+        assert!(kvm.get_active_intercepts(1).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_set_page_access_range_sets_every_page() {
+        let mut kvmi_mock = MockKVMi::default();
+        kvmi_mock.expect_get_vcpu_count().returning(|| Ok(0));
+        for paddr in &[0x1000, 0x2000, 0x3000] {
+            kvmi_mock
+                .expect_set_page_access()
+                .with(
+                    eq(*paddr),
+                    function(|x| matches!(x, KVMiPageAccess::RW)),
+                    eq(0),
+                )
+                .times(1)
+                .returning(|_, _, _| Ok(()));
+        }
+        // access restored on drop
+        kvmi_mock
+            .expect_set_page_access()
+            .with(
+                always(),
+                function(|x| matches!(x, KVMiPageAccess::RWX)),
+                eq(0),
+            )
+            .times(3)
+            .returning(|_, _, _| Ok(()));
+        let kvm = Kvm {
+            kvmi: kvmi_mock,
+            pause_count: PauseCounter::default(),
+            vec_events: Vec::new(),
+            state: GuestState::default(),
+        };
+
+        let result = kvm.set_page_access_range(0x1234, 0x3001, Access::RW);
+
+        assert!(result.is_ok(), "Expected ok, got error instead!");
+    }
+
+    #[test]
+    fn test_set_page_access_in_view_forwards_view() {
+        let mut kvmi_mock = MockKVMi::default();
+        kvmi_mock.expect_get_vcpu_count().returning(|| Ok(0));
+        kvmi_mock
+            .expect_set_page_access()
+            .with(
+                eq(0x1000),
+                function(|x| matches!(x, KVMiPageAccess::RX)),
+                eq(2),
+            )
+            .times(1)
+            .returning(|_, _, _| Ok(()));
+        // access restored on drop
+        kvmi_mock
+            .expect_set_page_access()
+            .with(
+                eq(0x1000),
+                function(|x| matches!(x, KVMiPageAccess::RWX)),
+                eq(2),
+            )
+            .times(1)
+            .returning(|_, _, _| Ok(()));
+        let kvm = Kvm {
+            kvmi: kvmi_mock,
+            pause_count: PauseCounter::default(),
+            vec_events: Vec::new(),
+            state: GuestState::default(),
+        };
+
+        let result = kvm.set_page_access_in_view(2, 0x1000, Access::RX);
+
+        assert!(result.is_ok(), "Expected ok, got error instead!");
+    }
+
+    #[test]
+    fn test_restore_guest_state_reverts_changes() {
+        let mut kvmi_mock = MockKVMi::default();
+        kvmi_mock.expect_get_vcpu_count().returning(|| Ok(1));
+        kvmi_mock
+            .expect_control_msr()
+            .with(eq(0), eq(0xc0000082), always())
+            .times(2)
+            .returning(|_, _, _| Ok(()));
+        kvmi_mock
+            .expect_set_page_access()
+            .with(
+                eq(0x1000),
+                function(|x| matches!(x, KVMiPageAccess::RW)),
+                eq(0),
+            )
+            .times(1)
+            .returning(|_, _, _| Ok(()));
+        kvmi_mock
+            .expect_set_page_access()
+            .with(
+                eq(0x1000),
+                function(|x| matches!(x, KVMiPageAccess::RWX)),
+                eq(0),
+            )
+            .times(1)
+            .returning(|_, _, _| Ok(()));
+        kvmi_mock
+            .expect_reply()
+            .withf(|_, reply| matches!(reply, KVMiEventReply::Continue))
+            .times(1)
+            .returning(|_, _| Ok(()));
+        let mut kvm = kvm_with_breakpoint_event(kvmi_mock);
+        kvm.toggle_intercept(0, InterceptType::Msr(0xc0000082), true)
+            .unwrap();
+        kvm.set_page_access(0x1000, Access::RW).unwrap();
+
+        kvm.restore_guest_state().unwrap();
+
+        assert!(kvm.get_active_intercepts(0).unwrap().is_empty());
+        assert!(kvm.vec_events[0].is_none());
+        // dropping the driver doesn't revert anything else
+        kvm.kvmi.checkpoint();
+        kvm.kvmi.expect_get_vcpu_count().returning(|| Ok(1));
+        kvm.kvmi.expect_control_events().returning(|_, _, _| Ok(()));
+    }
+
+    fn kvm_with_breakpoint_event(kvmi_mock: MockKVMi) -> Kvm<MockKVMi> {
+        Kvm {
+            kvmi: kvmi_mock,
+            pause_count: PauseCounter::default(),
+            vec_events: vec![Some(KVMiEvent {
+                vcpu: 0,
+                ev_type: KVMiEventType::Breakpoint {
+                    gpa: 0x1000,
+                    insn_len: 1,
+                },
+                ffi_event: std::ptr::null_mut(),
+            })],
+            state: GuestState::default(),
+        }
+    }
+
+    fn breakpoint_event() -> Event {
+        Event {
+            vcpu: 0,
+            kind: EventType::Breakpoint {
+                gpa: 0x1000,
+                insn_len: 1,
+            },
+        }
+    }
+
+    #[test]
+    fn test_reply_skip_instruction_moves_rip_past_breakpoint() {
+        let mut kvmi_mock = MockKVMi::default();
+        kvmi_mock.expect_get_vcpu_count().returning(|| Ok(0));
+        kvmi_mock.expect_get_registers().with(eq(0)).returning(|_| {
+            let regs = kvm_regs {
+                rip: 0x4000,
+                ..Default::default()
+            };
+            Ok((regs, kvm_sregs::default(), KvmMsrs::new()))
+        });
+        kvmi_mock
+            .expect_set_registers()
+            .with(eq(0), function(|regs: &kvm_regs| regs.rip == 0x4001))
+            .times(1)
+            .returning(|_, _| Ok(()));
+        kvmi_mock
+            .expect_reply()
+            .withf(|_, reply| matches!(reply, KVMiEventReply::Retry))
+            .times(1)
+            .returning(|_, _| Ok(()));
+        let mut kvm = kvm_with_breakpoint_event(kvmi_mock);
+
+        let result = kvm.reply_event(breakpoint_event(), EventReplyType::SkipInstruction);
+
+        assert!(result.is_ok(), "Expected ok, got error instead!");
+        assert!(kvm.vec_events[0].is_none());
+    }
+
+    #[test]
+    fn test_reply_emulate_on_breakpoint_returns_error() {
+        let mut kvmi_mock = MockKVMi::default();
+        kvmi_mock.expect_get_vcpu_count().returning(|| Ok(0));
+        // only replied when the driver is dropped
+        kvmi_mock
+            .expect_reply()
+            .withf(|_, reply| matches!(reply, KVMiEventReply::Continue))
+            .times(1)
+            .returning(|_, _| Ok(()));
+        let mut kvm = kvm_with_breakpoint_event(kvmi_mock);
+
+        let result = kvm.reply_event(breakpoint_event(), EventReplyType::Emulate);
+
+        assert!(result.is_err(), "Expected error, got ok instead!");
+        // the event is still pending and can be replied to
+        assert!(kvm.vec_events[0].is_some());
+    }
+
+    #[test]
+    fn test_reply_set_registers_applies_general_purpose_registers() {
+        let mut kvmi_mock = MockKVMi::default();
+        kvmi_mock.expect_get_vcpu_count().returning(|| Ok(0));
+        kvmi_mock
+            .expect_get_registers()
+            .with(eq(0))
+            .returning(|_| Ok((kvm_regs::default(), kvm_sregs::default(), KvmMsrs::new())));
+        kvmi_mock
+            .expect_set_registers()
+            .with(eq(0), function(|regs: &kvm_regs| regs.rax == 1))
+            .times(1)
+            .returning(|_, _| Ok(()));
+        kvmi_mock
+            .expect_reply()
+            .withf(|_, reply| matches!(reply, KVMiEventReply::Retry))
+            .times(1)
+            .returning(|_, _| Ok(()));
+        let mut kvm = kvm_with_breakpoint_event(kvmi_mock);
+        let regs = X86Registers {
+            rax: 1,
+            ..Default::default()
+        };
+
+        let result = kvm.reply_event(
+            breakpoint_event(),
+            EventReplyType::SetRegisters(Registers::X86(regs)),
+        );
+
+        assert!(result.is_ok(), "Expected ok, got error instead!");
+    }
+
+    #[test]
+    fn test_reply_set_registers_refuses_control_registers() {
+        let mut kvmi_mock = MockKVMi::default();
+        kvmi_mock.expect_get_vcpu_count().returning(|| Ok(0));
+        kvmi_mock
+            .expect_get_registers()
+            .with(eq(0))
+            .returning(|_| Ok((kvm_regs::default(), kvm_sregs::default(), KvmMsrs::new())));
+        kvmi_mock.expect_set_registers().never();
+        // only replied when the driver is dropped
+        kvmi_mock
+            .expect_reply()
+            .withf(|_, reply| matches!(reply, KVMiEventReply::Continue))
+            .times(1)
+            .returning(|_, _| Ok(()));
+        let mut kvm = kvm_with_breakpoint_event(kvmi_mock);
+        let regs = X86Registers {
+            rax: 1,
+            cr3: 0x2000,
+            ..Default::default()
+        };
+
+        let result = kvm.reply_event(
+            breakpoint_event(),
+            EventReplyType::SetRegisters(Registers::X86(regs)),
+        );
+
+        let err = result.expect_err("Expected error, got ok instead!");
+        assert!(matches!(
+            err.downcast_ref::<KVMDriverError>(),
+            Some(KVMDriverError::UnsupportedRegisters(regs)) if regs == &["cr3"]
+        ));
+        assert!(kvm.vec_events[0].is_some());
+    }
+
+    #[test]
+    fn test_breakpoint_without_singlestep_is_removed_and_replied() {
+        let mut kvmi_mock = MockKVMi::default();
+        kvmi_mock.expect_get_vcpu_count().returning(|| Ok(0));
+        kvmi_mock
+            .expect_read_physical()
+            .with(eq(0x1000), always())
+            .returning(|_, buf| {
+                buf[0] = 0x90;
+                Ok(())
+            });
+        kvmi_mock
+            .expect_write_physical()
+            .with(eq(0x1000), function(|buf: &[u8]| buf == [0xCC]))
+            .times(1)
+            .returning(|_, _| Ok(()));
+        // KVMi can't singlestep: the original instruction is restored
+        kvmi_mock
+            .expect_write_physical()
+            .with(eq(0x1000), function(|buf: &[u8]| buf == [0x90]))
+            .times(1)
+            .returning(|_, _| Ok(()));
+        kvmi_mock
+            .expect_reply()
+            .withf(|_, reply| matches!(reply, KVMiEventReply::Retry))
+            .times(1)
+            .returning(|_, _| Ok(()));
+        let mut kvm = kvm_with_breakpoint_event(kvmi_mock);
+        let mut bp = BreakpointManager::new();
+        bp.insert(&kvm, 0x1000).unwrap();
+
+        let result = bp.handle_event(&mut kvm, breakpoint_event(), |_, _| Ok(()));
+
+        let err = result.expect_err("Expected error, got ok instead!");
+        assert!(matches!(
+            err.downcast_ref::<BreakpointError>(),
+            Some(BreakpointError::StepOverFailed(0x1000, _))
+        ));
+        assert!(!bp.contains(0x1000));
+        assert!(kvm.vec_events[0].is_none());
+    }
+
     mock! {
         KVMi{}
         impl Debug for KVMi {
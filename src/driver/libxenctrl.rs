@@ -0,0 +1,367 @@
+//! libxenctrl functions which are not wrapped by the xenctrl crate
+//!
+//! libxenctrl is loaded at runtime, like the xenctrl crate does, and used through a
+//! separate xc_interface handle. Each function is looked up when it is called, so that
+//! the driver still initializes with a libxenctrl which doesn't export all of them.
+
+use std::ffi::c_void;
+use std::io::Error as IoError;
+use std::os::raw::{c_int, c_uint};
+use std::ptr::{null_mut, NonNull};
+
+use libloading::{library_filename, Library, Symbol};
+use xenctrl::xenmem_access_t;
+
+use crate::driver::xen::XenDriverError;
+
+const LIBXENCTRL_BASENAME: &str = "xenctrl";
+
+// xc_interface_open
+type FnInterfaceOpen = unsafe extern "C" fn(
+    logger: *mut c_void,
+    dombuild_logger: *mut c_void,
+    open_flags: c_uint,
+) -> *mut c_void;
+// xc_interface_close
+type FnInterfaceClose = unsafe extern "C" fn(xch: *mut c_void) -> c_int;
+// xc_hvm_inject_trap
+type FnHvmInjectTrap = unsafe extern "C" fn(
+    xch: *mut c_void,
+    domid: u32,
+    vcpu: c_int,
+    vector: u8,
+    trap_type: u8,
+    error_code: u32,
+    insn_len: u8,
+    cr2: u64,
+) -> c_int;
+// xc_monitor_cpuid, xc_monitor_descriptor_access, xc_monitor_io
+type FnMonitorToggle = unsafe extern "C" fn(xch: *mut c_void, domid: u32, enable: bool) -> c_int;
+// xc_monitor_write_ctrlreg
+type FnMonitorWriteCtrlreg = unsafe extern "C" fn(
+    xch: *mut c_void,
+    domid: u32,
+    index: u16,
+    enable: bool,
+    sync: bool,
+    bitmask: u64,
+    onchangeonly: bool,
+) -> c_int;
+// xc_altp2m_set_domain_state
+type FnAltp2mSetDomainState =
+    unsafe extern "C" fn(xch: *mut c_void, domid: u32, state: bool) -> c_int;
+// xc_altp2m_create_view
+type FnAltp2mCreateView = unsafe extern "C" fn(
+    xch: *mut c_void,
+    domid: u32,
+    default_access: xenmem_access_t,
+    view_id: *mut u16,
+) -> c_int;
+// xc_altp2m_destroy_view, xc_altp2m_switch_to_view
+type FnAltp2mView = unsafe extern "C" fn(xch: *mut c_void, domid: u32, view_id: u16) -> c_int;
+// xc_altp2m_set_mem_access
+type FnAltp2mSetMemAccess = unsafe extern "C" fn(
+    xch: *mut c_void,
+    domid: u32,
+    view_id: u16,
+    gfn: u64,
+    access: xenmem_access_t,
+) -> c_int;
+// xc_altp2m_change_gfn
+type FnAltp2mChangeGfn = unsafe extern "C" fn(
+    xch: *mut c_void,
+    domid: u32,
+    view_id: u16,
+    old_gfn: u64,
+    new_gfn: u64,
+) -> c_int;
+
+/// x86 event types, as expected by xc_hvm_inject_trap
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[repr(u8)]
+pub enum TrapType {
+    ExternalInterrupt = 0,
+    Nmi = 2,
+    HardwareException = 3,
+    SoftwareException = 6,
+}
+
+/// no error code is pushed on the stack (XEN_INVALID_ERROR_CODE)
+pub const INVALID_ERROR_CODE: u32 = u32::MAX;
+
+/// A trap to inject into a VCPU
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Trap {
+    pub vector: u8,
+    pub trap_type: TrapType,
+    pub error_code: u32,
+    pub insn_len: u8,
+    pub cr2: u64,
+}
+
+impl Trap {
+    /// The trap delivering an interrupt or exception vector, as requested by inject_interrupt
+    ///
+    /// #BP and #OF are delivered as raised by a one byte int3 or into instruction at the current RIP.
+    pub fn from_vector(vector: u8, error_code: Option<u32>, cr2: u64) -> Self {
+        let (trap_type, insn_len) = match vector {
+            2 => (TrapType::Nmi, 0),
+            3 | 4 => (TrapType::SoftwareException, 1),
+            // vectors 0 to 31 are reserved for exceptions
+            0..=31 => (TrapType::HardwareException, 0),
+            _ => (TrapType::ExternalInterrupt, 0),
+        };
+        Trap {
+            vector,
+            trap_type,
+            error_code: error_code.unwrap_or(INVALID_ERROR_CODE),
+            insn_len,
+            cr2,
+        }
+    }
+
+    /// The #BP exception raised by an int3 instruction of `insn_len` bytes
+    pub fn breakpoint(insn_len: u8) -> Self {
+        Trap {
+            vector: 3,
+            trap_type: TrapType::SoftwareException,
+            error_code: INVALID_ERROR_CODE,
+            insn_len,
+            cr2: 0,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct LibXenCtrl {
+    lib: Library,
+    handle: NonNull<c_void>,
+}
+
+impl LibXenCtrl {
+    pub fn new() -> Result<Self, XenDriverError> {
+        let lib = unsafe { Library::new(library_filename(LIBXENCTRL_BASENAME))? };
+        let handle = unsafe {
+            let interface_open: Symbol<FnInterfaceOpen> = lib.get(b"xc_interface_open\0")?;
+            interface_open(null_mut(), null_mut(), 0)
+        };
+        let handle = NonNull::new(handle).ok_or_else(|| {
+            XenDriverError::XcCallError("xc_interface_open", IoError::last_os_error())
+        })?;
+        Ok(LibXenCtrl { lib, handle })
+    }
+
+    fn check(function: &'static str, rc: c_int) -> Result<(), XenDriverError> {
+        if rc < 0 {
+            return Err(XenDriverError::XcCallError(
+                function,
+                IoError::last_os_error(),
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn hvm_inject_trap(&self, domid: u32, vcpu: u16, trap: Trap) -> Result<(), XenDriverError> {
+        debug!("xc_hvm_inject_trap: {:?} on VCPU {}", trap, vcpu);
+        let rc = unsafe {
+            let hvm_inject_trap: Symbol<FnHvmInjectTrap> = self.lib.get(b"xc_hvm_inject_trap\0")?;
+            hvm_inject_trap(
+                self.handle.as_ptr(),
+                domid,
+                vcpu.into(),
+                trap.vector,
+                trap.trap_type as u8,
+                trap.error_code,
+                trap.insn_len,
+                trap.cr2,
+            )
+        };
+        LibXenCtrl::check("xc_hvm_inject_trap", rc)
+    }
+
+    // the xc_monitor_* functions which only take the enable flag
+    fn monitor_toggle(
+        &self,
+        function: &'static str,
+        domid: u32,
+        enable: bool,
+    ) -> Result<(), XenDriverError> {
+        debug!("{}: {}", function, enable);
+        let rc = unsafe {
+            let monitor_toggle: Symbol<FnMonitorToggle> = self.lib.get(function.as_bytes())?;
+            monitor_toggle(self.handle.as_ptr(), domid, enable)
+        };
+        LibXenCtrl::check(function, rc)
+    }
+
+    pub fn monitor_cpuid(&self, domid: u32, enable: bool) -> Result<(), XenDriverError> {
+        self.monitor_toggle("xc_monitor_cpuid", domid, enable)
+    }
+
+    pub fn monitor_descriptor_access(
+        &self,
+        domid: u32,
+        enable: bool,
+    ) -> Result<(), XenDriverError> {
+        self.monitor_toggle("xc_monitor_descriptor_access", domid, enable)
+    }
+
+    pub fn monitor_io(&self, domid: u32, enable: bool) -> Result<(), XenDriverError> {
+        self.monitor_toggle("xc_monitor_io", domid, enable)
+    }
+
+    /// Synchronous write events of a control register, only reported when its value changes
+    ///
+    /// The xenctrl crate doesn't accept the XCR0 index.
+    pub fn monitor_write_ctrlreg(
+        &self,
+        domid: u32,
+        index: u16,
+        enable: bool,
+    ) -> Result<(), XenDriverError> {
+        debug!("xc_monitor_write_ctrlreg: index {}, {}", index, enable);
+        let rc = unsafe {
+            let monitor_write_ctrlreg: Symbol<FnMonitorWriteCtrlreg> =
+                self.lib.get(b"xc_monitor_write_ctrlreg\0")?;
+            monitor_write_ctrlreg(self.handle.as_ptr(), domid, index, enable, true, 0, true)
+        };
+        LibXenCtrl::check("xc_monitor_write_ctrlreg", rc)
+    }
+
+    pub fn altp2m_set_domain_state(&self, domid: u32, state: bool) -> Result<(), XenDriverError> {
+        debug!("xc_altp2m_set_domain_state: {}", state);
+        let rc = unsafe {
+            let set_domain_state: Symbol<FnAltp2mSetDomainState> =
+                self.lib.get(b"xc_altp2m_set_domain_state\0")?;
+            set_domain_state(self.handle.as_ptr(), domid, state)
+        };
+        LibXenCtrl::check("xc_altp2m_set_domain_state", rc)
+    }
+
+    /// Create a view whose pages have the default access of the host p2m
+    pub fn altp2m_create_view(&self, domid: u32) -> Result<u16, XenDriverError> {
+        let mut view = 0;
+        let rc = unsafe {
+            let create_view: Symbol<FnAltp2mCreateView> =
+                self.lib.get(b"xc_altp2m_create_view\0")?;
+            create_view(
+                self.handle.as_ptr(),
+                domid,
+                xenmem_access_t::XENMEM_access_default,
+                &mut view,
+            )
+        };
+        LibXenCtrl::check("xc_altp2m_create_view", rc)?;
+        debug!("xc_altp2m_create_view: view {}", view);
+        Ok(view)
+    }
+
+    // the xc_altp2m_* functions which only take a view
+    fn altp2m_view_call(
+        &self,
+        function: &'static str,
+        domid: u32,
+        view: u16,
+    ) -> Result<(), XenDriverError> {
+        debug!("{}: view {}", function, view);
+        let rc = unsafe {
+            let view_call: Symbol<FnAltp2mView> = self.lib.get(function.as_bytes())?;
+            view_call(self.handle.as_ptr(), domid, view)
+        };
+        LibXenCtrl::check(function, rc)
+    }
+
+    pub fn altp2m_destroy_view(&self, domid: u32, view: u16) -> Result<(), XenDriverError> {
+        self.altp2m_view_call("xc_altp2m_destroy_view", domid, view)
+    }
+
+    /// Switch all the VCPUs of the domain to the view
+    pub fn altp2m_switch_to_view(&self, domid: u32, view: u16) -> Result<(), XenDriverError> {
+        self.altp2m_view_call("xc_altp2m_switch_to_view", domid, view)
+    }
+
+    pub fn altp2m_set_mem_access(
+        &self,
+        domid: u32,
+        view: u16,
+        gfn: u64,
+        access: xenmem_access_t,
+    ) -> Result<(), XenDriverError> {
+        let rc = unsafe {
+            let set_mem_access: Symbol<FnAltp2mSetMemAccess> =
+                self.lib.get(b"xc_altp2m_set_mem_access\0")?;
+            set_mem_access(self.handle.as_ptr(), domid, view, gfn, access)
+        };
+        LibXenCtrl::check("xc_altp2m_set_mem_access", rc)
+    }
+
+    pub fn altp2m_change_gfn(
+        &self,
+        domid: u32,
+        view: u16,
+        old_gfn: u64,
+        new_gfn: u64,
+    ) -> Result<(), XenDriverError> {
+        debug!(
+            "xc_altp2m_change_gfn: view {}, 0x{:x} -> 0x{:x}",
+            view, old_gfn, new_gfn
+        );
+        let rc = unsafe {
+            let change_gfn: Symbol<FnAltp2mChangeGfn> = self.lib.get(b"xc_altp2m_change_gfn\0")?;
+            change_gfn(self.handle.as_ptr(), domid, view, old_gfn, new_gfn)
+        };
+        LibXenCtrl::check("xc_altp2m_change_gfn", rc)
+    }
+}
+
+impl Drop for LibXenCtrl {
+    fn drop(&mut self) {
+        let rc = unsafe {
+            match self.lib.get::<FnInterfaceClose>(b"xc_interface_close\0") {
+                Ok(interface_close) => interface_close(self.handle.as_ptr()),
+                Err(_) => return,
+            }
+        };
+        if rc < 0 {
+            error!("Failed to close the xc_interface handle");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use super::*;
+
+    #[test_case(14, Some(2), TrapType::HardwareException, 2, 0; "page fault")]
+    #[test_case(6, None, TrapType::HardwareException, INVALID_ERROR_CODE, 0; "invalid opcode")]
+    #[test_case(2, None, TrapType::Nmi, INVALID_ERROR_CODE, 0; "nmi")]
+    #[test_case(3, None, TrapType::SoftwareException, INVALID_ERROR_CODE, 1; "breakpoint")]
+    #[test_case(4, None, TrapType::SoftwareException, INVALID_ERROR_CODE, 1; "overflow")]
+    #[test_case(0x80, None, TrapType::ExternalInterrupt, INVALID_ERROR_CODE, 0; "interrupt")]
+    fn test_trap_from_vector(
+        vector: u8,
+        error_code: Option<u32>,
+        trap_type: TrapType,
+        expected_error_code: u32,
+        insn_len: u8,
+    ) {
+        let trap = Trap::from_vector(vector, error_code, 0x1000);
+
+        assert_eq!(trap.trap_type, trap_type);
+        assert_eq!(trap.error_code, expected_error_code);
+        assert_eq!(trap.insn_len, insn_len);
+        assert_eq!(trap.cr2, 0x1000);
+    }
+
+    #[test]
+    fn test_breakpoint_trap() {
+        let trap = Trap::breakpoint(1);
+
+        assert_eq!(trap.vector, 3);
+        assert_eq!(trap.trap_type, TrapType::SoftwareException);
+        assert_eq!(trap.error_code, INVALID_ERROR_CODE);
+        assert_eq!(trap.insn_len, 1);
+    }
+}
@@ -1,11 +1,20 @@
+use crate::api::os::{ModuleInfo, ProcessInfo};
 use crate::api::params::{DriverInitParams, MemflowConnectorParams};
+use crate::api::registers::Registers;
+use crate::api::vms::VmInfo;
 use crate::api::{DriverType, Introspectable};
+use crate::driver::qemu::list_qemu_vms;
+use crate::errors::{InitFailureReason, UnsupportedOperation};
 use std::error::Error;
 
-use memflow::mem::PhysicalMemory;
-use memflow::plugins::{Args, ConnectorArgs, ConnectorInstanceArcBox, Inventory};
-use memflow::types::PhysicalAddress;
-use std::cell::RefCell;
+use memflow::error::PartialResultExt;
+use memflow::mem::{MemoryView, PhysicalMemory};
+use memflow::os::{Os, Process};
+use memflow::plugins::{
+    Args, ConnectorArgs, ConnectorInstanceArcBox, Inventory, OsArgs, OsInstanceArcBox,
+};
+use memflow::types::{Address, PhysicalAddress};
+use std::cell::{RefCell, RefMut};
 
 #[derive(thiserror::Error, Debug)]
 pub enum MemflowDriverError {
@@ -13,14 +22,146 @@ pub enum MemflowDriverError {
     MissingConnectorParameter,
     #[error("Invalid format for Memflow connector argument (key=value), got {0}")]
     InvalidConnectorArgument(String),
+    #[error("Memflow connector {0} cannot be initialized with {1} parameters")]
+    ConnectorParamsMismatch(String, &'static str),
+    #[error("Invalid Memflow connector parameter: {0}")]
+    InvalidConnectorParameter(&'static str),
+    #[error("Memflow driver has been initialized without an OS layer (os_name)")]
+    MissingOsLayer,
+}
+
+impl MemflowDriverError {
+    pub(crate) fn init_failure_reason(&self) -> InitFailureReason {
+        match self {
+            MemflowDriverError::MissingConnectorParameter
+            | MemflowDriverError::InvalidConnectorArgument(_)
+            | MemflowDriverError::ConnectorParamsMismatch(_, _)
+            | MemflowDriverError::InvalidConnectorParameter(_) => {
+                InitFailureReason::MissingParameter
+            }
+            MemflowDriverError::MissingOsLayer => InitFailureReason::Other,
+        }
+    }
 }
 
 const QEMU_PROCFS_CONNECTOR_NAME: &str = "qemu";
+const KVM_CONNECTOR_NAME: &str = "kvm";
+const PCILEECH_CONNECTOR_NAME: &str = "pcileech";
+const COREDUMP_CONNECTOR_NAME: &str = "coredump";
+const PCILEECH_DEFAULT_DEVICE: &str = "FPGA";
+
+/// Parse `key=value` strings into memflow arguments
+fn insert_args(mut extra_args: Args, args: &[String]) -> Result<Args, MemflowDriverError> {
+    // for each string, split at '=' to get key, value
+    for s in args.iter() {
+        let (key, value) = s
+            .split_once('=')
+            .ok_or_else(|| MemflowDriverError::InvalidConnectorArgument(s.clone()))?;
+        // push it into memflow Args type
+        extra_args = extra_args.insert(key, value);
+    }
+    Ok(extra_args)
+}
+
+/// Build the memflow connector arguments, checking that the parameters fit the connector
+///
+/// # Arguments
+/// * 'connector_name' - memflow connector name
+/// * 'connector_params' - optional connector parameters
+/// * 'vm_name' - optional `CommonInitParams.vm_name`, forwarded to the qemu connector
+///
+fn connector_args(
+    connector_name: &str,
+    connector_params: Option<MemflowConnectorParams>,
+    vm_name: Option<String>,
+) -> Result<Args, MemflowDriverError> {
+    let check_connector = |expected: &str, params: &'static str| {
+        if connector_name == expected {
+            Ok(())
+        } else {
+            Err(MemflowDriverError::ConnectorParamsMismatch(
+                connector_name.to_string(),
+                params,
+            ))
+        }
+    };
+    let vm_name = vm_name.filter(|name| !name.is_empty());
+    match connector_params {
+        None | Some(MemflowConnectorParams::Default { .. }) => {
+            let mut extra_args = Args::new();
+            // reuse the common vm_name as the qemu connector 'name' argument
+            if connector_name == QEMU_PROCFS_CONNECTOR_NAME {
+                if let Some(vm_name) = vm_name {
+                    extra_args = extra_args.insert("name", &vm_name);
+                }
+            }
+            match connector_params {
+                Some(MemflowConnectorParams::Default { args }) => insert_args(extra_args, &args),
+                _ => Ok(extra_args),
+            }
+        }
+        Some(MemflowConnectorParams::QEMUProcFs { vm_name: qemu_name }) => {
+            check_connector(QEMU_PROCFS_CONNECTOR_NAME, "QEMUProcFs")?;
+            match qemu_name.or(vm_name) {
+                Some(name) if name.is_empty() => Err(
+                    MemflowDriverError::InvalidConnectorParameter("empty QEMU VM name"),
+                ),
+                Some(name) => Ok(Args::new().insert("name", &name)),
+                // the connector picks the first QEMU process
+                None => Ok(Args::new()),
+            }
+        }
+        Some(MemflowConnectorParams::KVM { pid }) => {
+            check_connector(KVM_CONNECTOR_NAME, "KVM")?;
+            if pid == 0 {
+                return Err(MemflowDriverError::InvalidConnectorParameter(
+                    "KVM process pid cannot be 0",
+                ));
+            }
+            Ok(Args::with_default(&pid.to_string()))
+        }
+        Some(MemflowConnectorParams::PCILeech { device, memmap }) => {
+            check_connector(PCILEECH_CONNECTOR_NAME, "PCILeech")?;
+            let device = device.unwrap_or_else(|| String::from(PCILEECH_DEFAULT_DEVICE));
+            if device.is_empty() {
+                return Err(MemflowDriverError::InvalidConnectorParameter(
+                    "empty PCILeech device",
+                ));
+            }
+            let mut extra_args = Args::new().insert("device", &device);
+            if let Some(memmap) = memmap {
+                extra_args = extra_args.insert("memmap", &memmap);
+            }
+            Ok(extra_args)
+        }
+        Some(MemflowConnectorParams::Coredump { filepath }) => {
+            check_connector(COREDUMP_CONNECTOR_NAME, "Coredump")?;
+            if filepath.is_empty() {
+                return Err(MemflowDriverError::InvalidConnectorParameter(
+                    "empty coredump file path",
+                ));
+            }
+            Ok(Args::with_default(&filepath))
+        }
+    }
+}
 
 pub struct Memflow {
     // refcell required because read methods are mutable
     // contrary to our read_frame signature
     connector: RefCell<ConnectorInstanceArcBox<'static>>,
+    // optional OS layer, built on a clone of the connector
+    os: Option<RefCell<OsInstanceArcBox<'static>>>,
+}
+
+/// Whether at least one memflow connector plugin is installed
+pub fn probe() -> bool {
+    !Inventory::scan().available_connectors().is_empty()
+}
+
+/// List the named QEMU processes, as seen by the qemu connector
+pub fn list_vms() -> Result<Vec<VmInfo>, Box<dyn Error>> {
+    Ok(list_qemu_vms()?)
 }
 
 impl Memflow {
@@ -31,32 +172,11 @@ impl Memflow {
             .memflow
             .ok_or(MemflowDriverError::MissingConnectorParameter)?;
         // parse connector args
-        let mut extra_args = Args::new();
-        // reuse some of the common parameters in init_params
-        #[allow(clippy::single_match)]
-        match memflow_init_params.connector_name.as_str() {
-            QEMU_PROCFS_CONNECTOR_NAME => {
-                // if init_params.common.vm_name exists and connector_name is qemu
-                // then insert vm_name value as 'name' connector args
-                if init_params.common.is_some() {
-                    extra_args = extra_args.insert("name", &init_params.common.unwrap().vm_name);
-                }
-            }
-            _ => {}
-        };
-        if memflow_init_params.connector_args.is_some() {
-            let MemflowConnectorParams::Default { args } =
-                memflow_init_params.connector_args.unwrap();
-
-            // for each string, split at '=' to get key, value
-            for s in args.iter() {
-                let (key, value) = s
-                    .split_once('=')
-                    .ok_or_else(|| MemflowDriverError::InvalidConnectorArgument(s.clone()))?;
-                // push it into memflow Args type
-                extra_args = extra_args.insert(key, value);
-            }
-        }
+        let extra_args = connector_args(
+            &memflow_init_params.connector_name,
+            memflow_init_params.connector_args,
+            init_params.common.map(|common| common.vm_name),
+        )?;
         // display final connector args
         debug!("Memflow connector args: {:#?}", extra_args);
         let create_connector_args = ConnectorArgs::new(None, extra_args, None);
@@ -68,13 +188,46 @@ impl Memflow {
             None,
             Some(&create_connector_args),
         )?;
+        // create the optional OS layer
+        let os = match memflow_init_params.os_name {
+            Some(os_name) => {
+                let os_args = insert_args(
+                    Args::new(),
+                    &memflow_init_params.os_args.unwrap_or_default(),
+                )?;
+                debug!("Memflow OS {} args: {:#?}", os_name, os_args);
+                let os = inventory.create_os(
+                    &os_name,
+                    Some(connector.clone()),
+                    Some(&OsArgs::new(None, os_args)),
+                )?;
+                Some(RefCell::new(os))
+            }
+            None => None,
+        };
         Ok(Memflow {
             connector: RefCell::new(connector),
+            os,
         })
     }
+
+    fn os(&self) -> Result<RefMut<'_, OsInstanceArcBox<'static>>, MemflowDriverError> {
+        self.os
+            .as_ref()
+            .map(RefCell::borrow_mut)
+            .ok_or(MemflowDriverError::MissingOsLayer)
+    }
 }
 
 impl Introspectable for Memflow {
+    // the memflow connectors expose the guest memory, but not the VCPUs
+    fn get_vcpu_count(&self) -> Result<u16, Box<dyn Error>> {
+        Err(Box::new(UnsupportedOperation {
+            driver_type: DriverType::Memflow,
+            operation: "VCPU count",
+        }))
+    }
+
     fn read_physical(
         &self,
         paddr: u64,
@@ -88,11 +241,240 @@ impl Introspectable for Memflow {
         Ok(())
     }
 
+    fn write_physical(&self, paddr: u64, buf: &[u8]) -> Result<(), Box<dyn Error>> {
+        self.connector
+            .borrow_mut()
+            .phys_write(PhysicalAddress::from(paddr), buf)?;
+        Ok(())
+    }
+
     fn get_max_physical_addr(&self) -> Result<u64, Box<dyn Error>> {
         Ok(self.connector.borrow_mut().metadata().max_address.to_umem())
     }
 
+    fn read_registers(&self, _vcpu: u16) -> Result<Registers, Box<dyn Error>> {
+        Err(Box::new(UnsupportedOperation {
+            driver_type: DriverType::Memflow,
+            operation: "registers read",
+        }))
+    }
+
+    fn write_registers(&self, _vcpu: u16, _reg: Registers) -> Result<(), Box<dyn Error>> {
+        Err(Box::new(UnsupportedOperation {
+            driver_type: DriverType::Memflow,
+            operation: "registers write",
+        }))
+    }
+
+    fn list_processes(&self) -> Result<Vec<ProcessInfo>, Box<dyn Error>> {
+        Ok(self
+            .os()?
+            .process_info_list()?
+            .into_iter()
+            .map(|info| ProcessInfo {
+                pid: info.pid,
+                name: info.name.to_string(),
+                path: info.path.to_string(),
+                command_line: info.command_line.to_string(),
+                dtb: info.dtb1.to_umem(),
+            })
+            .collect())
+    }
+
+    fn list_modules(&self, pid: u32) -> Result<Vec<ModuleInfo>, Box<dyn Error>> {
+        let mut os = self.os()?;
+        let mut process = os.process_by_pid(pid)?;
+        Ok(process
+            .module_list()?
+            .into_iter()
+            .map(|module| ModuleInfo {
+                name: module.name.to_string(),
+                path: module.path.to_string(),
+                base: module.base.to_umem(),
+                size: module.size,
+            })
+            .collect())
+    }
+
+    fn read_virtual(
+        &self,
+        pid: u32,
+        vaddr: u64,
+        buf: &mut [u8],
+        bytes_read: &mut u64,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut os = self.os()?;
+        let mut process = os.process_by_pid(pid)?;
+        // unmapped pages fail the whole read
+        process.read_raw_into(Address::from(vaddr), buf).data()?;
+        *bytes_read = buf.len() as u64;
+        Ok(())
+    }
+
     fn get_driver_type(&self) -> DriverType {
         DriverType::Memflow
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use memflow::dummy::os::create_dummy;
+    use memflow::dummy::DummyMemory;
+    use memflow::plugins::connector::create_instance;
+    use memflow::plugins::LibArc;
+
+    use super::*;
+
+    fn dummy_driver(with_os: bool) -> Memflow {
+        let connector = create_instance(
+            DummyMemory::new(0x4000),
+            LibArc::default(),
+            &ConnectorArgs::default(),
+            true,
+        );
+        // the dummy OS comes with its own memory and one process
+        let os = with_os
+            .then(|| RefCell::new(create_dummy(&OsArgs::default(), LibArc::default()).unwrap()));
+        Memflow {
+            connector: RefCell::new(connector),
+            os,
+        }
+    }
+
+    fn missing_os_layer(error: Box<dyn Error>) -> bool {
+        matches!(
+            error.downcast_ref::<MemflowDriverError>(),
+            Some(MemflowDriverError::MissingOsLayer)
+        )
+    }
+
+    #[test]
+    fn test_write_physical_is_read_back() {
+        let drv = dummy_driver(false);
+        let mut buf = [0u8; 4];
+        let mut bytes_read = 0;
+
+        drv.write_physical(0x1ffe, &[0xde, 0xad, 0xbe, 0xef])
+            .unwrap();
+        drv.read_physical(0x1ffe, &mut buf, &mut bytes_read)
+            .unwrap();
+
+        assert_eq!(buf, [0xde, 0xad, 0xbe, 0xef]);
+        assert_eq!(bytes_read, 4);
+    }
+
+    #[test]
+    fn test_cpu_state_is_unsupported() {
+        let drv = dummy_driver(false);
+
+        for error in [
+            drv.get_vcpu_count().unwrap_err(),
+            drv.read_registers(0).unwrap_err(),
+            drv.write_registers(0, Registers::X86(Default::default()))
+                .unwrap_err(),
+        ] {
+            assert!(
+                matches!(
+                    error.downcast_ref::<UnsupportedOperation>(),
+                    Some(UnsupportedOperation {
+                        driver_type: DriverType::Memflow,
+                        ..
+                    })
+                ),
+                "{}",
+                error
+            );
+        }
+    }
+
+    #[test]
+    fn test_os_operations_require_os_layer() {
+        let drv = dummy_driver(false);
+        let mut buf = [0u8; 4];
+        let mut bytes_read = 0;
+
+        assert!(missing_os_layer(drv.list_processes().unwrap_err()));
+        assert!(missing_os_layer(drv.list_modules(1).unwrap_err()));
+        assert!(missing_os_layer(
+            drv.read_virtual(1, 0x1000, &mut buf, &mut bytes_read)
+                .unwrap_err()
+        ));
+        assert_eq!(
+            MemflowDriverError::MissingOsLayer.init_failure_reason(),
+            InitFailureReason::Other
+        );
+    }
+
+    #[test]
+    fn test_processes_are_listed_with_os_layer() {
+        let drv = dummy_driver(true);
+
+        let processes = drv.list_processes().unwrap();
+
+        assert_eq!(processes.len(), 1);
+        assert_eq!(drv.list_modules(processes[0].pid).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_default_args_reuse_vm_name() {
+        let args = connector_args(
+            QEMU_PROCFS_CONNECTOR_NAME,
+            Some(MemflowConnectorParams::Default {
+                args: vec![String::from("map_override=0x1000")],
+            }),
+            Some(String::from("win10")),
+        )
+        .unwrap();
+
+        assert_eq!(args.get("name"), Some("win10"));
+        assert_eq!(args.get("map_override"), Some("0x1000"));
+    }
+
+    #[test]
+    fn test_typed_params_are_mapped() {
+        let kvm = connector_args(
+            KVM_CONNECTOR_NAME,
+            Some(MemflowConnectorParams::KVM { pid: 1234 }),
+            None,
+        )
+        .unwrap();
+        let pcileech = connector_args(
+            PCILEECH_CONNECTOR_NAME,
+            Some(MemflowConnectorParams::PCILeech {
+                device: None,
+                memmap: Some(String::from("mmap.txt")),
+            }),
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(kvm.get_default(), Some("1234"));
+        assert_eq!(pcileech.get("device"), Some(PCILEECH_DEFAULT_DEVICE));
+        assert_eq!(pcileech.get("memmap"), Some("mmap.txt"));
+    }
+
+    #[test]
+    fn test_invalid_typed_params_are_refused() {
+        let mismatch = connector_args(
+            QEMU_PROCFS_CONNECTOR_NAME,
+            Some(MemflowConnectorParams::Coredump {
+                filepath: String::from("/tmp/win10.dmp"),
+            }),
+            None,
+        );
+        let invalid = connector_args(
+            KVM_CONNECTOR_NAME,
+            Some(MemflowConnectorParams::KVM { pid: 0 }),
+            None,
+        );
+
+        assert!(matches!(
+            mismatch,
+            Err(MemflowDriverError::ConnectorParamsMismatch(_, "Coredump"))
+        ));
+        assert!(matches!(
+            invalid,
+            Err(MemflowDriverError::InvalidConnectorParameter(_))
+        ));
+    }
+}
@@ -1,7 +1,21 @@
+#[cfg(test)]
+pub mod fake;
 #[cfg(feature = "kvm")]
 pub mod kvm;
+#[cfg(feature = "xen")]
+mod libxenctrl;
 #[cfg(feature = "mflow")]
 pub mod memflow;
+#[cfg(any(feature = "kvm", feature = "xen", feature = "virtualbox"))]
+mod pause;
+pub mod plugin;
+#[cfg(any(feature = "kvm", feature = "mflow"))]
+pub mod qemu;
+pub mod read_only;
+#[cfg(feature = "remote")]
+pub mod remote;
+#[cfg(any(feature = "kvm", feature = "xen"))]
+mod state;
 #[cfg(feature = "virtualbox")]
 pub mod virtualbox;
 #[cfg(feature = "xen")]
@@ -0,0 +1,104 @@
+/// Counts the nested pause requests of a driver
+///
+/// The VM is only paused by the first request, and resumed when the last one is matched.
+#[derive(Debug, Default)]
+pub struct PauseCounter {
+    // number of pause requests not matched by a resume yet
+    count: u32,
+}
+
+impl PauseCounter {
+    /// Count a pause request, pausing the VM with `pause_vm` if it is not paused yet
+    pub fn pause<E>(&mut self, pause_vm: impl FnOnce() -> Result<(), E>) -> Result<(), E> {
+        if self.count == 0 {
+            pause_vm()?;
+        }
+        self.count += 1;
+        debug!("pause count: {}", self.count);
+        Ok(())
+    }
+
+    /// Count a resume request, resuming the VM with `resume_vm` unless it is still paused by
+    /// someone else
+    pub fn resume<E>(&mut self, resume_vm: impl FnOnce() -> Result<(), E>) -> Result<(), E> {
+        if self.count > 1 {
+            self.count -= 1;
+            debug!("pause count: {}", self.count);
+            return Ok(());
+        }
+        resume_vm()?;
+        self.count = 0;
+        Ok(())
+    }
+
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nested_pause_resumes_once() {
+        let mut counter = PauseCounter::default();
+        let mut pauses = 0;
+        let mut resumes = 0;
+
+        for _ in 0..2 {
+            counter
+                .pause(|| -> Result<(), ()> {
+                    pauses += 1;
+                    Ok(())
+                })
+                .unwrap();
+        }
+        for _ in 0..2 {
+            counter
+                .resume(|| -> Result<(), ()> {
+                    resumes += 1;
+                    Ok(())
+                })
+                .unwrap();
+        }
+
+        assert_eq!((pauses, resumes), (1, 1));
+        assert_eq!(counter.count(), 0);
+    }
+
+    #[test]
+    fn test_failed_pause_is_not_counted() {
+        let mut counter = PauseCounter::default();
+
+        assert!(counter.pause(|| Err(())).is_err());
+
+        assert_eq!(counter.count(), 0);
+    }
+
+    #[test]
+    fn test_failed_resume_keeps_vm_paused() {
+        let mut counter = PauseCounter::default();
+        counter.pause(|| -> Result<(), ()> { Ok(()) }).unwrap();
+
+        assert!(counter.resume(|| Err(())).is_err());
+
+        assert_eq!(counter.count(), 1);
+    }
+
+    #[test]
+    fn test_resume_without_pause_resumes_vm() {
+        let mut counter = PauseCounter::default();
+        let mut resumes = 0;
+
+        counter
+            .resume(|| -> Result<(), ()> {
+                resumes += 1;
+                Ok(())
+            })
+            .unwrap();
+
+        assert_eq!(resumes, 1);
+        assert_eq!(counter.count(), 0);
+    }
+}
@@ -0,0 +1,642 @@
+use std::env;
+use std::error::Error;
+use std::ffi::{c_void, CStr, CString, OsStr};
+use std::fs;
+use std::mem::MaybeUninit;
+use std::os::raw::c_char;
+use std::path::{Path, PathBuf};
+use std::ptr::null_mut;
+use std::sync::{Arc, OnceLock};
+
+use libloading::Library;
+
+use crate::api::events::{Event, EventReplyType, InterceptType};
+use crate::api::params::DriverInitParams;
+use crate::api::plugin::{
+    PluginDescriptor, PluginEntrypoint, PluginResult, DEFAULT_PLUGIN_DIR, PLUGIN_ABI_VERSION,
+    PLUGIN_ENTRYPOINT,
+};
+use crate::api::registers::Registers;
+use crate::api::vms::VmInfo;
+use crate::api::{Access, DriverType, Introspectable};
+
+#[derive(thiserror::Error, Debug)]
+pub enum PluginError {
+    #[error("failed to load plugin: {0}")]
+    Load(#[from] libloading::Error),
+    #[error(
+        "plugin ABI version {0} is not supported, expected {}",
+        PLUGIN_ABI_VERSION
+    )]
+    AbiMismatch(u32),
+    #[error("{0}")]
+    Plugin(String),
+    #[error("{0} is not supported by this plugin")]
+    UnsupportedOperation(&'static str),
+}
+
+/// A driver plugin, loaded from the plugin directory
+pub struct LoadedPlugin {
+    descriptor: PluginDescriptor,
+    // keeps the shared object loaded, None for the plugins linked in the library
+    library: Option<Arc<Library>>,
+}
+
+impl LoadedPlugin {
+    /// Load a plugin from a shared object
+    pub fn load(path: &Path) -> Result<Self, PluginError> {
+        let library = unsafe { Library::new(path)? };
+        let descriptor = unsafe {
+            let entrypoint = library.get::<PluginEntrypoint>(PLUGIN_ENTRYPOINT.as_bytes())?;
+            entrypoint()
+        };
+        LoadedPlugin::from_descriptor(descriptor, Some(Arc::new(library)))
+    }
+
+    fn from_descriptor(
+        descriptor: PluginDescriptor,
+        library: Option<Arc<Library>>,
+    ) -> Result<Self, PluginError> {
+        if descriptor.abi_version != PLUGIN_ABI_VERSION {
+            return Err(PluginError::AbiMismatch(descriptor.abi_version));
+        }
+        Ok(LoadedPlugin {
+            descriptor,
+            library,
+        })
+    }
+
+    pub fn driver_type(&self) -> DriverType {
+        self.descriptor.driver_type
+    }
+
+    pub fn probe(&self) -> bool {
+        unsafe { (self.descriptor.probe)() }
+    }
+
+    pub fn init(&self, init_params: DriverInitParams) -> Result<PluginDriver, Box<dyn Error>> {
+        let init_params = CString::new(serde_json::to_string(&init_params)?)?;
+        let mut ctx = null_mut();
+        unsafe {
+            check(
+                &self.descriptor,
+                (self.descriptor.init)(init_params.as_ptr(), &mut ctx),
+            )?;
+        }
+        Ok(PluginDriver {
+            ctx,
+            descriptor: self.descriptor,
+            _library: self.library.clone(),
+        })
+    }
+
+    pub fn list_vms(&self) -> Result<Vec<VmInfo>, Box<dyn Error>> {
+        let list_vms = self
+            .descriptor
+            .list_vms
+            .ok_or(PluginError::UnsupportedOperation("VM listing"))?;
+        let mut json = null_mut();
+        unsafe {
+            check(&self.descriptor, list_vms(&mut json))?;
+            let vms = serde_json::from_str(&CStr::from_ptr(json).to_string_lossy());
+            (self.descriptor.free_error)(json);
+            Ok(vms?)
+        }
+    }
+}
+
+/// The plugin directory, `MICROVMI_PLUGIN_DIR` or the default one
+pub fn plugin_dir() -> PathBuf {
+    env::var_os("MICROVMI_PLUGIN_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(DEFAULT_PLUGIN_DIR))
+}
+
+/// Load every plugin of a directory
+///
+/// The files which are not valid plugins are skipped.
+pub fn load_plugins(dir: &Path) -> Vec<LoadedPlugin> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            debug!("No plugin loaded from {}: {}", dir.display(), e);
+            return Vec::new();
+        }
+    };
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension() == Some(OsStr::new(env::consts::DLL_EXTENSION)))
+        .collect();
+    // load order decides which plugin provides a driver type
+    paths.sort();
+    paths
+        .iter()
+        .filter_map(|path| match LoadedPlugin::load(path) {
+            Ok(plugin) => {
+                info!(
+                    "Plugin loaded: {:?} driver from {}",
+                    plugin.driver_type(),
+                    path.display()
+                );
+                Some(plugin)
+            }
+            Err(e) => {
+                warn!("Failed to load plugin {}: {}", path.display(), e);
+                None
+            }
+        })
+        .collect()
+}
+
+/// The plugins of the plugin directory, loaded on first use
+pub fn plugins() -> &'static [LoadedPlugin] {
+    static PLUGINS: OnceLock<Vec<LoadedPlugin>> = OnceLock::new();
+    PLUGINS.get_or_init(|| load_plugins(&plugin_dir()))
+}
+
+/// Find the first plugin providing a driver type
+pub fn find(driver_type: DriverType) -> Option<&'static LoadedPlugin> {
+    plugins()
+        .iter()
+        .find(|plugin| plugin.driver_type() == driver_type)
+}
+
+/// Turn a plugin error message into an error, and release it
+unsafe fn check(descriptor: &PluginDescriptor, result: PluginResult) -> Result<(), PluginError> {
+    if result.is_null() {
+        return Ok(());
+    }
+    let message = CStr::from_ptr(result as *const c_char)
+        .to_string_lossy()
+        .into_owned();
+    (descriptor.free_error)(result);
+    Err(PluginError::Plugin(message))
+}
+
+/// Driver provided by a plugin
+pub struct PluginDriver {
+    ctx: *mut c_void,
+    descriptor: PluginDescriptor,
+    // dropped after the driver has been destroyed
+    _library: Option<Arc<Library>>,
+}
+
+macro_rules! plugin_call {
+    ($self:ident, $op:ident, $name:literal $(, $arg:expr)*) => {
+        match $self.descriptor.vtable.$op {
+            Some(f) => unsafe { check(&$self.descriptor, f($self.ctx $(, $arg)*)) },
+            None => Err(PluginError::UnsupportedOperation($name)),
+        }
+    };
+}
+
+impl Introspectable for PluginDriver {
+    fn get_vcpu_count(&self) -> Result<u16, Box<dyn Error>> {
+        let mut count = 0;
+        plugin_call!(self, get_vcpu_count, "vcpu count", &mut count)?;
+        Ok(count)
+    }
+
+    fn read_physical(
+        &self,
+        paddr: u64,
+        buf: &mut [u8],
+        bytes_read: &mut u64,
+    ) -> Result<(), Box<dyn Error>> {
+        Ok(plugin_call!(
+            self,
+            read_physical,
+            "physical memory read",
+            paddr,
+            buf.as_mut_ptr(),
+            buf.len(),
+            bytes_read
+        )?)
+    }
+
+    fn write_physical(&self, paddr: u64, buf: &[u8]) -> Result<(), Box<dyn Error>> {
+        Ok(plugin_call!(
+            self,
+            write_physical,
+            "physical memory write",
+            paddr,
+            buf.as_ptr(),
+            buf.len()
+        )?)
+    }
+
+    fn get_max_physical_addr(&self) -> Result<u64, Box<dyn Error>> {
+        let mut addr = 0;
+        plugin_call!(
+            self,
+            get_max_physical_addr,
+            "max physical address",
+            &mut addr
+        )?;
+        Ok(addr)
+    }
+
+    fn read_registers(&self, vcpu: u16) -> Result<Registers, Box<dyn Error>> {
+        let mut registers = MaybeUninit::<Registers>::uninit();
+        plugin_call!(
+            self,
+            read_registers,
+            "registers read",
+            vcpu,
+            registers.as_mut_ptr()
+        )?;
+        Ok(unsafe { registers.assume_init() })
+    }
+
+    fn write_registers(&self, vcpu: u16, reg: Registers) -> Result<(), Box<dyn Error>> {
+        Ok(plugin_call!(
+            self,
+            write_registers,
+            "registers write",
+            vcpu,
+            reg
+        )?)
+    }
+
+    fn get_page_access(&self, paddr: u64) -> Result<Access, Box<dyn Error>> {
+        let mut access = 0;
+        plugin_call!(
+            self,
+            get_page_access,
+            "page access read",
+            paddr,
+            &mut access
+        )?;
+        Ok(Access::from_bits_truncate(access))
+    }
+
+    fn set_page_access(&self, paddr: u64, access: Access) -> Result<(), Box<dyn Error>> {
+        Ok(plugin_call!(
+            self,
+            set_page_access,
+            "page access modification",
+            paddr,
+            access.bits()
+        )?)
+    }
+
+    fn set_page_access_range(
+        &self,
+        start: u64,
+        end: u64,
+        access: Access,
+    ) -> Result<(), Box<dyn Error>> {
+        Ok(plugin_call!(
+            self,
+            set_page_access_range,
+            "page access modification",
+            start,
+            end,
+            access.bits()
+        )?)
+    }
+
+    fn set_page_access_multi(&self, pages: &[(u64, Access)]) -> Result<(), Box<dyn Error>> {
+        let (paddrs, access): (Vec<u64>, Vec<u32>) = pages
+            .iter()
+            .map(|(paddr, access)| (*paddr, access.bits()))
+            .unzip();
+        Ok(plugin_call!(
+            self,
+            set_page_access_multi,
+            "page access modification",
+            paddrs.as_ptr(),
+            access.as_ptr(),
+            pages.len()
+        )?)
+    }
+
+    fn create_view(&mut self) -> Result<u16, Box<dyn Error>> {
+        let mut view = 0;
+        plugin_call!(self, create_view, "view creation", &mut view)?;
+        Ok(view)
+    }
+
+    fn destroy_view(&mut self, view: u16) -> Result<(), Box<dyn Error>> {
+        Ok(plugin_call!(self, destroy_view, "view destruction", view)?)
+    }
+
+    fn switch_view(&mut self, vcpu: u16, view: u16) -> Result<(), Box<dyn Error>> {
+        Ok(plugin_call!(self, switch_view, "view switch", vcpu, view)?)
+    }
+
+    fn set_page_access_in_view(
+        &self,
+        view: u16,
+        paddr: u64,
+        access: Access,
+    ) -> Result<(), Box<dyn Error>> {
+        Ok(plugin_call!(
+            self,
+            set_page_access_in_view,
+            "page access modification",
+            view,
+            paddr,
+            access.bits()
+        )?)
+    }
+
+    fn remap_gfn(&mut self, view: u16, from: u64, to: u64) -> Result<(), Box<dyn Error>> {
+        Ok(plugin_call!(
+            self,
+            remap_gfn,
+            "gfn remapping",
+            view,
+            from,
+            to
+        )?)
+    }
+
+    fn pause(&mut self) -> Result<(), Box<dyn Error>> {
+        Ok(plugin_call!(self, pause, "pause")?)
+    }
+
+    fn resume(&mut self) -> Result<(), Box<dyn Error>> {
+        Ok(plugin_call!(self, resume, "resume")?)
+    }
+
+    fn inject_interrupt(
+        &mut self,
+        vcpu: u16,
+        vector: u8,
+        error_code: Option<u32>,
+        cr2: u64,
+    ) -> Result<(), Box<dyn Error>> {
+        Ok(plugin_call!(
+            self,
+            inject_interrupt,
+            "interrupt injection",
+            vcpu,
+            vector,
+            error_code.is_some(),
+            error_code.unwrap_or(0),
+            cr2
+        )?)
+    }
+
+    fn toggle_intercept(
+        &mut self,
+        vcpu: u16,
+        intercept_type: InterceptType,
+        enabled: bool,
+    ) -> Result<(), Box<dyn Error>> {
+        Ok(plugin_call!(
+            self,
+            toggle_intercept,
+            "intercept",
+            vcpu,
+            intercept_type,
+            enabled
+        )?)
+    }
+
+    fn get_active_intercepts(&self, vcpu: u16) -> Result<Vec<InterceptType>, Box<dyn Error>> {
+        let mut intercepts: Vec<InterceptType> = Vec::with_capacity(16);
+        loop {
+            let mut len = 0;
+            plugin_call!(
+                self,
+                get_active_intercepts,
+                "active intercepts",
+                vcpu,
+                intercepts.as_mut_ptr(),
+                intercepts.capacity(),
+                &mut len
+            )?;
+            if len <= intercepts.capacity() {
+                unsafe { intercepts.set_len(len) };
+                return Ok(intercepts);
+            }
+            // retry with a buffer large enough
+            intercepts.reserve_exact(len);
+        }
+    }
+
+    fn restore_guest_state(&mut self) -> Result<(), Box<dyn Error>> {
+        Ok(plugin_call!(
+            self,
+            restore_guest_state,
+            "guest state restoration"
+        )?)
+    }
+
+    fn listen(&mut self, timeout: u32) -> Result<Option<Event>, Box<dyn Error>> {
+        let mut event = MaybeUninit::<Event>::uninit();
+        let mut has_event = false;
+        plugin_call!(
+            self,
+            listen,
+            "listen",
+            timeout,
+            event.as_mut_ptr(),
+            &mut has_event
+        )?;
+        Ok(if has_event {
+            Some(unsafe { event.assume_init() })
+        } else {
+            None
+        })
+    }
+
+    fn reply_event(
+        &mut self,
+        event: Event,
+        reply_type: EventReplyType,
+    ) -> Result<(), Box<dyn Error>> {
+        Ok(plugin_call!(
+            self,
+            reply_event,
+            "event reply",
+            event,
+            reply_type
+        )?)
+    }
+
+    fn read_virtual(
+        &self,
+        pid: u32,
+        vaddr: u64,
+        buf: &mut [u8],
+        bytes_read: &mut u64,
+    ) -> Result<(), Box<dyn Error>> {
+        Ok(plugin_call!(
+            self,
+            read_virtual,
+            "virtual memory read",
+            pid,
+            vaddr,
+            buf.as_mut_ptr(),
+            buf.len(),
+            bytes_read
+        )?)
+    }
+
+    fn get_driver_type(&self) -> DriverType {
+        self.descriptor.driver_type
+    }
+}
+
+impl Drop for PluginDriver {
+    fn drop(&mut self) {
+        unsafe { (self.descriptor.destroy)(self.ctx) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::plugin::{descriptor, Plugin, PluginVtable};
+    use crate::driver::fake::FakeDriver;
+
+    struct FakePlugin;
+
+    impl Plugin for FakePlugin {
+        type Driver = FakeDriver;
+        const DRIVER_TYPE: DriverType = DriverType::Xen;
+
+        fn probe() -> bool {
+            true
+        }
+
+        fn init(init_params: DriverInitParams) -> Result<FakeDriver, Box<dyn Error>> {
+            let vm_name = init_params.common.ok_or("missing vm_name")?.vm_name;
+            Ok(FakeDriver {
+                driver_type: DriverType::Xen,
+                ..FakeDriver::new(vm_name.into_bytes())
+            })
+        }
+
+        fn list_vms() -> Result<Vec<VmInfo>, Box<dyn Error>> {
+            Ok(vec![VmInfo {
+                name: String::from("windows10"),
+                id: Some(5),
+            }])
+        }
+    }
+
+    fn fake_plugin() -> LoadedPlugin {
+        LoadedPlugin::from_descriptor(descriptor::<FakePlugin>(), None).unwrap()
+    }
+
+    fn init_params(vm_name: &str) -> DriverInitParams {
+        DriverInitParams {
+            common: Some(crate::api::params::CommonInitParams {
+                vm_name: String::from(vm_name),
+                read_only: false,
+                audit_log: None,
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_driver_is_called_through_the_plugin_abi() {
+        let plugin = fake_plugin();
+        assert_eq!(DriverType::Xen, plugin.driver_type());
+        assert!(plugin.probe());
+
+        let drv = plugin.init(init_params("windows10")).unwrap();
+        let mut buf = [0u8; 3];
+        let mut bytes_read = 0;
+        drv.read_physical(2, &mut buf, &mut bytes_read).unwrap();
+
+        assert_eq!(b"ndo", &buf);
+        assert_eq!(3, bytes_read);
+        assert_eq!(DriverType::Xen, drv.get_driver_type());
+    }
+
+    #[test]
+    fn test_plugin_errors_are_returned() {
+        let plugin = fake_plugin();
+        let init_error = plugin.init(DriverInitParams::default()).err().unwrap();
+        assert_eq!("missing vm_name", init_error.to_string());
+
+        let drv = plugin.init(init_params("windows10")).unwrap();
+        let mut buf = [0u8; 16];
+        let mut bytes_read = 0;
+        let read_error = drv.read_physical(0, &mut buf, &mut bytes_read).unwrap_err();
+        assert_eq!("out of bounds", read_error.to_string());
+    }
+
+    #[test]
+    fn test_unimplemented_operation_does_not_unwind_into_the_host() {
+        let drv = fake_plugin().init(init_params("windows10")).unwrap();
+        let error = drv.get_vcpu_count().unwrap_err();
+        assert!(
+            error.to_string().starts_with("plugin panicked"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn test_missing_vtable_entry_is_unsupported() {
+        let mut plugin_descriptor = descriptor::<FakePlugin>();
+        plugin_descriptor.vtable = PluginVtable::default();
+        let plugin = LoadedPlugin::from_descriptor(plugin_descriptor, None).unwrap();
+        let drv = plugin.init(init_params("windows10")).unwrap();
+        assert!(matches!(
+            drv.get_max_physical_addr()
+                .unwrap_err()
+                .downcast_ref::<PluginError>(),
+            Some(PluginError::UnsupportedOperation(_))
+        ));
+    }
+
+    #[test]
+    fn test_vms_are_listed_through_the_plugin_abi() {
+        let vms = fake_plugin().list_vms().unwrap();
+
+        assert_eq!(
+            vec![VmInfo {
+                name: String::from("windows10"),
+                id: Some(5),
+            }],
+            vms
+        );
+    }
+
+    #[test]
+    fn test_missing_list_vms_entry_is_unsupported() {
+        let mut plugin_descriptor = descriptor::<FakePlugin>();
+        plugin_descriptor.list_vms = None;
+        let plugin = LoadedPlugin::from_descriptor(plugin_descriptor, None).unwrap();
+        assert!(matches!(
+            plugin.list_vms().unwrap_err().downcast_ref::<PluginError>(),
+            Some(PluginError::UnsupportedOperation(_))
+        ));
+    }
+
+    #[test]
+    fn test_abi_version_mismatch_is_refused() {
+        let mut plugin_descriptor = descriptor::<FakePlugin>();
+        plugin_descriptor.abi_version = PLUGIN_ABI_VERSION + 1;
+        assert!(matches!(
+            LoadedPlugin::from_descriptor(plugin_descriptor, None),
+            Err(PluginError::AbiMismatch(_))
+        ));
+    }
+
+    #[test]
+    fn test_invalid_plugins_are_skipped() {
+        let dir = env::temp_dir().join(format!("microvmi-plugins-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join(format!("invalid.{}", env::consts::DLL_EXTENSION)),
+            b"not a shared object",
+        )
+        .unwrap();
+        fs::write(dir.join("README"), b"ignored").unwrap();
+
+        let plugins = load_plugins(&dir);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(plugins.is_empty());
+        assert!(load_plugins(&dir).is_empty());
+    }
+}
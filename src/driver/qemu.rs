@@ -0,0 +1,84 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::api::vms::VmInfo;
+
+const PROCFS: &str = "/proc";
+
+/// List the running QEMU processes which have been given a name (`-name`)
+pub fn list_qemu_vms() -> Result<Vec<VmInfo>, io::Error> {
+    let mut vms = Vec::new();
+    for entry in fs::read_dir(PROCFS)? {
+        let entry = entry?;
+        let pid = match entry
+            .file_name()
+            .to_str()
+            .and_then(|s| s.parse::<u32>().ok())
+        {
+            Some(pid) => pid,
+            None => continue,
+        };
+        // the process may have exited meanwhile
+        let cmdline = match fs::read(entry.path().join("cmdline")) {
+            Ok(cmdline) => cmdline,
+            Err(_) => continue,
+        };
+        if let Some(name) = qemu_vm_name(&cmdline) {
+            vms.push(VmInfo {
+                name,
+                id: Some(pid),
+            });
+        }
+    }
+    Ok(vms)
+}
+
+/// Extract the VM name from a QEMU command line, as found in /proc/<pid>/cmdline
+fn qemu_vm_name(cmdline: &[u8]) -> Option<String> {
+    let mut args = cmdline
+        .split(|&b| b == 0)
+        .map(|arg| String::from_utf8_lossy(arg).into_owned());
+    let exe = args.next()?;
+    let exe_name = Path::new(&exe).file_name()?.to_string_lossy().into_owned();
+    if !exe_name.starts_with("qemu") {
+        return None;
+    }
+    let value = args.skip_while(|arg| arg != "-name").nth(1)?;
+    // -name [guest=]name[,debug-threads=on|off]
+    value
+        .split(',')
+        .find_map(|option| match option.split_once('=') {
+            Some(("guest", name)) => Some(name),
+            Some(_) => None,
+            None => Some(option),
+        })
+        .filter(|name| !name.is_empty())
+        .map(String::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_qemu_vm_name() {
+        let cmdline =
+            b"/usr/bin/qemu-system-x86_64\0-enable-kvm\0-name\0guest=win10,debug-threads=on\0";
+
+        assert_eq!(qemu_vm_name(cmdline), Some(String::from("win10")));
+        assert_eq!(
+            qemu_vm_name(b"qemu-kvm\0-name\0ubuntu\0"),
+            Some(String::from("ubuntu"))
+        );
+    }
+
+    #[test]
+    fn test_not_a_named_qemu_process() {
+        assert_eq!(qemu_vm_name(b"/usr/bin/vim\0-name\0win10\0"), None);
+        assert_eq!(
+            qemu_vm_name(b"/usr/bin/qemu-system-x86_64\0-enable-kvm\0"),
+            None
+        );
+    }
+}
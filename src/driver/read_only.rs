@@ -0,0 +1,216 @@
+use std::error::Error;
+
+use crate::api::events::{Event, EventReplyType, InterceptType};
+use crate::api::os::{ModuleInfo, ProcessInfo};
+use crate::api::registers::Registers;
+use crate::api::{Access, DriverType, Introspectable};
+
+#[derive(thiserror::Error, Debug)]
+pub enum ReadOnlyError {
+    #[error("{0} refused: the driver has been initialized in read-only mode")]
+    Refused(&'static str),
+}
+
+/// Driver wrapper refusing every operation which could modify the guest
+///
+/// Returned by `init` when `CommonInitParams.read_only` is set.
+/// Only the operations reading the guest state, and pausing / resuming the VM,
+/// are forwarded to the wrapped driver.
+pub struct ReadOnly<T: Introspectable + ?Sized> {
+    drv: Box<T>,
+}
+
+impl<T: Introspectable + ?Sized> ReadOnly<T> {
+    pub fn new(drv: Box<T>) -> Self {
+        ReadOnly { drv }
+    }
+}
+
+fn refuse<R>(operation: &'static str) -> Result<R, Box<dyn Error>> {
+    Err(Box::new(ReadOnlyError::Refused(operation)))
+}
+
+impl<T: Introspectable + ?Sized> Introspectable for ReadOnly<T> {
+    fn get_vcpu_count(&self) -> Result<u16, Box<dyn Error>> {
+        self.drv.get_vcpu_count()
+    }
+
+    fn read_physical(
+        &self,
+        paddr: u64,
+        buf: &mut [u8],
+        bytes_read: &mut u64,
+    ) -> Result<(), Box<dyn Error>> {
+        self.drv.read_physical(paddr, buf, bytes_read)
+    }
+
+    fn write_physical(&self, _paddr: u64, _buf: &[u8]) -> Result<(), Box<dyn Error>> {
+        refuse("physical memory write")
+    }
+
+    fn get_max_physical_addr(&self) -> Result<u64, Box<dyn Error>> {
+        self.drv.get_max_physical_addr()
+    }
+
+    fn read_registers(&self, vcpu: u16) -> Result<Registers, Box<dyn Error>> {
+        self.drv.read_registers(vcpu)
+    }
+
+    fn get_page_access(&self, paddr: u64) -> Result<Access, Box<dyn Error>> {
+        self.drv.get_page_access(paddr)
+    }
+
+    fn set_page_access(&self, _paddr: u64, _access: Access) -> Result<(), Box<dyn Error>> {
+        refuse("page access modification")
+    }
+
+    fn set_page_access_range(
+        &self,
+        _start: u64,
+        _end: u64,
+        _access: Access,
+    ) -> Result<(), Box<dyn Error>> {
+        refuse("page access modification")
+    }
+
+    fn set_page_access_multi(&self, _pages: &[(u64, Access)]) -> Result<(), Box<dyn Error>> {
+        refuse("page access modification")
+    }
+
+    fn create_view(&mut self) -> Result<u16, Box<dyn Error>> {
+        refuse("view creation")
+    }
+
+    fn destroy_view(&mut self, _view: u16) -> Result<(), Box<dyn Error>> {
+        refuse("view destruction")
+    }
+
+    fn switch_view(&mut self, _vcpu: u16, _view: u16) -> Result<(), Box<dyn Error>> {
+        refuse("view switching")
+    }
+
+    fn set_page_access_in_view(
+        &self,
+        _view: u16,
+        _paddr: u64,
+        _access: Access,
+    ) -> Result<(), Box<dyn Error>> {
+        refuse("page access modification")
+    }
+
+    fn remap_gfn(&mut self, _view: u16, _from: u64, _to: u64) -> Result<(), Box<dyn Error>> {
+        refuse("GFN remapping")
+    }
+
+    fn write_registers(&self, _vcpu: u16, _reg: Registers) -> Result<(), Box<dyn Error>> {
+        refuse("registers write")
+    }
+
+    fn pause(&mut self) -> Result<(), Box<dyn Error>> {
+        self.drv.pause()
+    }
+
+    fn resume(&mut self) -> Result<(), Box<dyn Error>> {
+        self.drv.resume()
+    }
+
+    fn inject_interrupt(
+        &mut self,
+        _vcpu: u16,
+        _vector: u8,
+        _error_code: Option<u32>,
+        _cr2: u64,
+    ) -> Result<(), Box<dyn Error>> {
+        refuse("interrupt injection")
+    }
+
+    fn toggle_intercept(
+        &mut self,
+        _vcpu: u16,
+        _intercept_type: InterceptType,
+        _enabled: bool,
+    ) -> Result<(), Box<dyn Error>> {
+        refuse("intercept modification")
+    }
+
+    fn get_active_intercepts(&self, vcpu: u16) -> Result<Vec<InterceptType>, Box<dyn Error>> {
+        self.drv.get_active_intercepts(vcpu)
+    }
+
+    fn restore_guest_state(&mut self) -> Result<(), Box<dyn Error>> {
+        // nothing has been changed
+        Ok(())
+    }
+
+    fn listen(&mut self, _timeout: u32) -> Result<Option<Event>, Box<dyn Error>> {
+        refuse("event listening")
+    }
+
+    fn reply_event(
+        &mut self,
+        _event: Event,
+        _reply_type: EventReplyType,
+    ) -> Result<(), Box<dyn Error>> {
+        refuse("event reply")
+    }
+
+    fn list_processes(&self) -> Result<Vec<ProcessInfo>, Box<dyn Error>> {
+        self.drv.list_processes()
+    }
+
+    fn list_modules(&self, pid: u32) -> Result<Vec<ModuleInfo>, Box<dyn Error>> {
+        self.drv.list_modules(pid)
+    }
+
+    fn read_virtual(
+        &self,
+        pid: u32,
+        vaddr: u64,
+        buf: &mut [u8],
+        bytes_read: &mut u64,
+    ) -> Result<(), Box<dyn Error>> {
+        self.drv.read_virtual(pid, vaddr, buf, bytes_read)
+    }
+
+    fn get_driver_type(&self) -> DriverType {
+        self.drv.get_driver_type()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::driver::fake::FakeDriver;
+
+    fn fake_driver() -> Box<FakeDriver> {
+        Box::new(FakeDriver {
+            driver_type: DriverType::Xen,
+            ..FakeDriver::new(vec![0x90; 0x10])
+        })
+    }
+
+    #[test]
+    fn test_reads_are_forwarded() {
+        let drv = ReadOnly::new(fake_driver());
+        let mut buf = [0u8; 4];
+        let mut bytes_read = 0;
+
+        drv.read_physical(0x4, &mut buf, &mut bytes_read).unwrap();
+
+        assert_eq!(buf, [0x90; 4]);
+        assert_eq!(bytes_read, 4);
+        assert_eq!(drv.get_driver_type(), DriverType::Xen);
+    }
+
+    #[test]
+    fn test_modifications_are_refused() {
+        let mut drv = ReadOnly::new(fake_driver());
+
+        assert!(drv.write_physical(0x4, &[0xCC]).is_err());
+        assert!(drv.set_page_access(0x1000, Access::RW).is_err());
+        assert!(drv
+            .toggle_intercept(0, InterceptType::Breakpoint, true)
+            .is_err());
+        assert!(drv.listen(0).is_err());
+    }
+}
@@ -0,0 +1,531 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::error::Error;
+use std::net::TcpStream;
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::time::Duration;
+
+use crate::api::events::{Event, EventReplyType, InterceptType};
+use crate::api::os::{ModuleInfo, ProcessInfo};
+use crate::api::params::DriverInitParams;
+use crate::api::protocol::{
+    read_frame, try_read_frame, write_frame, ProtocolError, Request, Response, Stream,
+    PROTOCOL_VERSION,
+};
+use crate::api::registers::Registers;
+use crate::api::vms::VmInfo;
+use crate::api::{Access, DriverType, Introspectable};
+use crate::errors::InitFailureReason;
+
+/// Size of the memory transferred by a single request
+const CHUNK_SIZE: usize = 1 << 20;
+/// Number of requests sent before waiting for their responses
+const PIPELINE_DEPTH: usize = 16;
+
+#[derive(thiserror::Error, Debug)]
+pub enum RemoteDriverError {
+    #[error("Remote driver initialization requires the server address (remote.addr)")]
+    MissingAddress,
+    #[error("Remote driver initialization requires the server token (remote.token)")]
+    MissingToken,
+    #[error("Invalid server address {0}, expected tcp://<host>:<port> or unix://<path>")]
+    InvalidAddress(String),
+    #[error("Server refused the connection: {0}")]
+    Refused(String),
+    #[error("Server error: {0}")]
+    Server(String),
+    #[error("Server stopped pushing the events: {0}")]
+    Subscription(String),
+    #[error("Unexpected server response: {0}")]
+    UnexpectedResponse(String),
+    #[error(transparent)]
+    Protocol(#[from] ProtocolError),
+}
+
+impl RemoteDriverError {
+    pub(crate) fn init_failure_reason(&self) -> InitFailureReason {
+        match self {
+            RemoteDriverError::MissingAddress
+            | RemoteDriverError::MissingToken
+            | RemoteDriverError::InvalidAddress(_) => InitFailureReason::MissingParameter,
+            RemoteDriverError::Refused(_) => InitFailureReason::PermissionDenied,
+            RemoteDriverError::Protocol(ProtocolError::Io(e)) => {
+                InitFailureReason::from_io_error(e)
+            }
+            _ => InitFailureReason::Other,
+        }
+    }
+
+    fn unexpected(response: Response) -> Self {
+        RemoteDriverError::UnexpectedResponse(format!("{:?}", response))
+    }
+}
+
+/// Whether a server can be reached
+///
+/// The server address is only known from the initialization parameters, there is nothing to probe.
+pub fn probe() -> bool {
+    false
+}
+
+/// List the VMs seen by the Remote driver
+///
+/// A server serves a single VM, selected when it was started: there is nothing to list.
+pub fn list_vms() -> Result<Vec<VmInfo>, Box<dyn Error>> {
+    Ok(Vec::new())
+}
+
+/// Connect to `tcp://<host>:<port>` or `unix://<path>`
+fn connect(addr: &str) -> Result<Box<dyn Stream>, RemoteDriverError> {
+    if let Some(host) = addr.strip_prefix("tcp://") {
+        let stream = TcpStream::connect(host).map_err(ProtocolError::Io)?;
+        // requests are small and latency bound
+        stream.set_nodelay(true).map_err(ProtocolError::Io)?;
+        return Ok(Box::new(stream));
+    }
+    #[cfg(unix)]
+    if let Some(path) = addr.strip_prefix("unix://") {
+        return Ok(Box::new(
+            UnixStream::connect(path).map_err(ProtocolError::Io)?,
+        ));
+    }
+    Err(RemoteDriverError::InvalidAddress(addr.to_string()))
+}
+
+struct Connection {
+    stream: Box<dyn Stream>,
+    // events pushed while waiting for a response, or the error ending the subscription
+    events: VecDeque<Result<Event, String>>,
+    subscribed: bool,
+}
+
+impl Connection {
+    fn send(&mut self, request: &Request, data: &[u8]) -> Result<(), RemoteDriverError> {
+        Ok(write_frame(&mut self.stream, request, data)?)
+    }
+
+    /// Receive the next response, queuing the events pushed before it
+    fn receive(&mut self) -> Result<(Response, Vec<u8>), RemoteDriverError> {
+        loop {
+            let (response, data) = read_frame(&mut self.stream)?;
+            if let Some(response) = self.queue_event(response) {
+                return match response {
+                    Response::Error(e) => Err(RemoteDriverError::Server(e)),
+                    response => Ok((response, data)),
+                };
+            }
+        }
+    }
+
+    /// Queue a pushed event, returns the other responses
+    fn queue_event(&mut self, response: Response) -> Option<Response> {
+        match response {
+            Response::PushedEvent(event) => self.events.push_back(Ok(event)),
+            Response::PushError(e) => {
+                self.subscribed = false;
+                self.events.push_back(Err(e));
+            }
+            response => return Some(response),
+        }
+        None
+    }
+
+    fn call(&mut self, request: &Request) -> Result<(Response, Vec<u8>), RemoteDriverError> {
+        self.send(request, &[])?;
+        self.receive()
+    }
+
+    /// Wait for a pushed event
+    fn wait_event(&mut self, timeout: u32) -> Result<Option<Event>, RemoteDriverError> {
+        // a zero timeout would disable the timeout
+        let timeout = Duration::from_millis(timeout.max(1).into());
+        self.stream
+            .set_read_timeout(Some(timeout))
+            .map_err(ProtocolError::Io)?;
+        let frame = try_read_frame(&mut self.stream);
+        self.stream
+            .set_read_timeout(None)
+            .map_err(ProtocolError::Io)?;
+        match frame? {
+            None => Ok(None),
+            Some((response, _)) => match self.queue_event(response) {
+                None => self.next_event(),
+                Some(response) => Err(RemoteDriverError::unexpected(response)),
+            },
+        }
+    }
+
+    fn next_event(&mut self) -> Result<Option<Event>, RemoteDriverError> {
+        match self.events.pop_front() {
+            None => Ok(None),
+            Some(Ok(event)) => Ok(Some(event)),
+            Some(Err(e)) => Err(RemoteDriverError::Subscription(e)),
+        }
+    }
+}
+
+/// Driver forwarding the calls to a `microvmi-server`, see the [protocol](../../api/protocol/index.html) module
+///
+/// The memory transfers are split into chunks, and the requests pipelined.
+/// The events are pushed by the server, once `listen` has been called.
+pub struct Remote {
+    conn: RefCell<Connection>,
+}
+
+macro_rules! remote_call {
+    ($self:ident, $request:expr, $pattern:pat => $value:expr) => {
+        match $self.conn.borrow_mut().call(&$request)? {
+            ($pattern, _) => Ok($value),
+            (response, _) => Err(RemoteDriverError::unexpected(response).into()),
+        }
+    };
+}
+
+impl Remote {
+    pub fn new(init_params: DriverInitParams) -> Result<Self, Box<dyn Error>> {
+        let params = init_params.remote.unwrap_or_default();
+        if params.addr.is_empty() {
+            return Err(Box::new(RemoteDriverError::MissingAddress));
+        }
+        if params.token.is_empty() {
+            return Err(Box::new(RemoteDriverError::MissingToken));
+        }
+        let mut conn = Connection {
+            stream: connect(&params.addr)?,
+            events: VecDeque::new(),
+            subscribed: false,
+        };
+        let hello = Request::Hello {
+            version: PROTOCOL_VERSION,
+            token: params.token,
+        };
+        match conn.call(&hello) {
+            Ok((Response::Hello { driver_type }, _)) => {
+                info!("Connected to {}: {:?} driver", params.addr, driver_type)
+            }
+            Ok((response, _)) => return Err(RemoteDriverError::unexpected(response).into()),
+            Err(RemoteDriverError::Server(e)) => {
+                return Err(Box::new(RemoteDriverError::Refused(e)))
+            }
+            Err(e) => return Err(Box::new(e)),
+        }
+        Ok(Remote {
+            conn: RefCell::new(conn),
+        })
+    }
+
+    /// Read memory in pipelined chunks
+    ///
+    /// Every response is received before returning the first error.
+    fn read_memory<F>(
+        &self,
+        buf: &mut [u8],
+        bytes_read: &mut u64,
+        request: F,
+    ) -> Result<(), Box<dyn Error>>
+    where
+        F: Fn(u64, u32) -> Request,
+    {
+        let mut conn = self.conn.borrow_mut();
+        let mut chunks = buf.chunks_mut(CHUNK_SIZE);
+        let mut pending = VecDeque::with_capacity(PIPELINE_DEPTH);
+        let mut offset = 0;
+        let mut first_error = None;
+        *bytes_read = 0;
+        loop {
+            while pending.len() < PIPELINE_DEPTH {
+                let chunk = match chunks.next() {
+                    Some(chunk) => chunk,
+                    None => break,
+                };
+                conn.send(&request(offset, chunk.len() as u32), &[])?;
+                offset += chunk.len() as u64;
+                pending.push_back(chunk);
+            }
+            let chunk = match pending.pop_front() {
+                Some(chunk) => chunk,
+                None => break,
+            };
+            match conn.receive() {
+                Ok((Response::Read { bytes_read: read }, data)) => {
+                    let len = data.len().min(chunk.len());
+                    chunk[..len].copy_from_slice(&data[..len]);
+                    *bytes_read += read;
+                }
+                Ok((response, _)) => {
+                    first_error.get_or_insert(RemoteDriverError::unexpected(response));
+                }
+                Err(e @ RemoteDriverError::Server(_)) => {
+                    first_error.get_or_insert(e);
+                }
+                Err(e) => return Err(Box::new(e)),
+            }
+        }
+        match first_error {
+            Some(e) => Err(Box::new(e)),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Introspectable for Remote {
+    fn get_vcpu_count(&self) -> Result<u16, Box<dyn Error>> {
+        remote_call!(self, Request::GetVcpuCount, Response::VcpuCount(count) => count)
+    }
+
+    fn read_physical(
+        &self,
+        paddr: u64,
+        buf: &mut [u8],
+        bytes_read: &mut u64,
+    ) -> Result<(), Box<dyn Error>> {
+        self.read_memory(buf, bytes_read, |offset, size| Request::ReadPhysical {
+            paddr: paddr + offset,
+            size,
+        })
+    }
+
+    fn write_physical(&self, paddr: u64, buf: &[u8]) -> Result<(), Box<dyn Error>> {
+        let mut conn = self.conn.borrow_mut();
+        let mut chunks = buf.chunks(CHUNK_SIZE);
+        let mut pending = 0;
+        let mut offset = 0;
+        let mut first_error = None;
+        loop {
+            while pending < PIPELINE_DEPTH {
+                let chunk = match chunks.next() {
+                    Some(chunk) => chunk,
+                    None => break,
+                };
+                let request = Request::WritePhysical {
+                    paddr: paddr + offset,
+                };
+                conn.send(&request, chunk)?;
+                offset += chunk.len() as u64;
+                pending += 1;
+            }
+            if pending == 0 {
+                break;
+            }
+            pending -= 1;
+            match conn.receive() {
+                Ok((Response::Done, _)) => (),
+                Ok((response, _)) => {
+                    first_error.get_or_insert(RemoteDriverError::unexpected(response));
+                }
+                Err(e @ RemoteDriverError::Server(_)) => {
+                    first_error.get_or_insert(e);
+                }
+                Err(e) => return Err(Box::new(e)),
+            }
+        }
+        match first_error {
+            Some(e) => Err(Box::new(e)),
+            None => Ok(()),
+        }
+    }
+
+    fn get_max_physical_addr(&self) -> Result<u64, Box<dyn Error>> {
+        remote_call!(self, Request::GetMaxPhysicalAddr, Response::MaxPhysicalAddr(addr) => addr)
+    }
+
+    fn read_registers(&self, vcpu: u16) -> Result<Registers, Box<dyn Error>> {
+        remote_call!(self, Request::ReadRegisters { vcpu }, Response::Registers(registers) => registers)
+    }
+
+    fn get_page_access(&self, paddr: u64) -> Result<Access, Box<dyn Error>> {
+        remote_call!(self, Request::GetPageAccess { paddr }, Response::PageAccess(access) => access)
+    }
+
+    fn set_page_access(&self, paddr: u64, access: Access) -> Result<(), Box<dyn Error>> {
+        remote_call!(self, Request::SetPageAccess { paddr, access }, Response::Done => ())
+    }
+
+    fn set_page_access_range(
+        &self,
+        start: u64,
+        end: u64,
+        access: Access,
+    ) -> Result<(), Box<dyn Error>> {
+        remote_call!(self, Request::SetPageAccessRange { start, end, access }, Response::Done => ())
+    }
+
+    fn set_page_access_multi(&self, pages: &[(u64, Access)]) -> Result<(), Box<dyn Error>> {
+        let request = Request::SetPageAccessMulti {
+            pages: pages.to_vec(),
+        };
+        remote_call!(self, request, Response::Done => ())
+    }
+
+    fn create_view(&mut self) -> Result<u16, Box<dyn Error>> {
+        remote_call!(self, Request::CreateView, Response::View(view) => view)
+    }
+
+    fn destroy_view(&mut self, view: u16) -> Result<(), Box<dyn Error>> {
+        remote_call!(self, Request::DestroyView { view }, Response::Done => ())
+    }
+
+    fn switch_view(&mut self, vcpu: u16, view: u16) -> Result<(), Box<dyn Error>> {
+        remote_call!(self, Request::SwitchView { vcpu, view }, Response::Done => ())
+    }
+
+    fn set_page_access_in_view(
+        &self,
+        view: u16,
+        paddr: u64,
+        access: Access,
+    ) -> Result<(), Box<dyn Error>> {
+        let request = Request::SetPageAccessInView {
+            view,
+            paddr,
+            access,
+        };
+        remote_call!(self, request, Response::Done => ())
+    }
+
+    fn remap_gfn(&mut self, view: u16, from: u64, to: u64) -> Result<(), Box<dyn Error>> {
+        remote_call!(self, Request::RemapGfn { view, from, to }, Response::Done => ())
+    }
+
+    fn write_registers(&self, vcpu: u16, reg: Registers) -> Result<(), Box<dyn Error>> {
+        let request = Request::WriteRegisters {
+            vcpu,
+            registers: reg,
+        };
+        remote_call!(self, request, Response::Done => ())
+    }
+
+    fn pause(&mut self) -> Result<(), Box<dyn Error>> {
+        remote_call!(self, Request::Pause, Response::Done => ())
+    }
+
+    fn resume(&mut self) -> Result<(), Box<dyn Error>> {
+        remote_call!(self, Request::Resume, Response::Done => ())
+    }
+
+    fn inject_interrupt(
+        &mut self,
+        vcpu: u16,
+        vector: u8,
+        error_code: Option<u32>,
+        cr2: u64,
+    ) -> Result<(), Box<dyn Error>> {
+        let request = Request::InjectInterrupt {
+            vcpu,
+            vector,
+            error_code,
+            cr2,
+        };
+        remote_call!(self, request, Response::Done => ())
+    }
+
+    fn toggle_intercept(
+        &mut self,
+        vcpu: u16,
+        intercept_type: InterceptType,
+        enabled: bool,
+    ) -> Result<(), Box<dyn Error>> {
+        let request = Request::ToggleIntercept {
+            vcpu,
+            intercept_type,
+            enabled,
+        };
+        remote_call!(self, request, Response::Done => ())
+    }
+
+    fn get_active_intercepts(&self, vcpu: u16) -> Result<Vec<InterceptType>, Box<dyn Error>> {
+        remote_call!(self, Request::GetActiveIntercepts { vcpu }, Response::Intercepts(intercepts) => intercepts)
+    }
+
+    fn restore_guest_state(&mut self) -> Result<(), Box<dyn Error>> {
+        remote_call!(self, Request::RestoreGuestState, Response::Done => ())
+    }
+
+    fn listen(&mut self, timeout: u32) -> Result<Option<Event>, Box<dyn Error>> {
+        let conn = self.conn.get_mut();
+        if !conn.events.is_empty() {
+            return Ok(conn.next_event()?);
+        }
+        if !conn.subscribed {
+            match conn.call(&Request::Subscribe { enabled: true })? {
+                (Response::Done, _) => conn.subscribed = true,
+                (response, _) => return Err(RemoteDriverError::unexpected(response).into()),
+            }
+        }
+        Ok(conn.wait_event(timeout)?)
+    }
+
+    fn reply_event(
+        &mut self,
+        event: Event,
+        reply_type: EventReplyType,
+    ) -> Result<(), Box<dyn Error>> {
+        remote_call!(self, Request::ReplyEvent { event, reply_type }, Response::Done => ())
+    }
+
+    fn list_processes(&self) -> Result<Vec<ProcessInfo>, Box<dyn Error>> {
+        remote_call!(self, Request::ListProcesses, Response::Processes(processes) => processes)
+    }
+
+    fn list_modules(&self, pid: u32) -> Result<Vec<ModuleInfo>, Box<dyn Error>> {
+        remote_call!(self, Request::ListModules { pid }, Response::Modules(modules) => modules)
+    }
+
+    fn read_virtual(
+        &self,
+        pid: u32,
+        vaddr: u64,
+        buf: &mut [u8],
+        bytes_read: &mut u64,
+    ) -> Result<(), Box<dyn Error>> {
+        self.read_memory(buf, bytes_read, |offset, size| Request::ReadVirtual {
+            pid,
+            vaddr: vaddr + offset,
+            size,
+        })
+    }
+
+    fn get_driver_type(&self) -> DriverType {
+        DriverType::Remote
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::params::RemoteInitParams;
+    use test_case::test_case;
+
+    fn init_params(addr: &str, token: &str) -> DriverInitParams {
+        DriverInitParams {
+            remote: Some(RemoteInitParams {
+                addr: addr.to_string(),
+                token: token.to_string(),
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test_case("", "secret"; "missing address")]
+    #[test_case("tcp://127.0.0.1:1", ""; "missing token")]
+    #[test_case("127.0.0.1:7475", "secret"; "missing scheme")]
+    #[test_case("http://127.0.0.1:7475", "secret"; "unknown scheme")]
+    fn test_invalid_parameters(addr: &str, token: &str) {
+        let error = Remote::new(init_params(addr, token)).err().unwrap();
+        let error = error.downcast_ref::<RemoteDriverError>().unwrap();
+        assert_eq!(
+            InitFailureReason::MissingParameter,
+            error.init_failure_reason()
+        );
+    }
+
+    #[test]
+    fn test_missing_parameters() {
+        let error = Remote::new(DriverInitParams::default()).err().unwrap();
+        assert!(matches!(
+            error.downcast_ref::<RemoteDriverError>(),
+            Some(RemoteDriverError::MissingAddress)
+        ));
+    }
+}
@@ -0,0 +1,209 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashSet};
+use std::error::Error;
+use std::mem;
+
+use crate::api::events::InterceptType;
+use crate::api::{Access, PAGE_SIZE};
+
+const PAGE_MASK: u64 = !(PAGE_SIZE as u64 - 1);
+
+/// Guest state changed by a driver, recorded to be reported and restored
+#[derive(Debug, Default)]
+pub struct GuestState {
+    // VCPU -> active intercepts
+    intercepts: Vec<HashSet<InterceptType>>,
+    // (view, first page address) -> (end address, access before the driver modified it)
+    // the ranges don't overlap, and the page access is set through a shared reference
+    original_access: RefCell<BTreeMap<(u16, u64), (u64, Access)>>,
+}
+
+impl GuestState {
+    pub fn new(vcpu_count: u16) -> Self {
+        GuestState {
+            intercepts: vec![HashSet::new(); vcpu_count.into()],
+            original_access: RefCell::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn set_intercept(&mut self, vcpu: u16, intercept_type: InterceptType, enabled: bool) {
+        let vcpu_index: usize = vcpu.into();
+        if vcpu_index >= self.intercepts.len() {
+            self.intercepts.resize_with(vcpu_index + 1, HashSet::new);
+        }
+        if enabled {
+            self.intercepts[vcpu_index].insert(intercept_type);
+        } else {
+            self.intercepts[vcpu_index].remove(&intercept_type);
+        }
+    }
+
+    pub fn is_intercepted(&self, vcpu: u16, intercept_type: InterceptType) -> bool {
+        self.intercepts
+            .get(usize::from(vcpu))
+            .is_some_and(|intercepts| intercepts.contains(&intercept_type))
+    }
+
+    /// Whether the intercept is enabled on any VCPU but the given one
+    pub fn is_intercepted_elsewhere(&self, vcpu: u16, intercept_type: InterceptType) -> bool {
+        self.intercepts
+            .iter()
+            .enumerate()
+            .any(|(index, intercepts)| {
+                index != usize::from(vcpu) && intercepts.contains(&intercept_type)
+            })
+    }
+
+    pub fn active_intercepts(&self, vcpu: u16) -> Vec<InterceptType> {
+        self.intercepts
+            .get(usize::from(vcpu))
+            .map_or_else(Vec::new, |intercepts| intercepts.iter().copied().collect())
+    }
+
+    /// Record the access of the pages in the range before it is modified
+    ///
+    /// Only the pages which are not recorded yet are queried with `get_access`, so that the
+    /// access restored is the one the guest had before any modification.
+    pub fn record_original_access<F>(
+        &self,
+        view: u16,
+        start: u64,
+        end: u64,
+        mut get_access: F,
+    ) -> Result<(), Box<dyn Error>>
+    where
+        F: FnMut(u64) -> Result<Access, Box<dyn Error>>,
+    {
+        let mut original_access = self.original_access.borrow_mut();
+        let end = (end + PAGE_SIZE as u64 - 1) & PAGE_MASK;
+        let mut paddr = start & PAGE_MASK;
+        while paddr < end {
+            // skip the recorded range containing this page
+            if let Some((_, (range_end, _))) = original_access
+                .range((view, 0)..=(view, paddr))
+                .next_back()
+                .filter(|(_, (range_end, _))| *range_end > paddr)
+            {
+                paddr = *range_end;
+                continue;
+            }
+            // query the pages up to the next recorded range
+            let gap_end = original_access
+                .range((view, paddr)..=(view, u64::MAX))
+                .next()
+                .map_or(end, |((_, range_start), _)| (*range_start).min(end));
+            let mut run: Option<(u64, u64, Access)> = None;
+            while paddr < gap_end {
+                let access = get_access(paddr)?;
+                run = match run {
+                    Some((run_start, _, run_access)) if run_access == access => {
+                        Some((run_start, paddr + PAGE_SIZE as u64, access))
+                    }
+                    _ => {
+                        if let Some((run_start, run_end, run_access)) = run {
+                            original_access.insert((view, run_start), (run_end, run_access));
+                        }
+                        Some((paddr, paddr + PAGE_SIZE as u64, access))
+                    }
+                };
+                paddr += PAGE_SIZE as u64;
+            }
+            if let Some((run_start, run_end, run_access)) = run {
+                original_access.insert((view, run_start), (run_end, run_access));
+            }
+        }
+        Ok(())
+    }
+
+    /// The recorded ranges, as (view, start, end, original access), which are then forgotten
+    pub fn take_original_access(&self) -> Vec<(u16, u64, u64, Access)> {
+        mem::take(&mut *self.original_access.borrow_mut())
+            .into_iter()
+            .map(|((view, start), (end, access))| (view, start, end, access))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_first_recorded_access_is_kept() {
+        let state = GuestState::default();
+        state
+            .record_original_access(0, 0x1000, 0x3000, |_| Ok(Access::RX))
+            .unwrap();
+
+        // already recorded pages are not queried again
+        state
+            .record_original_access(0, 0x2000, 0x4000, |paddr| {
+                assert_eq!(paddr, 0x3000);
+                Ok(Access::RWX)
+            })
+            .unwrap();
+
+        assert_eq!(
+            state.take_original_access(),
+            vec![
+                (0, 0x1000, 0x3000, Access::RX),
+                (0, 0x3000, 0x4000, Access::RWX)
+            ]
+        );
+        assert!(state.take_original_access().is_empty());
+    }
+
+    #[test]
+    fn test_gaps_between_recorded_ranges_are_queried() {
+        let state = GuestState::default();
+        state
+            .record_original_access(0, 0x2000, 0x2001, |_| Ok(Access::R))
+            .unwrap();
+        state
+            .record_original_access(1, 0x1000, 0x2000, |_| Ok(Access::R))
+            .unwrap();
+        let mut queried = Vec::new();
+
+        state
+            .record_original_access(0, 0x1000, 0x4000, |paddr| {
+                queried.push(paddr);
+                Ok(Access::RWX)
+            })
+            .unwrap();
+
+        assert_eq!(queried, vec![0x1000, 0x3000]);
+        assert_eq!(
+            state.take_original_access(),
+            vec![
+                (0, 0x1000, 0x2000, Access::RWX),
+                (0, 0x2000, 0x3000, Access::R),
+                (0, 0x3000, 0x4000, Access::RWX),
+                (1, 0x1000, 0x2000, Access::R),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_pages_with_different_access_are_split() {
+        let state = GuestState::default();
+
+        state
+            .record_original_access(0, 0x0, 0x3000, |paddr| {
+                Ok(if paddr == 0x1000 {
+                    Access::RW
+                } else {
+                    Access::RWX
+                })
+            })
+            .unwrap();
+
+        assert_eq!(
+            state.take_original_access(),
+            vec![
+                (0, 0x0, 0x1000, Access::RWX),
+                (0, 0x1000, 0x2000, Access::RW),
+                (0, 0x2000, 0x3000, Access::RWX),
+            ]
+        );
+    }
+}
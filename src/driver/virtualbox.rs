@@ -1,20 +1,67 @@
 use std::error::Error;
+use std::process::Command;
 
 use fdp::{RegisterType, FDP};
 
 use crate::api::params::DriverInitParams;
 use crate::api::registers::{Registers, SegmentReg, SystemTableReg, X86Registers};
+use crate::api::vms::VmInfo;
 use crate::api::{DriverType, Introspectable, PAGE_SIZE};
+use crate::driver::pause::PauseCounter;
+use crate::errors::InitFailureReason;
 
 #[derive(Debug)]
 pub struct VBox {
     fdp: FDP,
+    pause_count: PauseCounter,
 }
 
 #[derive(thiserror::Error, Debug)]
 pub enum VBoxDriverError {
     #[error("VirtualBox driver requires a VM name parameter")]
     MissingVMName,
+    #[error("failed to list VirtualBox machines: {0}")]
+    ListVmsError(String),
+}
+
+impl VBoxDriverError {
+    pub(crate) fn init_failure_reason(&self) -> InitFailureReason {
+        match self {
+            VBoxDriverError::MissingVMName => InitFailureReason::MissingParameter,
+            _ => InitFailureReason::Other,
+        }
+    }
+}
+
+const VBOXMANAGE: &str = "VBoxManage";
+
+/// Whether VBoxManage can be run
+pub fn probe() -> bool {
+    Command::new(VBOXMANAGE)
+        .arg("--version")
+        .output()
+        .is_ok_and(|output| output.status.success())
+}
+
+/// List the running VirtualBox machines
+pub fn list_vms() -> Result<Vec<VmInfo>, Box<dyn Error>> {
+    let output = Command::new(VBOXMANAGE)
+        .args(["list", "runningvms"])
+        .output()?;
+    if !output.status.success() {
+        return Err(Box::new(VBoxDriverError::ListVmsError(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        )));
+    }
+    // "<name>" {<uuid>}
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| line.strip_prefix('"')?.rsplit_once("\" {"))
+        .map(|(name, _)| VmInfo {
+            name: name.to_string(),
+            id: None,
+        })
+        .collect())
 }
 
 impl VBox {
@@ -26,7 +73,10 @@ impl VBox {
 
         // init FDP
         let fdp = FDP::new(&domain_name)?;
-        Ok(VBox { fdp })
+        Ok(VBox {
+            fdp,
+            pause_count: PauseCounter::default(),
+        })
     }
 }
 
@@ -117,11 +167,13 @@ impl Introspectable for VBox {
     }
 
     fn pause(&mut self) -> Result<(), Box<dyn Error>> {
-        self.fdp.pause()
+        let fdp = &self.fdp;
+        self.pause_count.pause(|| fdp.pause())
     }
 
     fn resume(&mut self) -> Result<(), Box<dyn Error>> {
-        self.fdp.resume()
+        let fdp = &self.fdp;
+        self.pause_count.resume(|| fdp.resume())
     }
 
     fn get_driver_type(&self) -> DriverType {
@@ -1,6 +1,8 @@
 use std::convert::Infallible;
+use std::convert::TryFrom;
 use std::convert::TryInto;
 use std::error::Error;
+use std::fmt;
 use std::io::Error as IoError;
 use std::io::ErrorKind;
 use std::mem;
@@ -12,37 +14,387 @@ use nix::poll::{poll, PollFd};
 use xenctrl::consts::{PAGE_SHIFT, PAGE_SIZE};
 use xenctrl::error::XcError;
 use xenctrl::RING_HAS_UNCONSUMED_REQUESTS;
-use xenctrl::{XenControl, XenCr, XenEventType};
+use xenctrl::{
+    xenmem_access_t, XenControl, XenCr, XenEventType, XEN_DOMCTL_DEBUG_OP_SINGLE_STEP_OFF,
+    XEN_DOMCTL_DEBUG_OP_SINGLE_STEP_ON,
+};
 use xenevtchn::XenEventChannel;
 use xenforeignmemory::{XenForeignMem, XenForeignMemoryError};
 use xenstore_rs::{XBTransaction, Xs, XsOpenFlags};
 use xenvmevent_sys::{
-    vm_event_back_ring, vm_event_response_t, VM_EVENT_FLAG_VCPU_PAUSED, VM_EVENT_INTERFACE_VERSION,
+    vm_event_back_ring, vm_event_request_t, vm_event_response_t, MEM_ACCESS_R, MEM_ACCESS_W,
+    MEM_ACCESS_X, VM_EVENT_DESC_GDTR, VM_EVENT_DESC_IDTR, VM_EVENT_DESC_LDTR, VM_EVENT_DESC_TR,
+    VM_EVENT_FLAG_ALTERNATE_P2M, VM_EVENT_FLAG_EMULATE, VM_EVENT_FLAG_SET_REGISTERS,
+    VM_EVENT_FLAG_VCPU_PAUSED, VM_EVENT_INTERFACE_VERSION, VM_EVENT_REASON_CPUID,
+    VM_EVENT_REASON_DESCRIPTOR_ACCESS, VM_EVENT_REASON_IO_INSTRUCTION, VM_EVENT_REASON_MEM_ACCESS,
+    VM_EVENT_REASON_MOV_TO_MSR, VM_EVENT_REASON_SINGLESTEP, VM_EVENT_REASON_SOFTWARE_BREAKPOINT,
+    VM_EVENT_REASON_WRITE_CTRLREG, VM_EVENT_X86_XCR0,
 };
 
-use crate::api::events::{CrType, Event, EventType, InterceptType};
-use crate::api::params::DriverInitParams;
+use crate::api::events::{CrType, DescriptorType, Event, EventReplyType, EventType, InterceptType};
+use crate::api::params::{DriverInitParams, XenInitParams, XenMonitorRing};
 use crate::api::registers::{Registers, SegmentReg, SystemTableReg, X86Registers};
-use crate::api::{DriverType, Introspectable};
+use crate::api::vms::VmInfo;
+use crate::api::{Access, DriverType, Introspectable};
+use crate::driver::libxenctrl::{LibXenCtrl, Trap};
+use crate::driver::pause::PauseCounter;
+use crate::driver::state::GuestState;
+use crate::errors::InitFailureReason;
+
+impl TryFrom<Access> for xenmem_access_t {
+    type Error = &'static str;
+    fn try_from(access: Access) -> Result<Self, Self::Error> {
+        match access {
+            Access::NIL => Ok(xenmem_access_t::XENMEM_access_n),
+            Access::R => Ok(xenmem_access_t::XENMEM_access_r),
+            Access::W => Ok(xenmem_access_t::XENMEM_access_w),
+            Access::RW => Ok(xenmem_access_t::XENMEM_access_rw),
+            Access::X => Ok(xenmem_access_t::XENMEM_access_x),
+            Access::RX => Ok(xenmem_access_t::XENMEM_access_rx),
+            Access::WX => Ok(xenmem_access_t::XENMEM_access_wx),
+            Access::RWX => Ok(xenmem_access_t::XENMEM_access_rwx),
+            _ => Err("invalid access value"),
+        }
+    }
+}
+
+impl TryFrom<xenmem_access_t> for Access {
+    type Error = &'static str;
+    fn try_from(access: xenmem_access_t) -> Result<Self, Self::Error> {
+        match access {
+            xenmem_access_t::XENMEM_access_n => Ok(Access::NIL),
+            xenmem_access_t::XENMEM_access_r => Ok(Access::R),
+            xenmem_access_t::XENMEM_access_w => Ok(Access::W),
+            xenmem_access_t::XENMEM_access_rw => Ok(Access::RW),
+            xenmem_access_t::XENMEM_access_x => Ok(Access::X),
+            xenmem_access_t::XENMEM_access_rx => Ok(Access::RX),
+            xenmem_access_t::XENMEM_access_wx => Ok(Access::WX),
+            // the domain default access is unrestricted
+            xenmem_access_t::XENMEM_access_rwx | xenmem_access_t::XENMEM_access_default => {
+                Ok(Access::RWX)
+            }
+            _ => Err("access cannot be represented"),
+        }
+    }
+}
+
+/// xenctrl calls involved in event and page access translation,
+/// abstracted to be mocked in tests
+pub trait XenIntrospectable: fmt::Debug {
+    fn get_request(
+        &self,
+        back_ring: &mut vm_event_back_ring,
+    ) -> Result<vm_event_request_t, XcError>;
+    fn put_response(
+        &self,
+        rsp: &mut vm_event_response_t,
+        back_ring: &mut vm_event_back_ring,
+    ) -> Result<(), XcError>;
+    fn get_event_type(&self, req: vm_event_request_t) -> Result<XenEventType, XcError>;
+    fn get_mem_access(&self, domid: u32, pfn: u64) -> Result<xenmem_access_t, XcError>;
+    fn set_mem_access(
+        &self,
+        domid: u32,
+        access: xenmem_access_t,
+        first_pfn: u64,
+        nr: u32,
+    ) -> Result<(), XcError>;
+}
+
+impl XenIntrospectable for XenControl {
+    fn get_request(
+        &self,
+        back_ring: &mut vm_event_back_ring,
+    ) -> Result<vm_event_request_t, XcError> {
+        XenControl::get_request(self, back_ring)
+    }
+
+    fn put_response(
+        &self,
+        rsp: &mut vm_event_response_t,
+        back_ring: &mut vm_event_back_ring,
+    ) -> Result<(), XcError> {
+        XenControl::put_response(self, rsp, back_ring)
+    }
+
+    fn get_event_type(&self, req: vm_event_request_t) -> Result<XenEventType, XcError> {
+        XenControl::get_event_type(self, req)
+    }
+
+    fn get_mem_access(&self, domid: u32, pfn: u64) -> Result<xenmem_access_t, XcError> {
+        XenControl::get_mem_access(self, domid, pfn)
+    }
+
+    fn set_mem_access(
+        &self,
+        domid: u32,
+        access: xenmem_access_t,
+        first_pfn: u64,
+        nr: u32,
+    ) -> Result<(), XcError> {
+        XenControl::set_mem_access(self, domid, access, first_pfn, nr)
+    }
+}
+
+/// Translate a vm_event request into a libmicrovmi event
+fn event_from_request<T: XenIntrospectable>(
+    xc: &T,
+    req: vm_event_request_t,
+) -> Result<EventType, XenDriverError> {
+    let page_offset = u64::from(PAGE_SIZE - 1) & unsafe { req.data.regs.x86.rip };
+    let event_type = match req.reason {
+        // xenctrl doesn't report the physical address, and expects the access flags alone
+        VM_EVENT_REASON_MEM_ACCESS => {
+            let mem_access = unsafe { req.u.mem_access };
+            let mut access = Access::NIL;
+            if mem_access.flags & MEM_ACCESS_R != 0 {
+                access |= Access::R;
+            }
+            if mem_access.flags & MEM_ACCESS_W != 0 {
+                access |= Access::W;
+            }
+            if mem_access.flags & MEM_ACCESS_X != 0 {
+                access |= Access::X;
+            }
+            EventType::Pagefault {
+                gva: mem_access.gla,
+                gpa: (mem_access.gfn << PAGE_SHIFT) | mem_access.offset,
+                access,
+                view: req.altp2m_idx,
+            }
+        }
+        // xenctrl only knows the CR0, CR3 and CR4 indexes
+        VM_EVENT_REASON_WRITE_CTRLREG
+            if unsafe { req.u.write_ctrlreg.index } == VM_EVENT_X86_XCR0 =>
+        {
+            EventType::Xsetbv {
+                xcr: 0,
+                new: unsafe { req.u.write_ctrlreg.new_value },
+            }
+        }
+        VM_EVENT_REASON_CPUID => {
+            let cpuid = unsafe { req.u.cpuid };
+            EventType::Cpuid {
+                leaf: cpuid.leaf,
+                subleaf: cpuid.subleaf,
+                insn_len: cpuid.insn_length.try_into()?,
+            }
+        }
+        VM_EVENT_REASON_DESCRIPTOR_ACCESS => {
+            let desc_access = unsafe { req.u.desc_access };
+            EventType::Descriptor {
+                descriptor: match desc_access.descriptor.into() {
+                    VM_EVENT_DESC_IDTR => DescriptorType::Idtr,
+                    VM_EVENT_DESC_GDTR => DescriptorType::Gdtr,
+                    VM_EVENT_DESC_LDTR => DescriptorType::Ldtr,
+                    VM_EVENT_DESC_TR => DescriptorType::Tr,
+                    _ => return Err(XenDriverError::UnsupportedEvent(req.reason)),
+                },
+                write: desc_access.is_write != 0,
+            }
+        }
+        VM_EVENT_REASON_IO_INSTRUCTION => {
+            let io = unsafe { req.u.io };
+            EventType::Io {
+                port: io.port,
+                size: io.bytes,
+                input: io.in_ != 0,
+                string: io.str_ != 0,
+            }
+        }
+        VM_EVENT_REASON_WRITE_CTRLREG
+        | VM_EVENT_REASON_MOV_TO_MSR
+        | VM_EVENT_REASON_SOFTWARE_BREAKPOINT
+        | VM_EVENT_REASON_SINGLESTEP => match xc.get_event_type(req)? {
+            XenEventType::Cr { cr_type, new, old } => EventType::Cr {
+                cr_type: match cr_type {
+                    XenCr::Cr0 => CrType::Cr0,
+                    XenCr::Cr3 => CrType::Cr3,
+                    XenCr::Cr4 => CrType::Cr4,
+                },
+                new,
+                old,
+            },
+            XenEventType::Msr { msr_type, value } => EventType::Msr { msr_type, value },
+            // Xen only reports the frame number, complete it with the page offset of RIP
+            XenEventType::Breakpoint { gfn, insn_len, .. } => EventType::Breakpoint {
+                gpa: (gfn << PAGE_SHIFT) | page_offset,
+                insn_len,
+            },
+            XenEventType::Singlestep { gfn } => EventType::Singlestep {
+                gpa: (gfn << PAGE_SHIFT) | page_offset,
+            },
+            XenEventType::Pagefault { .. } => {
+                return Err(XenDriverError::UnsupportedEvent(req.reason))
+            }
+        },
+        _ => return Err(XenDriverError::UnsupportedEvent(req.reason)),
+    };
+    Ok(event_type)
+}
+
+/// Pop the next request from the ring and translate it
+///
+/// A request which can't be translated is answered right away, so that its VCPU doesn't stay
+/// paused, and the error is returned. The caller must notify the event channel.
+fn pop_request<T: XenIntrospectable>(
+    xc: &T,
+    back_ring: &mut vm_event_back_ring,
+) -> Result<(vm_event_request_t, EventType), XenDriverError> {
+    let req = xc.get_request(back_ring)?;
+    let result = if req.version != VM_EVENT_INTERFACE_VERSION {
+        Err(XenDriverError::EventVersionMismatch(
+            req.version,
+            VM_EVENT_INTERFACE_VERSION,
+        ))
+    } else {
+        event_from_request(xc, req)
+    };
+    match result {
+        Ok(event_type) => Ok((req, event_type)),
+        Err(e) => {
+            xc.put_response(&mut response_from_request(&req), back_ring)?;
+            Err(e)
+        }
+    }
+}
+
+/// Build a response resuming the VCPU on the instruction which triggered the event
+fn response_from_request(req: &vm_event_request_t) -> vm_event_response_t {
+    let mut rsp = unsafe { mem::MaybeUninit::<vm_event_response_t>::zeroed().assume_init() };
+    rsp.reason = req.reason;
+    rsp.version = VM_EVENT_INTERFACE_VERSION;
+    rsp.vcpu_id = req.vcpu_id;
+    rsp.altp2m_idx = req.altp2m_idx;
+    rsp.flags = req.flags & VM_EVENT_FLAG_VCPU_PAUSED;
+    rsp
+}
+
+fn get_page_access<T: XenIntrospectable>(
+    xc: &T,
+    domid: u32,
+    paddr: u64,
+) -> Result<Access, Box<dyn Error>> {
+    let xen_access = xc
+        .get_mem_access(domid, paddr >> PAGE_SHIFT)
+        .map_err(XenDriverError::from)?;
+    Ok(xen_access.try_into()?)
+}
+
+fn set_page_access_range<T: XenIntrospectable>(
+    xc: &T,
+    domid: u32,
+    start: u64,
+    end: u64,
+    access: Access,
+) -> Result<(), Box<dyn Error>> {
+    if end <= start {
+        return Ok(());
+    }
+    let xen_access: xenmem_access_t = access.try_into()?;
+    let first_pfn = start >> PAGE_SHIFT;
+    let last_pfn = (end - 1) >> PAGE_SHIFT;
+    let mut pfn = first_pfn;
+    // nr is limited to 32 bits
+    while pfn <= last_pfn {
+        let nr = u32::try_from(last_pfn - pfn + 1).unwrap_or(u32::MAX);
+        xc.set_mem_access(domid, xen_access, pfn, nr)
+            .map_err(XenDriverError::from)?;
+        pfn += u64::from(nr);
+    }
+    Ok(())
+}
+
+fn set_page_access_multi<T: XenIntrospectable>(
+    xc: &T,
+    domid: u32,
+    pages: &[(u64, Access)],
+) -> Result<(), Box<dyn Error>> {
+    // xc_set_mem_access_multi is not exposed by xenctrl,
+    // set each run of contiguous pages sharing the same access in a single call
+    let mut pages_iter = pages.iter().peekable();
+    while let Some((paddr, access)) = pages_iter.next() {
+        let first_pfn = paddr >> PAGE_SHIFT;
+        let mut nr: u32 = 1;
+        while let Some((next_paddr, next_access)) = pages_iter.peek() {
+            if next_access != access
+                || next_paddr >> PAGE_SHIFT != first_pfn + u64::from(nr)
+                || nr == u32::MAX
+            {
+                break;
+            }
+            nr += 1;
+            pages_iter.next();
+        }
+        xc.set_mem_access(domid, (*access).try_into()?, first_pfn, nr)
+            .map_err(XenDriverError::from)?;
+    }
+    Ok(())
+}
 
 #[derive(Debug)]
 pub struct Xen {
     xc: XenControl,
-    xev: XenEventChannel,
+    // libxenctrl functions not wrapped by xenctrl
+    libxc: LibXenCtrl,
     xen_fgn: XenForeignMem,
-    _dom_name: String,
+    // None when attached by domain ID
+    _dom_name: Option<String>,
     domid: u32,
+    // not set up in read-only mode, or when disabled
+    monitor: Option<XenMonitor>,
+    // VCPU -> pending vm_event request
+    vec_events: Vec<Option<XenRequest>>,
+    // VCPU -> view to switch to when the pending request is answered
+    view_switches: Vec<Option<u16>>,
+    // altp2m views created by create_view
+    views: Vec<u16>,
+    state: GuestState,
+    pause_count: PauseCounter,
+}
+
+/// The vm_event ring used to receive events and send responses
+#[derive(Debug)]
+struct XenMonitor {
+    xev: XenEventChannel,
     back_ring: vm_event_back_ring,
 }
 
+/// A vm_event request waiting for its response
+#[derive(Copy, Clone)]
+struct XenRequest(vm_event_request_t);
+
+impl fmt::Debug for XenRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("XenRequest")
+            .field("reason", &self.0.reason)
+            .field("vcpu_id", &self.0.vcpu_id)
+            .finish()
+    }
+}
+
 #[derive(thiserror::Error, Debug)]
 pub enum XenDriverError {
-    #[error("Xen driver requires a VM name parameter")]
+    #[error("Xen driver requires a VM name or a domain ID parameter")]
     MissingVMName,
+    #[error("invalid domain ID {0}")]
+    InvalidDomId(u32),
     #[error("failed to read xenstore entry {0}: {1}")]
     XenstoreReadError(String, IoError),
     #[error("domain {0} not found in xenstore")]
     XenstoreDomainNotFoundError(String),
+    #[error("intercept {0:?} is not supported by the Xen driver")]
+    UnsupportedIntercept(InterceptType),
+    #[error("event reply {0} is not supported by the Xen driver for this event")]
+    UnsupportedReply(String),
+    #[error("registers {0:?} can't be set by an event reply on the Xen driver")]
+    UnsupportedRegisters(Vec<&'static str>),
+    #[error("no pending event on VCPU {0}")]
+    NoPendingEvent(u16),
+    #[error("vm_event reason {0} is not supported by the Xen driver")]
+    UnsupportedEvent(u32),
+    #[error("events are not available without the monitor ring (read-only mode or monitor ring disabled)")]
+    NoMonitorRing,
     #[error("event version mismatch: {0} <-> {1}")]
     EventVersionMismatch(u32, u32),
     #[error("failed to convert integer")]
@@ -53,72 +405,218 @@ pub enum XenDriverError {
     IoError(#[from] IoError),
     #[error("xenctrl error")]
     XcError(#[from] XcError),
+    #[error("failed to load libxenctrl")]
+    LibXenCtrlError(#[from] libloading::Error),
+    #[error("{0} failed")]
+    XcCallError(&'static str, #[source] IoError),
     #[error("UNIX error")]
     NixError(#[from] nix::Error),
     #[error("xenforeignmemory error")]
     ForeignMemoryError(#[from] XenForeignMemoryError),
 }
 
-impl Xen {
-    pub fn new(init_params: DriverInitParams) -> Result<Self, Box<dyn Error>> {
-        let domain_name = init_params
-            .common
-            .ok_or(XenDriverError::MissingVMName)?
-            .vm_name;
-        // find domain name in xenstore
-        let xs = Xs::new(XsOpenFlags::ReadOnly)?;
-        let mut found: bool = false;
-        let mut cand_domid = 0;
-        for domid_str in xs.directory(XBTransaction::Null, "/local/domain")? {
-            let name_path = format!("/local/domain/{}/name", domid_str);
-            let candidate = match xs.read(XBTransaction::Null, &name_path) {
-                Ok(candidate) => candidate,
-                Err(error) => {
-                    match error.kind() {
-                        ErrorKind::PermissionDenied => {
-                            // the domain has access to Xenstore only to a subset of the ids available
-                            // we should continue
-                            debug!("failed to read xenstore entry {}", name_path);
-                            continue;
-                        }
-                        _ => {
-                            return Err(Box::new(XenDriverError::XenstoreReadError(
-                                name_path, error,
-                            )))
-                        }
+impl XenDriverError {
+    pub(crate) fn init_failure_reason(&self) -> InitFailureReason {
+        match self {
+            XenDriverError::MissingVMName | XenDriverError::InvalidDomId(_) => {
+                InitFailureReason::MissingParameter
+            }
+            XenDriverError::XenstoreDomainNotFoundError(_) => InitFailureReason::VmNotFound,
+            XenDriverError::XenstoreReadError(_, e) | XenDriverError::IoError(e) => {
+                InitFailureReason::from_io_error(e)
+            }
+            // the xenctrl interface could not be opened
+            XenDriverError::XcError(_)
+            | XenDriverError::LibXenCtrlError(_)
+            | XenDriverError::XcCallError(_, _) => InitFailureReason::HypervisorNotPresent,
+            _ => InitFailureReason::Other,
+        }
+    }
+}
+
+/// Whether xenstore can be opened
+pub fn probe() -> bool {
+    Xs::new(XsOpenFlags::ReadOnly).is_ok()
+}
+
+/// List the guest domains registered in xenstore, dom0 excluded
+pub fn list_vms() -> Result<Vec<VmInfo>, Box<dyn Error>> {
+    let xs = Xs::new(XsOpenFlags::ReadOnly)?;
+    Ok(xenstore_domains(&xs)?
+        .into_iter()
+        .filter(|(domid, _)| *domid != 0)
+        .map(|(domid, name)| VmInfo {
+            name,
+            id: Some(domid),
+        })
+        .collect())
+}
+
+/// Walk xenstore /local/domain and return the ID and name of each domain
+fn xenstore_domains(xs: &Xs) -> Result<Vec<(u32, String)>, XenDriverError> {
+    let mut domains = Vec::new();
+    for domid_str in xs.directory(XBTransaction::Null, "/local/domain")? {
+        let name_path = format!("/local/domain/{}/name", domid_str);
+        let name = match xs.read(XBTransaction::Null, &name_path) {
+            Ok(name) => name,
+            Err(error) => {
+                match error.kind() {
+                    ErrorKind::PermissionDenied => {
+                        // the domain has access to Xenstore only to a subset of the ids available
+                        // we should continue
+                        debug!("failed to read xenstore entry {}", name_path);
+                        continue;
                     }
+                    _ => return Err(XenDriverError::XenstoreReadError(name_path, error)),
                 }
-            };
-            debug!("Xenstore entry: [{}] {}", domid_str, candidate);
-            if candidate == *domain_name {
-                cand_domid = domid_str.parse::<u32>().unwrap();
-                found = true;
             }
+        };
+        debug!("Xenstore entry: [{}] {}", domid_str, name);
+        let domid = match domid_str.parse::<u32>() {
+            Ok(domid) => domid,
+            Err(_) => continue,
+        };
+        domains.push((domid, name));
+    }
+    Ok(domains)
+}
+
+/// first reserved domain ID (DOMID_FIRST_RESERVED), the IDs above are not guest domains
+const DOMID_FIRST_RESERVED: u32 = 0x7FF0;
+
+/// The domain to attach to
+#[derive(Debug, PartialEq)]
+enum XenDomain {
+    Id(u32),
+    // looked up in xenstore
+    Name(String),
+}
+
+/// The domain to attach to, and whether the monitor ring should be set up
+#[derive(Debug, PartialEq)]
+struct XenTarget {
+    domain: XenDomain,
+    monitor_ring: bool,
+}
+
+/// Validate the initialization parameters
+///
+/// A domain ID takes precedence over the VM name.
+fn parse_init_params(init_params: DriverInitParams) -> Result<XenTarget, XenDriverError> {
+    let (domid, monitor_ring) = match init_params.xen {
+        None => (None, XenMonitorRing::Enabled),
+        Some(XenInitParams::DomId(domid)) => (Some(domid), XenMonitorRing::Enabled),
+        Some(XenInitParams::Options {
+            domid,
+            monitor_ring,
+        }) => (domid, monitor_ring),
+    };
+    let read_only = init_params
+        .common
+        .as_ref()
+        .is_some_and(|common| common.read_only);
+    let domain = match domid {
+        Some(domid) if domid >= DOMID_FIRST_RESERVED => {
+            return Err(XenDriverError::InvalidDomId(domid))
         }
-        if !found {
-            return Err(Box::new(XenDriverError::XenstoreDomainNotFoundError(
-                domain_name,
-            )));
-        }
+        Some(domid) => XenDomain::Id(domid),
+        None => XenDomain::Name(
+            init_params
+                .common
+                .map(|common| common.vm_name)
+                .filter(|vm_name| !vm_name.is_empty())
+                .ok_or(XenDriverError::MissingVMName)?,
+        ),
+    };
+    Ok(XenTarget {
+        domain,
+        // the monitor ring allows to modify the guest through event responses
+        monitor_ring: !read_only && monitor_ring == XenMonitorRing::Enabled,
+    })
+}
 
-        let mut xc = XenControl::new(None, None, 0)?;
-        let (_ring_page, back_ring, remote_port) = xc.monitor_enable(cand_domid)?;
-        let xev = XenEventChannel::new(cand_domid, remote_port)?;
+impl Xen {
+    pub fn new(init_params: DriverInitParams) -> Result<Self, Box<dyn Error>> {
+        let target = parse_init_params(init_params)?;
+        let (domain_name, cand_domid) = match target.domain {
+            XenDomain::Id(domid) => (None, domid),
+            XenDomain::Name(domain_name) => {
+                // find domain name in xenstore
+                let xs = Xs::new(XsOpenFlags::ReadOnly)?;
+                let domid = xenstore_domains(&xs)?
+                    .into_iter()
+                    .find(|(_, name)| *name == domain_name)
+                    .map(|(domid, _)| domid)
+                    .ok_or_else(|| {
+                        XenDriverError::XenstoreDomainNotFoundError(domain_name.clone())
+                    })?;
+                (Some(domain_name), domid)
+            }
+        };
 
+        let mut xc = XenControl::new(None, None, 0).map_err(XenDriverError::XcError)?;
+        let monitor = if target.monitor_ring {
+            let (_ring_page, back_ring, remote_port) = xc.monitor_enable(cand_domid)?;
+            let xev = XenEventChannel::new(cand_domid, remote_port)?;
+            Some(XenMonitor { xev, back_ring })
+        } else {
+            None
+        };
+
+        let libxc = LibXenCtrl::new()?;
         let xen_fgn = XenForeignMem::new()?;
-        let xen = Xen {
+        let mut xen = Xen {
             xc,
-            xev,
+            libxc,
             xen_fgn,
             _dom_name: domain_name,
             domid: cand_domid,
-            back_ring,
+            monitor,
+            vec_events: Vec::new(),
+            view_switches: Vec::new(),
+            views: Vec::new(),
+            state: GuestState::default(),
+            pause_count: PauseCounter::default(),
         };
+        // set vec_events size
+        let vcpu_count = xen.get_vcpu_count()?;
+        xen.vec_events.resize_with(vcpu_count.into(), || None);
+        xen.view_switches.resize_with(vcpu_count.into(), || None);
+        xen.state = GuestState::new(vcpu_count);
         trace!("Initialized {:#?}", xen);
         Ok(xen)
     }
 }
 
+impl Xen {
+    fn send_response(
+        &mut self,
+        vcpu_index: usize,
+        mut rsp: vm_event_response_t,
+    ) -> Result<(), Box<dyn Error>> {
+        let monitor = self.monitor.as_mut().ok_or(XenDriverError::NoMonitorRing)?;
+        if let Some(view) = self.view_switches[vcpu_index].take() {
+            rsp.flags |= VM_EVENT_FLAG_ALTERNATE_P2M;
+            rsp.altp2m_idx = view;
+        }
+        self.xc
+            .put_response(&mut rsp, &mut monitor.back_ring)
+            .map_err(XenDriverError::from)?;
+        monitor
+            .xev
+            .xenevtchn_notify()
+            .map_err(XenDriverError::from)?;
+        self.vec_events[vcpu_index] = None;
+        Ok(())
+    }
+
+    fn record_original_access(&self, start: u64, end: u64) -> Result<(), Box<dyn Error>> {
+        self.state.record_original_access(0, start, end, |paddr| {
+            get_page_access(&self.xc, self.domid, paddr)
+        })
+    }
+}
+
 impl Introspectable for Xen {
     fn read_physical(
         &self,
@@ -363,7 +861,8 @@ impl Introspectable for Xen {
     }
 
     fn listen(&mut self, timeout: u32) -> Result<Option<Event>, Box<dyn Error>> {
-        let fd = self.xev.xenevtchn_fd().map_err(XenDriverError::from)?;
+        let monitor = self.monitor.as_mut().ok_or(XenDriverError::NoMonitorRing)?;
+        let fd = monitor.xev.xenevtchn_fd().map_err(XenDriverError::from)?;
         let fd_struct = PollFd::new(fd, PollFlags::POLLIN | PollFlags::POLLERR);
         let mut fds = [fd_struct];
         let mut vcpu: u16 = 0;
@@ -371,9 +870,13 @@ impl Introspectable for Xen {
         let poll_result = poll(&mut fds, timeout.try_into().map_err(XenDriverError::from)?)?;
         let mut pending_event_port = -1;
         if poll_result == 1 {
-            pending_event_port = self.xev.xenevtchn_pending().map_err(XenDriverError::from)?;
+            pending_event_port = monitor
+                .xev
+                .xenevtchn_pending()
+                .map_err(XenDriverError::from)?;
             if pending_event_port != -1 {
-                self.xev
+                monitor
+                    .xev
                     .xenevtchn_unmask(
                         pending_event_port
                             .try_into()
@@ -382,49 +885,59 @@ impl Introspectable for Xen {
                     .map_err(XenDriverError::from)?;
             }
         }
-        let back_ring_ptr = &mut self.back_ring;
+        let back_ring_ptr = &mut monitor.back_ring;
         let mut flag = false;
         if poll_result > 0
-            && self.xev.get_bind_port() == pending_event_port
+            && monitor.xev.get_bind_port() == pending_event_port
             && RING_HAS_UNCONSUMED_REQUESTS!(back_ring_ptr) != 0
         {
             flag = true;
-            let req = self.xc.get_request(back_ring_ptr)?;
-            if req.version != VM_EVENT_INTERFACE_VERSION {
-                return Err(Box::new(XenDriverError::EventVersionMismatch(
-                    req.version,
-                    VM_EVENT_INTERFACE_VERSION,
-                )));
-            }
-            let xen_event_type = (self.xc.get_event_type(req)).map_err(XenDriverError::from)?;
-            event_type = match xen_event_type {
-                XenEventType::Cr { cr_type, new, old } => EventType::Cr {
-                    cr_type: match cr_type {
-                        XenCr::Cr0 => CrType::Cr0,
-                        XenCr::Cr3 => CrType::Cr3,
-                        XenCr::Cr4 => CrType::Cr4,
-                    },
-                    new,
-                    old,
-                },
-                XenEventType::Msr { msr_type, value } => EventType::Msr { msr_type, value },
-                XenEventType::Breakpoint { insn_len, .. } => {
-                    EventType::Breakpoint { gpa: 0, insn_len }
+            let (req, translated) = match pop_request(&self.xc, back_ring_ptr) {
+                Ok(popped) => popped,
+                Err(e) => {
+                    monitor
+                        .xev
+                        .xenevtchn_notify()
+                        .map_err(XenDriverError::from)?;
+                    return Err(Box::new(e));
                 }
-                _ => unimplemented!(),
             };
+            event_type = translated;
             vcpu = req.vcpu_id.try_into().map_err(XenDriverError::from)?;
-            let mut rsp =
-                unsafe { mem::MaybeUninit::<vm_event_response_t>::zeroed().assume_init() };
-            rsp.reason = req.reason;
-            rsp.version = VM_EVENT_INTERFACE_VERSION;
-            rsp.vcpu_id = req.vcpu_id;
-            rsp.flags = req.flags & VM_EVENT_FLAG_VCPU_PAUSED;
-            self.xc
-                .put_response(&mut rsp, &mut self.back_ring)
-                .map_err(XenDriverError::from)?;
+            // the response will be sent by reply_event
+            let vcpu_index: usize = vcpu.into();
+            self.vec_events[vcpu_index] = Some(XenRequest(req));
+            // filter out the events of VCPUs which didn't enable the intercept,
+            // an access which is not intercepted is allowed
+            let (intercept_type, reply_type) = match event_type {
+                EventType::Cr { cr_type, .. } => {
+                    (Some(InterceptType::Cr(cr_type)), EventReplyType::Continue)
+                }
+                EventType::Msr { msr_type, .. } => {
+                    (Some(InterceptType::Msr(msr_type)), EventReplyType::Continue)
+                }
+                EventType::Pagefault { .. } => {
+                    (Some(InterceptType::Pagefault), EventReplyType::Emulate)
+                }
+                EventType::Cpuid { .. } => (Some(InterceptType::Cpuid), EventReplyType::Continue),
+                EventType::Descriptor { .. } => {
+                    (Some(InterceptType::Descriptor), EventReplyType::Continue)
+                }
+                EventType::Io { .. } => (Some(InterceptType::Io), EventReplyType::Continue),
+                EventType::Xsetbv { .. } => (Some(InterceptType::Xsetbv), EventReplyType::Continue),
+                _ => (None, EventReplyType::Continue),
+            };
+            if let Some(intercept_type) = intercept_type {
+                if !self.state.is_intercepted(vcpu, intercept_type) {
+                    let event = Event {
+                        vcpu,
+                        kind: event_type,
+                    };
+                    self.reply_event(event, reply_type)?;
+                    return Ok(None);
+                }
+            }
         }
-        self.xev.xenevtchn_notify().map_err(XenDriverError::from)?;
         if flag {
             Ok(Some(Event {
                 vcpu,
@@ -435,12 +948,175 @@ impl Introspectable for Xen {
         }
     }
 
+    fn reply_event(
+        &mut self,
+        event: Event,
+        reply_type: EventReplyType,
+    ) -> Result<(), Box<dyn Error>> {
+        let vcpu_index: usize = event.vcpu.into();
+        let XenRequest(req) =
+            self.vec_events[vcpu_index].ok_or(XenDriverError::NoPendingEvent(event.vcpu))?;
+        let mut rsp = response_from_request(&req);
+        match reply_type {
+            // without any flag, the VCPU resumes on the instruction which triggered the event
+            EventReplyType::Continue | EventReplyType::Retry => {}
+            EventReplyType::Emulate => rsp.flags |= VM_EVENT_FLAG_EMULATE,
+            EventReplyType::SkipInstruction => {
+                let insn_len = unsafe {
+                    match req.reason {
+                        VM_EVENT_REASON_SOFTWARE_BREAKPOINT => {
+                            req.u.software_breakpoint.insn_length
+                        }
+                        VM_EVENT_REASON_CPUID => req.u.cpuid.insn_length,
+                        _ => {
+                            return Err(Box::new(XenDriverError::UnsupportedReply(format!(
+                                "{:?}",
+                                reply_type
+                            ))))
+                        }
+                    }
+                };
+                let mut regs = unsafe { req.data.regs.x86 };
+                regs.rip += u64::from(insn_len);
+                rsp.data.regs.x86 = regs;
+                rsp.flags |= VM_EVENT_FLAG_SET_REGISTERS;
+            }
+            EventReplyType::SetRegisters(Registers::X86(x86_registers)) => {
+                // only the general purpose registers, RIP and RFLAGS are applied by Xen
+                let Registers::X86(current) = self.read_registers(event.vcpu)?;
+                let unapplied = x86_registers.unapplied_on_reply(&current);
+                if !unapplied.is_empty() {
+                    return Err(Box::new(XenDriverError::UnsupportedRegisters(unapplied)));
+                }
+                let mut regs = unsafe { req.data.regs.x86 };
+                regs.rax = x86_registers.rax;
+                regs.rbx = x86_registers.rbx;
+                regs.rcx = x86_registers.rcx;
+                regs.rdx = x86_registers.rdx;
+                regs.rsi = x86_registers.rsi;
+                regs.rdi = x86_registers.rdi;
+                regs.rsp = x86_registers.rsp;
+                regs.rbp = x86_registers.rbp;
+                regs.r8 = x86_registers.r8;
+                regs.r9 = x86_registers.r9;
+                regs.r10 = x86_registers.r10;
+                regs.r11 = x86_registers.r11;
+                regs.r12 = x86_registers.r12;
+                regs.r13 = x86_registers.r13;
+                regs.r14 = x86_registers.r14;
+                regs.r15 = x86_registers.r15;
+                regs.rip = x86_registers.rip;
+                regs.rflags = x86_registers.rflags;
+                rsp.data.regs.x86 = regs;
+                rsp.flags |= VM_EVENT_FLAG_SET_REGISTERS;
+            }
+            // the breakpoint is delivered to the guest before the VCPU resumes
+            EventReplyType::Reinject => match req.reason {
+                VM_EVENT_REASON_SOFTWARE_BREAKPOINT => {
+                    let insn_len = unsafe { req.u.software_breakpoint.insn_length };
+                    self.libxc.hvm_inject_trap(
+                        self.domid,
+                        event.vcpu,
+                        Trap::breakpoint(insn_len.try_into()?),
+                    )?;
+                }
+                _ => {
+                    return Err(Box::new(XenDriverError::UnsupportedReply(format!(
+                        "{:?}",
+                        reply_type
+                    ))))
+                }
+            },
+        }
+        self.send_response(vcpu_index, rsp)
+    }
+
+    fn get_page_access(&self, paddr: u64) -> Result<Access, Box<dyn Error>> {
+        get_page_access(&self.xc, self.domid, paddr)
+    }
+
+    fn set_page_access(&self, paddr: u64, access: Access) -> Result<(), Box<dyn Error>> {
+        self.record_original_access(paddr, paddr + 1)?;
+        set_page_access_range(&self.xc, self.domid, paddr, paddr + 1, access)
+    }
+
+    fn set_page_access_range(
+        &self,
+        start: u64,
+        end: u64,
+        access: Access,
+    ) -> Result<(), Box<dyn Error>> {
+        self.record_original_access(start, end)?;
+        set_page_access_range(&self.xc, self.domid, start, end, access)
+    }
+
+    fn set_page_access_multi(&self, pages: &[(u64, Access)]) -> Result<(), Box<dyn Error>> {
+        for (paddr, _) in pages {
+            self.record_original_access(*paddr, paddr + 1)?;
+        }
+        set_page_access_multi(&self.xc, self.domid, pages)
+    }
+
+    fn create_view(&mut self) -> Result<u16, Box<dyn Error>> {
+        if self.views.is_empty() {
+            self.libxc.altp2m_set_domain_state(self.domid, true)?;
+        }
+        let view = self.libxc.altp2m_create_view(self.domid)?;
+        self.views.push(view);
+        Ok(view)
+    }
+
+    fn destroy_view(&mut self, view: u16) -> Result<(), Box<dyn Error>> {
+        self.libxc.altp2m_destroy_view(self.domid, view)?;
+        self.views.retain(|created| *created != view);
+        if self.views.is_empty() {
+            self.libxc.altp2m_set_domain_state(self.domid, false)?;
+        }
+        Ok(())
+    }
+
+    // Xen only switches the view of a single VCPU through a vm_event response
+    fn switch_view(&mut self, vcpu: u16, view: u16) -> Result<(), Box<dyn Error>> {
+        let vcpu_index: usize = vcpu.into();
+        if !matches!(self.vec_events.get(vcpu_index), Some(Some(_))) {
+            return Err(Box::new(XenDriverError::NoPendingEvent(vcpu)));
+        }
+        self.view_switches[vcpu_index] = Some(view);
+        Ok(())
+    }
+
+    fn set_page_access_in_view(
+        &self,
+        view: u16,
+        paddr: u64,
+        access: Access,
+    ) -> Result<(), Box<dyn Error>> {
+        if view == 0 {
+            return self.set_page_access(paddr, access);
+        }
+        self.libxc.altp2m_set_mem_access(
+            self.domid,
+            view,
+            paddr >> PAGE_SHIFT,
+            access.try_into()?,
+        )?;
+        Ok(())
+    }
+
+    fn remap_gfn(&mut self, view: u16, from: u64, to: u64) -> Result<(), Box<dyn Error>> {
+        Ok(self
+            .libxc
+            .altp2m_change_gfn(self.domid, view, from >> PAGE_SHIFT, to >> PAGE_SHIFT)?)
+    }
+
     fn toggle_intercept(
         &mut self,
-        _vcpu: u16,
+        vcpu: u16,
         intercept_type: InterceptType,
         enabled: bool,
     ) -> Result<(), Box<dyn Error>> {
+        // monitoring is domain wide, and stays enabled while any VCPU intercepts the event
+        let update_domain = !self.state.is_intercepted_elsewhere(vcpu, intercept_type);
         match intercept_type {
             InterceptType::Cr(micro_cr_type) => {
                 let xen_cr = match micro_cr_type {
@@ -448,37 +1124,147 @@ impl Introspectable for Xen {
                     CrType::Cr3 => XenCr::Cr3,
                     CrType::Cr4 => XenCr::Cr4,
                 };
-                Ok(self
-                    .xc
-                    .monitor_write_ctrlreg(self.domid, xen_cr, enabled, true, true)
-                    .map_err(XenDriverError::from)?)
+                if update_domain {
+                    self.xc
+                        .monitor_write_ctrlreg(self.domid, xen_cr, enabled, true, true)
+                        .map_err(XenDriverError::from)?;
+                }
+            }
+            InterceptType::Msr(micro_msr_type) => {
+                if update_domain {
+                    self.xc
+                        .monitor_mov_to_msr(self.domid, micro_msr_type, enabled)
+                        .map_err(XenDriverError::from)?;
+                }
+            }
+            InterceptType::Breakpoint => {
+                if update_domain {
+                    self.xc
+                        .monitor_software_breakpoint(self.domid, enabled)
+                        .map_err(XenDriverError::from)?;
+                }
+            }
+            // mem_access events are always delivered once the monitor ring is enabled,
+            // listen emulates the accesses of the VCPUs which didn't enable the intercept
+            InterceptType::Pagefault => {
+                if self.monitor.is_none() {
+                    return Err(Box::new(XenDriverError::NoMonitorRing));
+                }
+            }
+            InterceptType::Singlestep => {
+                // singlestep events are enabled domain wide, and toggled per VCPU
+                if enabled || update_domain {
+                    self.xc
+                        .monitor_singlestep(self.domid, enabled)
+                        .map_err(XenDriverError::from)?;
+                }
+                let op = if enabled {
+                    XEN_DOMCTL_DEBUG_OP_SINGLE_STEP_ON
+                } else {
+                    XEN_DOMCTL_DEBUG_OP_SINGLE_STEP_OFF
+                };
+                self.xc
+                    .domain_debug_control(self.domid, op, vcpu.into())
+                    .map_err(XenDriverError::from)?;
+            }
+            InterceptType::Cpuid => {
+                if update_domain {
+                    self.libxc.monitor_cpuid(self.domid, enabled)?;
+                }
+            }
+            InterceptType::Descriptor => {
+                if update_domain {
+                    self.libxc.monitor_descriptor_access(self.domid, enabled)?;
+                }
+            }
+            InterceptType::Io => {
+                if update_domain {
+                    self.libxc.monitor_io(self.domid, enabled)?;
+                }
+            }
+            InterceptType::Xsetbv => {
+                if update_domain {
+                    self.libxc.monitor_write_ctrlreg(
+                        self.domid,
+                        VM_EVENT_X86_XCR0.try_into()?,
+                        enabled,
+                    )?;
+                }
+            }
+            // VMCALL is only reported for the HVMOP_guest_request_vm_event hypercall
+            InterceptType::Hypercall => {
+                return Err(Box::new(XenDriverError::UnsupportedIntercept(
+                    intercept_type,
+                )))
+            }
+        }
+        self.state.set_intercept(vcpu, intercept_type, enabled);
+        Ok(())
+    }
+
+    fn get_active_intercepts(&self, vcpu: u16) -> Result<Vec<InterceptType>, Box<dyn Error>> {
+        Ok(self.state.active_intercepts(vcpu))
+    }
+
+    fn restore_guest_state(&mut self) -> Result<(), Box<dyn Error>> {
+        debug!("restore guest state");
+        for vcpu in 0..self.get_vcpu_count()? {
+            for intercept_type in self.state.active_intercepts(vcpu) {
+                self.toggle_intercept(vcpu, intercept_type, false)?;
+            }
+        }
+        // the other views are destroyed below
+        for (_, start, end, access) in self.state.take_original_access() {
+            set_page_access_range(&self.xc, self.domid, start, end, access)?;
+        }
+        // resume the VCPUs waiting for a reply, in the default view
+        for vcpu_index in 0..self.vec_events.len() {
+            if let Some(XenRequest(req)) = self.vec_events[vcpu_index] {
+                if !self.views.is_empty() {
+                    self.view_switches[vcpu_index] = Some(0);
+                }
+                let rsp = response_from_request(&req);
+                self.send_response(vcpu_index, rsp)?;
             }
-            InterceptType::Msr(micro_msr_type) => Ok(self
-                .xc
-                .monitor_mov_to_msr(self.domid, micro_msr_type, enabled)
-                .map_err(XenDriverError::from)?),
-            InterceptType::Breakpoint => Ok(self
-                .xc
-                .monitor_software_breakpoint(self.domid, enabled)
-                .map_err(XenDriverError::from)?),
-            _ => unimplemented!(),
         }
+        if !self.views.is_empty() {
+            self.libxc.altp2m_switch_to_view(self.domid, 0)?;
+            for view in mem::take(&mut self.views) {
+                self.libxc.altp2m_destroy_view(self.domid, view)?;
+            }
+            self.libxc.altp2m_set_domain_state(self.domid, false)?;
+        }
+        Ok(())
     }
 
     fn pause(&mut self) -> Result<(), Box<dyn Error>> {
         debug!("pause");
-        Ok(self
-            .xc
-            .domain_pause(self.domid)
-            .map_err(XenDriverError::from)?)
+        let (xc, domid) = (&self.xc, self.domid);
+        self.pause_count
+            .pause(|| xc.domain_pause(domid))
+            .map_err(XenDriverError::from)?;
+        Ok(())
     }
 
     fn resume(&mut self) -> Result<(), Box<dyn Error>> {
         debug!("resume");
-        Ok(self
-            .xc
-            .domain_unpause(self.domid)
-            .map_err(XenDriverError::from)?)
+        let (xc, domid) = (&self.xc, self.domid);
+        self.pause_count
+            .resume(|| xc.domain_unpause(domid))
+            .map_err(XenDriverError::from)?;
+        Ok(())
+    }
+
+    fn inject_interrupt(
+        &mut self,
+        vcpu: u16,
+        vector: u8,
+        error_code: Option<u32>,
+        cr2: u64,
+    ) -> Result<(), Box<dyn Error>> {
+        self.libxc
+            .hvm_inject_trap(self.domid, vcpu, Trap::from_vector(vector, error_code, cr2))?;
+        Ok(())
     }
 
     fn get_driver_type(&self) -> DriverType {
@@ -489,8 +1275,305 @@ impl Introspectable for Xen {
 impl Drop for Xen {
     fn drop(&mut self) {
         debug!("Closing Xen driver");
-        self.xc
-            .monitor_disable(self.domid)
-            .expect("Failed to unmap event ring page");
+        if let Err(e) = self.restore_guest_state() {
+            error!("Failed to restore guest state: {}", e);
+        }
+        if self.monitor.is_some() {
+            if let Err(e) = self.xc.monitor_disable(self.domid) {
+                error!("Failed to disable the monitor ring: {}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mockall::mock;
+    use mockall::predicate::{always, eq, function};
+    use test_case::test_case;
+    use xenvmevent_sys::VM_EVENT_REASON_GUEST_REQUEST;
+
+    use super::*;
+    use crate::api::params::CommonInitParams;
+
+    fn request(reason: u32) -> vm_event_request_t {
+        let mut req = unsafe { mem::MaybeUninit::<vm_event_request_t>::zeroed().assume_init() };
+        req.version = VM_EVENT_INTERFACE_VERSION;
+        req.reason = reason;
+        req
+    }
+
+    #[test]
+    fn test_mem_access_request_translates_to_pagefault() {
+        let xc_mock = MockXenControl::default();
+        let mut req = request(VM_EVENT_REASON_MEM_ACCESS);
+        req.u.mem_access.gfn = 0x1234;
+        req.u.mem_access.offset = 0x56;
+        req.u.mem_access.gla = 0xfffff80000001056;
+        req.u.mem_access.flags = MEM_ACCESS_W | MEM_ACCESS_X;
+
+        let event_type = event_from_request(&xc_mock, req).unwrap();
+
+        assert!(matches!(
+            event_type,
+            EventType::Pagefault {
+                gva: 0xfffff80000001056,
+                gpa: 0x1234056,
+                access: Access::WX,
+                view: 0,
+            }
+        ));
+    }
+
+    #[test]
+    fn test_xcr0_write_translates_to_xsetbv() {
+        let mut xc_mock = MockXenControl::default();
+        // xenctrl doesn't know the XCR0 index
+        xc_mock.expect_get_event_type().never();
+        let mut req = request(VM_EVENT_REASON_WRITE_CTRLREG);
+        req.u.write_ctrlreg.index = VM_EVENT_X86_XCR0;
+        req.u.write_ctrlreg.new_value = 0x7;
+
+        let event_type = event_from_request(&xc_mock, req).unwrap();
+
+        assert!(matches!(event_type, EventType::Xsetbv { xcr: 0, new: 0x7 }));
+    }
+
+    #[test]
+    fn test_cpuid_request_translates_to_cpuid() {
+        let xc_mock = MockXenControl::default();
+        let mut req = request(VM_EVENT_REASON_CPUID);
+        req.u.cpuid.insn_length = 2;
+        req.u.cpuid.leaf = 0x4000_0000;
+        req.u.cpuid.subleaf = 1;
+
+        let event_type = event_from_request(&xc_mock, req).unwrap();
+
+        assert!(matches!(
+            event_type,
+            EventType::Cpuid {
+                leaf: 0x4000_0000,
+                subleaf: 1,
+                insn_len: 2,
+            }
+        ));
+    }
+
+    #[test_case(VM_EVENT_DESC_IDTR, DescriptorType::Idtr; "idtr")]
+    #[test_case(VM_EVENT_DESC_GDTR, DescriptorType::Gdtr; "gdtr")]
+    #[test_case(VM_EVENT_DESC_LDTR, DescriptorType::Ldtr; "ldtr")]
+    #[test_case(VM_EVENT_DESC_TR, DescriptorType::Tr; "tr")]
+    fn test_descriptor_request_translates_to_descriptor(
+        xen_descriptor: u32,
+        expected: DescriptorType,
+    ) {
+        let xc_mock = MockXenControl::default();
+        let mut req = request(VM_EVENT_REASON_DESCRIPTOR_ACCESS);
+        req.u.desc_access.descriptor = xen_descriptor.try_into().unwrap();
+        req.u.desc_access.is_write = 1;
+
+        let event_type = event_from_request(&xc_mock, req).unwrap();
+
+        assert!(matches!(
+            event_type,
+            EventType::Descriptor { descriptor, write: true } if descriptor == expected
+        ));
+    }
+
+    #[test]
+    fn test_io_request_translates_to_io() {
+        let xc_mock = MockXenControl::default();
+        let mut req = request(VM_EVENT_REASON_IO_INSTRUCTION);
+        req.u.io.port = 0x3f8;
+        req.u.io.bytes = 1;
+        req.u.io.in_ = 0;
+        req.u.io.str_ = 1;
+
+        let event_type = event_from_request(&xc_mock, req).unwrap();
+
+        assert!(matches!(
+            event_type,
+            EventType::Io {
+                port: 0x3f8,
+                size: 1,
+                input: false,
+                string: true,
+            }
+        ));
+    }
+
+    #[test]
+    fn test_unsupported_request_returns_error() {
+        let mut xc_mock = MockXenControl::default();
+        xc_mock.expect_get_event_type().never();
+
+        let result = event_from_request(&xc_mock, request(VM_EVENT_REASON_GUEST_REQUEST));
+
+        assert!(result.is_err(), "Expected error, got ok instead!");
+    }
+
+    #[test]
+    fn test_unsupported_request_is_answered() {
+        let mut xc_mock = MockXenControl::default();
+        xc_mock.expect_get_request().times(1).returning(|_| {
+            let mut req = request(VM_EVENT_REASON_GUEST_REQUEST);
+            req.vcpu_id = 1;
+            req.flags = VM_EVENT_FLAG_VCPU_PAUSED;
+            Ok(req)
+        });
+        xc_mock
+            .expect_put_response()
+            .withf(|rsp, _| {
+                rsp.vcpu_id == 1
+                    && rsp.reason == VM_EVENT_REASON_GUEST_REQUEST
+                    && rsp.flags == VM_EVENT_FLAG_VCPU_PAUSED
+            })
+            .times(1)
+            .returning(|_, _| Ok(()));
+        let mut back_ring =
+            unsafe { mem::MaybeUninit::<vm_event_back_ring>::zeroed().assume_init() };
+
+        let result = pop_request(&xc_mock, &mut back_ring);
+
+        assert!(matches!(
+            result,
+            Err(XenDriverError::UnsupportedEvent(
+                VM_EVENT_REASON_GUEST_REQUEST
+            ))
+        ));
+    }
+
+    #[test]
+    fn test_get_page_access_translates_xen_access() {
+        let mut xc_mock = MockXenControl::default();
+        xc_mock
+            .expect_get_mem_access()
+            .with(eq(1), eq(0x1234))
+            .times(1)
+            .returning(|_, _| Ok(xenmem_access_t::XENMEM_access_rx));
+
+        let access = get_page_access(&xc_mock, 1, 0x1234567).unwrap();
+
+        assert_eq!(access, Access::RX);
+    }
+
+    #[test]
+    fn test_set_page_access_multi_merges_contiguous_pages() {
+        let mut xc_mock = MockXenControl::default();
+        xc_mock
+            .expect_set_mem_access()
+            .with(
+                eq(1),
+                function(|x| *x == xenmem_access_t::XENMEM_access_r),
+                eq(0x10),
+                eq(2),
+            )
+            .times(1)
+            .returning(|_, _, _, _| Ok(()));
+        xc_mock
+            .expect_set_mem_access()
+            .with(
+                eq(1),
+                function(|x| *x == xenmem_access_t::XENMEM_access_rw),
+                eq(0x12),
+                always(),
+            )
+            .times(1)
+            .returning(|_, _, _, _| Ok(()));
+
+        let result = set_page_access_multi(
+            &xc_mock,
+            1,
+            &[
+                (0x10000, Access::R),
+                (0x11000, Access::R),
+                (0x12000, Access::RW),
+            ],
+        );
+
+        assert!(result.is_ok(), "Expected ok, got error instead!");
+    }
+
+    fn init_params(
+        vm_name: Option<&str>,
+        read_only: bool,
+        xen: Option<XenInitParams>,
+    ) -> DriverInitParams {
+        DriverInitParams {
+            common: vm_name.map(|vm_name| CommonInitParams {
+                vm_name: String::from(vm_name),
+                read_only,
+                audit_log: None,
+            }),
+            xen,
+            ..Default::default()
+        }
+    }
+
+    #[test_case(init_params(Some("win10"), false, None), XenDomain::Name(String::from("win10")), true; "vm name")]
+    #[test_case(init_params(Some("win10"), false, Some(XenInitParams::DomId(5))), XenDomain::Id(5), true; "domid takes precedence")]
+    #[test_case(init_params(None, false, Some(XenInitParams::Options { domid: Some(5), monitor_ring: XenMonitorRing::Disabled })), XenDomain::Id(5), false; "monitor ring disabled")]
+    #[test_case(init_params(Some("win10"), true, Some(XenInitParams::DomId(5))), XenDomain::Id(5), false; "read-only")]
+    fn test_parse_init_params(
+        init_params: DriverInitParams,
+        domain: XenDomain,
+        monitor_ring: bool,
+    ) {
+        assert_eq!(
+            parse_init_params(init_params).unwrap(),
+            XenTarget {
+                domain,
+                monitor_ring
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_invalid_init_params() {
+        let reserved = init_params(None, false, Some(XenInitParams::DomId(0x7FF0)));
+        let missing = init_params(
+            Some(""),
+            false,
+            Some(XenInitParams::Options {
+                domid: None,
+                monitor_ring: XenMonitorRing::Enabled,
+            }),
+        );
+
+        assert!(matches!(
+            parse_init_params(reserved),
+            Err(XenDriverError::InvalidDomId(0x7FF0))
+        ));
+        assert!(matches!(
+            parse_init_params(missing),
+            Err(XenDriverError::MissingVMName)
+        ));
+    }
+
+    mock! {
+        XenControl{}
+        impl fmt::Debug for XenControl {
+            fn fmt<'a>(&self, f: &mut fmt::Formatter<'a>) -> fmt::Result;
+        }
+        impl XenIntrospectable for XenControl {
+            fn get_request(
+                &self,
+                back_ring: &mut vm_event_back_ring,
+            ) -> Result<vm_event_request_t, XcError>;
+            fn put_response(
+                &self,
+                rsp: &mut vm_event_response_t,
+                back_ring: &mut vm_event_back_ring,
+            ) -> Result<(), XcError>;
+            fn get_event_type(&self, req: vm_event_request_t) -> Result<XenEventType, XcError>;
+            fn get_mem_access(&self, domid: u32, pfn: u64) -> Result<xenmem_access_t, XcError>;
+            fn set_mem_access(
+                &self,
+                domid: u32,
+                access: xenmem_access_t,
+                first_pfn: u64,
+                nr: u32,
+            ) -> Result<(), XcError>;
+        }
     }
 }
@@ -1,15 +1,197 @@
+use crate::api::config::ConfigError;
+use crate::api::url::UrlError;
 use crate::api::DriverType;
+#[cfg(feature = "kvm")]
+use crate::driver::kvm::KVMDriverError;
+#[cfg(feature = "mflow")]
+use crate::driver::memflow::MemflowDriverError;
+#[cfg(feature = "remote")]
+use crate::driver::remote::RemoteDriverError;
+#[cfg(feature = "virtualbox")]
+use crate::driver::virtualbox::VBoxDriverError;
+#[cfg(feature = "xen")]
+use crate::driver::xen::XenDriverError;
 use std::error::Error;
+use std::fmt;
+use std::io::{self, ErrorKind};
 
 #[derive(thiserror::Error, Debug)]
 pub enum MicrovmiError {
-    #[error("no suitable microvmi driver available")]
-    NoDriverAvailable,
+    #[error("no suitable microvmi driver available{}", DisplayFailures(.0))]
+    NoDriverAvailable(Vec<DriverInitError>),
     #[error("driver {0:?} has not been compiled")]
     DriverNotCompiled(DriverType),
+    #[error("invalid URL: {0}")]
+    InvalidUrl(#[from] UrlError),
+    #[error("invalid configuration: {0}")]
+    InvalidConfig(#[from] ConfigError),
     #[error("{source}")]
     Other {
         #[from]
         source: Box<dyn Error>,
     },
 }
+
+/// An Introspectable operation which the driver doesn't implement
+#[derive(thiserror::Error, Debug)]
+#[error("{operation} is not supported by the {driver_type:?} driver")]
+pub struct UnsupportedOperation {
+    pub driver_type: DriverType,
+    pub operation: &'static str,
+}
+
+/// Why a driver failed to initialize
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum InitFailureReason {
+    /// a required initialization parameter is missing or invalid
+    MissingParameter,
+    /// the hypervisor interface is present, but access has been denied
+    PermissionDenied,
+    /// the hypervisor is present, but the VM could not be found
+    VmNotFound,
+    /// the hypervisor interface could not be found
+    HypervisorNotPresent,
+    /// any other failure
+    Other,
+}
+
+impl fmt::Display for InitFailureReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let reason = match self {
+            InitFailureReason::MissingParameter => "missing parameter",
+            InitFailureReason::PermissionDenied => "permission denied",
+            InitFailureReason::VmNotFound => "VM not found",
+            InitFailureReason::HypervisorNotPresent => "hypervisor not present",
+            InitFailureReason::Other => "initialization failed",
+        };
+        f.write_str(reason)
+    }
+}
+
+impl InitFailureReason {
+    /// Classify a driver initialization error
+    ///
+    /// The driver errors are looked up along the error source chain.
+    pub fn from_error(error: &MicrovmiError) -> Self {
+        match error {
+            MicrovmiError::Other { source } => Self::from_source(source.as_ref()),
+            _ => InitFailureReason::Other,
+        }
+    }
+
+    fn from_source(error: &(dyn Error + 'static)) -> Self {
+        #[cfg(feature = "kvm")]
+        if let Some(e) = error.downcast_ref::<KVMDriverError>() {
+            return e.init_failure_reason();
+        }
+        #[cfg(feature = "mflow")]
+        if let Some(e) = error.downcast_ref::<MemflowDriverError>() {
+            return e.init_failure_reason();
+        }
+        #[cfg(feature = "remote")]
+        if let Some(e) = error.downcast_ref::<RemoteDriverError>() {
+            return e.init_failure_reason();
+        }
+        #[cfg(feature = "virtualbox")]
+        if let Some(e) = error.downcast_ref::<VBoxDriverError>() {
+            return e.init_failure_reason();
+        }
+        #[cfg(feature = "xen")]
+        if let Some(e) = error.downcast_ref::<XenDriverError>() {
+            return e.init_failure_reason();
+        }
+        if let Some(e) = error.downcast_ref::<io::Error>() {
+            return Self::from_io_error(e);
+        }
+        error
+            .source()
+            .map_or(InitFailureReason::Other, Self::from_source)
+    }
+
+    /// Classify the IO error raised while opening a hypervisor interface
+    pub(crate) fn from_io_error(error: &io::Error) -> Self {
+        match error.kind() {
+            ErrorKind::PermissionDenied => InitFailureReason::PermissionDenied,
+            ErrorKind::NotFound | ErrorKind::ConnectionRefused => {
+                InitFailureReason::HypervisorNotPresent
+            }
+            _ => InitFailureReason::Other,
+        }
+    }
+}
+
+/// Initialization failure of one driver, as reported by `MicrovmiError::NoDriverAvailable`
+#[derive(thiserror::Error, Debug)]
+#[error("{driver_type:?}: {reason} ({error})")]
+pub struct DriverInitError {
+    pub driver_type: DriverType,
+    pub reason: InitFailureReason,
+    #[source]
+    pub error: Box<MicrovmiError>,
+}
+
+impl DriverInitError {
+    pub fn new(driver_type: DriverType, error: MicrovmiError) -> Self {
+        DriverInitError {
+            driver_type,
+            reason: InitFailureReason::from_error(&error),
+            error: Box::new(error),
+        }
+    }
+}
+
+struct DisplayFailures<'a>(&'a [DriverInitError]);
+
+impl fmt::Display for DisplayFailures<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, failure) in self.0.iter().enumerate() {
+            f.write_str(if i == 0 { ": " } else { ", " })?;
+            write!(f, "{}", failure)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_io_errors_are_classified() {
+        let denied: Box<dyn Error> = Box::new(io::Error::from(ErrorKind::PermissionDenied));
+        let missing: Box<dyn Error> = Box::new(io::Error::from(ErrorKind::NotFound));
+
+        let denied = DriverInitError::new(DriverType::KVM, denied.into());
+        let missing = DriverInitError::new(DriverType::Xen, missing.into());
+
+        assert_eq!(denied.reason, InitFailureReason::PermissionDenied);
+        assert_eq!(missing.reason, InitFailureReason::HypervisorNotPresent);
+    }
+
+    #[test]
+    fn test_every_failure_is_displayed() {
+        let failures = vec![
+            DriverInitError::new(
+                DriverType::KVM,
+                MicrovmiError::Other {
+                    source: "no socket".into(),
+                },
+            ),
+            DriverInitError::new(
+                DriverType::Xen,
+                MicrovmiError::Other {
+                    source: Box::new(io::Error::from(ErrorKind::PermissionDenied)),
+                },
+            ),
+        ];
+
+        let message = MicrovmiError::NoDriverAvailable(failures).to_string();
+
+        assert!(
+            message.contains("KVM: initialization failed (no socket)"),
+            "{}",
+            message
+        );
+        assert!(message.contains("Xen: permission denied"), "{}", message);
+    }
+}
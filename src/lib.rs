@@ -16,20 +16,29 @@ extern crate log;
 #[macro_use]
 extern crate bitflags;
 
+use std::path::Path;
+
 use enum_iterator::IntoEnumIterator;
 
+use api::audit::{Audited, AuditedVm, JsonAuditSink};
+use api::config::Config;
 use api::params::DriverInitParams;
+use api::url::parse_url;
+use api::vms::{DriverProbe, VmInfo};
 use api::DriverType;
 use api::Introspectable;
 #[cfg(feature = "kvm")]
 use driver::kvm::Kvm;
 #[cfg(feature = "mflow")]
 use driver::memflow::Memflow;
+use driver::read_only::ReadOnly;
+#[cfg(feature = "remote")]
+use driver::remote::Remote;
 #[cfg(feature = "virtualbox")]
 use driver::virtualbox::VBox;
 #[cfg(feature = "xen")]
 use driver::xen::Xen;
-use errors::MicrovmiError;
+use errors::{DriverInitError, MicrovmiError};
 #[cfg(feature = "kvm")]
 use kvmi::create_kvmi;
 
@@ -42,6 +51,8 @@ use kvmi::create_kvmi;
 ///
 /// # Arguments
 /// * `driver_type`: optional driver type to initialize. If None, all compiled drivers will be initialized one by one. The first that succeeds will be returned.
+///
+/// The drivers which have not been compiled are looked up in the driver plugins, see the [plugin](api/plugin/index.html) module.
 /// * `init_params`: optional driver initialization parameters
 ///
 /// # Examples
@@ -54,7 +65,7 @@ use kvmi::create_kvmi;
 /// // a `vm_name` parameter is required for multiple drivers: Xen, KVM, VirtualBox
 /// use microvmi::api::params::{DriverInitParams, CommonInitParams};
 /// let init_params = DriverInitParams {
-///     common: Some(CommonInitParams { vm_name: String::from("windows10")}),
+///     common: Some(CommonInitParams { vm_name: String::from("windows10"), ..Default::default() }),
 ///     ..Default::default()
 /// };
 /// let drv = init(None, Some(init_params));
@@ -65,7 +76,7 @@ use kvmi::create_kvmi;
 /// use microvmi::api::params::KVMInitParams;
 /// use microvmi::api::DriverType;
 /// let init_params = DriverInitParams {
-///     common: Some(CommonInitParams { vm_name: String::from("windows10")}),
+///     common: Some(CommonInitParams { vm_name: String::from("windows10"), ..Default::default() }),
 ///     kvm: Some(KVMInitParams::UnixSocket {path: String::from("/tmp/introspector")}),
 ///     ..Default::default()
 /// };
@@ -79,6 +90,7 @@ pub fn init(
     debug!("Microvmi init params: {:#?}", init_params);
     match driver_type {
         None => {
+            let mut failures = Vec::new();
             // for each possible DriverType
             for drv_type in DriverType::into_enum_iter() {
                 // try to init
@@ -87,14 +99,16 @@ pub fn init(
                         info!("Driver initialized: {:?}", driver.get_driver_type());
                         return Ok(driver);
                     }
+                    // only report the compiled drivers
+                    Err(MicrovmiError::DriverNotCompiled(_)) => continue,
                     Err(e) => {
                         debug!("{:?} driver initialization failed: {}", drv_type, e);
-                        continue;
+                        failures.push(DriverInitError::new(drv_type, e));
                     }
                 }
             }
             info!("No driver available");
-            Err(MicrovmiError::NoDriverAvailable)
+            Err(MicrovmiError::NoDriverAvailable(failures))
         }
         Some(drv_type) => match init_driver(drv_type, init_params) {
             Ok(driver) => {
@@ -109,8 +123,120 @@ pub fn init(
     }
 }
 
+/// libmicrovmi initialization from a connection URL
+///
+/// The URL specifies the driver type and its initialization parameters,
+/// see the [url](api/url/index.html) module for the syntax.
+///
+/// # Examples
+/// ```no_run
+/// use microvmi::init_from_url;
+/// let drv = init_from_url("vmi://kvm/?vm_name=windows10&kvm_unix_socket=/tmp/introspector");
+/// ```
+pub fn init_from_url(url: &str) -> Result<Box<dyn Introspectable>, MicrovmiError> {
+    let (driver_type, init_params) = parse_url(url)?;
+    init(driver_type, init_params)
+}
+
+/// libmicrovmi initialization from a configuration file and the environment
+///
+/// The settings are read from the configuration file, then overridden by the `MICROVMI_*` environment variables,
+/// see the [config](api/config/index.html) module.
+///
+/// # Arguments
+/// * `config_path`: optional path of the configuration file, otherwise taken from `MICROVMI_CONFIG` if set
+///
+/// # Examples
+/// ```no_run
+/// use std::path::Path;
+/// use microvmi::init_from_config;
+/// let drv = init_from_config(Some(Path::new("/etc/microvmi.toml")));
+/// ```
+pub fn init_from_config(
+    config_path: Option<&Path>,
+) -> Result<Box<dyn Introspectable>, MicrovmiError> {
+    let (driver_type, init_params) = Config::load(config_path)?.into_init_params()?;
+    init(driver_type, Some(init_params))
+}
+
+/// List the compiled drivers and the driver plugins, and probe whether their hypervisor interface is reachable
+///
+/// # Examples
+/// ```
+/// use microvmi::available_drivers;
+/// for probe in available_drivers() {
+///     println!("{:?}: reachable: {}", probe.driver_type, probe.reachable);
+/// }
+/// ```
+pub fn available_drivers() -> Vec<DriverProbe> {
+    DriverType::into_enum_iter()
+        .filter_map(|driver_type| {
+            #[allow(clippy::match_single_binding)]
+            let reachable = match driver_type {
+                #[cfg(feature = "kvm")]
+                DriverType::KVM => Some(driver::kvm::probe()),
+                #[cfg(feature = "mflow")]
+                DriverType::Memflow => Some(driver::memflow::probe()),
+                #[cfg(feature = "remote")]
+                DriverType::Remote => Some(driver::remote::probe()),
+                #[cfg(feature = "virtualbox")]
+                DriverType::VirtualBox => Some(driver::virtualbox::probe()),
+                #[cfg(feature = "xen")]
+                DriverType::Xen => Some(driver::xen::probe()),
+                #[allow(unreachable_patterns)]
+                _ => driver::plugin::find(driver_type).map(|plugin| plugin.probe()),
+            };
+            reachable.map(|reachable| {
+                debug!("{:?} driver probe: reachable: {}", driver_type, reachable);
+                DriverProbe {
+                    driver_type,
+                    reachable,
+                }
+            })
+        })
+        .collect()
+}
+
+/// List the VMs a driver can see
+///
+/// The returned names can be used as `CommonInitParams.vm_name`:
+/// - Xen: the domains registered in xenstore
+/// - VirtualBox: the running machines
+/// - KVM, Memflow: the QEMU processes started with a `-name`
+/// - Remote: none, the server selects the VM
+/// - drivers provided by a plugin: the VMs reported by the plugin
+///
+/// # Examples
+/// ```no_run
+/// use microvmi::api::DriverType;
+/// use microvmi::list_vms;
+/// for vm in list_vms(DriverType::Xen).unwrap() {
+///     println!("{} ({:?})", vm.name, vm.id);
+/// }
+/// ```
+pub fn list_vms(driver_type: DriverType) -> Result<Vec<VmInfo>, MicrovmiError> {
+    #[allow(clippy::match_single_binding)]
+    match driver_type {
+        #[cfg(feature = "kvm")]
+        DriverType::KVM => Ok(driver::kvm::list_vms()?),
+        #[cfg(feature = "mflow")]
+        DriverType::Memflow => Ok(driver::memflow::list_vms()?),
+        #[cfg(feature = "remote")]
+        DriverType::Remote => Ok(driver::remote::list_vms()?),
+        #[cfg(feature = "virtualbox")]
+        DriverType::VirtualBox => Ok(driver::virtualbox::list_vms()?),
+        #[cfg(feature = "xen")]
+        DriverType::Xen => Ok(driver::xen::list_vms()?),
+        #[allow(unreachable_patterns)]
+        _ => match driver::plugin::find(driver_type) {
+            Some(plugin) => Ok(plugin.list_vms()?),
+            None => Err(MicrovmiError::DriverNotCompiled(driver_type)),
+        },
+    }
+}
+
 /// Initialize a given driver type
-/// return None if the requested driver has not been compiled in libmicrovmi
+/// return DriverNotCompiled if the requested driver has neither been compiled in libmicrovmi, nor provided by a plugin
 fn init_driver(
     driver_type: DriverType,
     init_params_option: Option<DriverInitParams>,
@@ -118,17 +244,48 @@ fn init_driver(
     let _init_params = init_params_option.unwrap_or(DriverInitParams {
         ..Default::default()
     });
+    let read_only = _init_params
+        .common
+        .as_ref()
+        .is_some_and(|common| common.read_only);
+    let audit_log = _init_params
+        .common
+        .as_ref()
+        .and_then(|common| common.audit_log.clone());
+    let audited_vm = AuditedVm::from_init_params(&_init_params);
     #[allow(clippy::match_single_binding)]
-    match driver_type {
+    let driver: Result<Box<dyn Introspectable>, MicrovmiError> = match driver_type {
         #[cfg(feature = "kvm")]
         DriverType::KVM => Ok(Box::new(Kvm::new(create_kvmi()?, _init_params)?)),
         #[cfg(feature = "mflow")]
         DriverType::Memflow => Ok(Box::new(Memflow::new(_init_params)?)),
+        #[cfg(feature = "remote")]
+        DriverType::Remote => Ok(Box::new(Remote::new(_init_params)?)),
         #[cfg(feature = "virtualbox")]
         DriverType::VirtualBox => Ok(Box::new(VBox::new(_init_params)?)),
         #[cfg(feature = "xen")]
         DriverType::Xen => Ok(Box::new(Xen::new(_init_params)?)),
         #[allow(unreachable_patterns)]
-        _ => Err(MicrovmiError::DriverNotCompiled(driver_type)),
+        _ => match driver::plugin::find(driver_type) {
+            Some(plugin) => Ok(Box::new(plugin.init(_init_params)?)),
+            None => Err(MicrovmiError::DriverNotCompiled(driver_type)),
+        },
+    };
+    let driver = if read_only {
+        info!("Read-only mode");
+        Box::new(ReadOnly::new(driver?))
+    } else {
+        driver?
+    };
+    // outermost, to record the operations refused in read-only mode too
+    match audit_log {
+        Some(path) => {
+            info!("Audit log: {}", path);
+            let sink = JsonAuditSink::create(&path).map_err(|e| MicrovmiError::Other {
+                source: Box::new(e),
+            })?;
+            Ok(Box::new(Audited::new(driver, sink, audited_vm)))
+        }
+        None => Ok(driver),
     }
 }
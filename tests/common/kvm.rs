@@ -8,6 +8,7 @@ use microvmi::init;
 use super::config::{KVMI_SOCKET, VIRSH_URI, VM_NAME};
 use crate::common::context::Context;
 
+#[allow(clippy::upper_case_acronyms)]
 pub struct KVM;
 
 impl Context for KVM {
@@ -25,7 +26,7 @@ impl Context for KVM {
             .status()
             .expect("Failed to start virsh")
             .success()
-            .then(|| 0)
+            .then_some(0)
             .expect("Failed to run virsh snapshot-revert");
     }
 
@@ -35,6 +36,8 @@ impl Context for KVM {
             Some(DriverInitParams {
                 common: Some(CommonInitParams {
                     vm_name: String::from(VM_NAME),
+                    read_only: false,
+                    audit_log: None,
                 }),
                 kvm: Some(KVMInitParams::UnixSocket {
                     path: String::from(KVMI_SOCKET),
@@ -57,7 +60,7 @@ impl Context for KVM {
             .status()
             .expect("Failed to start virsh")
             .success()
-            .then(|| 0)
+            .then_some(0)
             .expect("Failed to run virsh destroy");
     }
 }
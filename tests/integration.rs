@@ -6,7 +6,6 @@ use std::thread;
 use std::time::Duration;
 
 use colored::*;
-use env_logger;
 
 use common::config::TIMEOUT;
 use common::context::init_context;
@@ -16,6 +16,8 @@ fn intercept_cr3_one(mut drv: Box<dyn Introspectable>) {
             ..
         }
     ));
+    drv.reply_event(event, EventReplyType::Continue)
+        .expect("Failed to send event reply");
 }
 
 inventory::submit!(IntegrationTest {
@@ -1,7 +1,11 @@
 /// This crate implements utilities and common code shared by libmicrovmi examples
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
 use clap::{Arg, ArgMatches};
 use microvmi::api::params::{
     CommonInitParams, DriverInitParams, KVMInitParams, MemflowConnectorParams, MemflowInitParams,
+    RemoteInitParams, XenInitParams, VMADDR_CID_ANY,
 };
 
 /// This trait allows to convert a struct to Clap's command line arguments
@@ -21,11 +25,31 @@ impl Clappable for DriverInitParams {
                 .long("vm_name")
                 .takes_value(true)
                 .help("Driver parameter (required for Xen, KVM, VirtualBox): VM name"),
+            Arg::with_name("read_only")
+                .long("read_only")
+                .takes_value(false)
+                .help("Driver parameter (optional): refuse any modification of the VM"),
+            Arg::with_name("audit_log")
+                .long("audit_log")
+                .takes_value(true)
+                .help("Driver parameter (optional): log the modifications of the VM to this JSON lines file"),
+            // xen
+            Arg::with_name("xen_domid")
+                .long("xen_domid")
+                .takes_value(true)
+                .validator(|s| s.parse::<u32>().map(|_| ()).map_err(|e| e.to_string()))
+                .help("Driver parameter (optional for Xen): domain ID, instead of the VM name"),
             // kvm
             Arg::with_name("kvm_unix_socket")
                 .long("kvm_unix_socket")
                 .takes_value(true)
                 .help("Driver parameter (required for KVM): KVM unix socket path"),
+            Arg::with_name("kvm_vsock_port")
+                .long("kvm_vsock_port")
+                .takes_value(true)
+                .conflicts_with("kvm_unix_socket")
+                .validator(|s| s.parse::<u32>().map(|_| ()).map_err(|e| e.to_string()))
+                .help("Driver parameter (optional for KVM): KVM vsock port, instead of the unix socket"),
             // memflow
             Arg::with_name("memflow_connector_name")
                 .long("memflow_connector_name")
@@ -35,18 +59,59 @@ impl Clappable for DriverInitParams {
                 .long("memflow_connector_args")
                 .multiple(true)
                 .min_values(1),
+            Arg::with_name("memflow_os_name")
+                .long("memflow_os_name")
+                .takes_value(true)
+                .help("Driver parameter (optional for Memflow): Memflow OS plugin name"),
+            Arg::with_name("memflow_os_args")
+                .long("memflow_os_args")
+                .multiple(true)
+                .min_values(1),
+            // remote
+            Arg::with_name("remote_addr")
+                .long("remote_addr")
+                .takes_value(true)
+                .requires("remote_token")
+                .help("Driver parameter (required for Remote): microvmi-server address, tcp://<host>:<port> or unix://<path>"),
+            Arg::with_name("remote_token")
+                .long("remote_token")
+                .takes_value(true)
+                .requires("remote_addr")
+                .help("Driver parameter (required for Remote): token shared with the microvmi-server"),
         ]
     }
 
     fn from_matches(matches: &ArgMatches) -> Self {
-        let common = matches.value_of("vm_name").map(|s| CommonInitParams {
-            vm_name: String::from(s),
-        });
-        let kvm = matches
-            .value_of("kvm_unix_socket")
-            .map(|s| KVMInitParams::UnixSocket {
-                path: String::from(s),
-            });
+        let vm_name = matches.value_of("vm_name");
+        let read_only = matches.is_present("read_only");
+        let audit_log = matches.value_of("audit_log").map(String::from);
+        let common = if vm_name.is_some() || read_only || audit_log.is_some() {
+            Some(CommonInitParams {
+                vm_name: vm_name.map(String::from).unwrap_or_default(),
+                read_only,
+                audit_log,
+            })
+        } else {
+            None
+        };
+        let xen = matches
+            .value_of("xen_domid")
+            .and_then(|s| s.parse().ok())
+            .map(XenInitParams::DomId);
+        let kvm = match matches
+            .value_of("kvm_vsock_port")
+            .and_then(|s| s.parse().ok())
+        {
+            Some(port) => Some(KVMInitParams::Vsock {
+                cid: VMADDR_CID_ANY,
+                port,
+            }),
+            None => matches
+                .value_of("kvm_unix_socket")
+                .map(|s| KVMInitParams::UnixSocket {
+                    path: String::from(s),
+                }),
+        };
         let memflow = matches
             .value_of("memflow_connector_name")
             .map(|name| MemflowInitParams {
@@ -56,21 +121,67 @@ impl Clappable for DriverInitParams {
                         args: v.map(|s| s.to_string()).collect(),
                     }
                 }),
+                os_name: matches.value_of("memflow_os_name").map(String::from),
+                os_args: matches
+                    .values_of("memflow_os_args")
+                    .map(|v| v.map(|s| s.to_string()).collect()),
+            });
+        let remote = matches
+            .value_of("remote_addr")
+            .zip(matches.value_of("remote_token"))
+            .map(|(addr, token)| RemoteInitParams {
+                addr: String::from(addr),
+                token: String::from(token),
             });
         DriverInitParams {
             common,
+            xen,
             kvm,
             memflow,
+            remote,
             ..Default::default()
         }
     }
 }
 
+/// Install a Ctrl-C handler which lets the introspection loop stop gracefully
+///
+/// Instead of killing the process, which would leave the guest with the intercepts
+/// and page access restrictions set by the driver, Ctrl-C clears the returned flag.
+/// The loop should exit when the flag is false, so that the driver is dropped and the guest state restored.
+///
+/// # Examples
+/// ```no_run
+/// use std::sync::atomic::Ordering;
+/// use microvmi::api::events::EventReplyType;
+/// use microvmi::init;
+/// use utilities::ctrlc_handler;
+///
+/// let running = ctrlc_handler().expect("Failed to set Ctrl-C handler");
+/// let mut drv = init(None, None).expect("Failed to init driver");
+/// while running.load(Ordering::SeqCst) {
+///     if let Some(ev) = drv.listen(1000).unwrap() {
+///         println!("event on VCPU {}", ev.vcpu);
+///         drv.reply_event(ev, EventReplyType::Continue).unwrap();
+///     }
+/// }
+/// ```
+pub fn ctrlc_handler() -> Result<Arc<AtomicBool>, ctrlc::Error> {
+    let running = Arc::new(AtomicBool::new(true));
+    let r = running.clone();
+    ctrlc::set_handler(move || {
+        r.store(false, Ordering::SeqCst);
+    })?;
+    Ok(running)
+}
+
 #[cfg(test)]
 mod tests {
     use super::Clappable;
     use clap::App;
-    use microvmi::api::params::{DriverInitParams, KVMInitParams, MemflowConnectorParams};
+    use microvmi::api::params::{
+        DriverInitParams, KVMInitParams, MemflowConnectorParams, XenInitParams, VMADDR_CID_ANY,
+    };
 
     #[test]
     fn test_common_vm_name() {
@@ -82,6 +193,29 @@ mod tests {
         assert_eq!("windows10", params.common.unwrap().vm_name)
     }
 
+    #[test]
+    fn test_common_read_only() {
+        let cmdline = vec!["test", "--vm_name=windows10", "--read_only"];
+        let matches = App::new("test")
+            .args(DriverInitParams::to_clap_args().as_ref())
+            .get_matches_from(cmdline);
+        let params = DriverInitParams::from_matches(&matches);
+        assert!(params.common.unwrap().read_only)
+    }
+
+    #[test]
+    fn test_common_audit_log() {
+        let cmdline = vec!["test", "--audit_log=/tmp/audit.jsonl"];
+        let matches = App::new("test")
+            .args(DriverInitParams::to_clap_args().as_ref())
+            .get_matches_from(cmdline);
+        let params = DriverInitParams::from_matches(&matches);
+        assert_eq!(
+            Some(String::from("/tmp/audit.jsonl")),
+            params.common.unwrap().audit_log
+        )
+    }
+
     #[test]
     fn test_kvm_unix_socket() {
         let cmdline = vec!["test", "--kvm_unix_socket=/tmp/introspector"];
@@ -97,6 +231,32 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_kvm_vsock_port() {
+        let cmdline = vec!["test", "--kvm_vsock_port=1234"];
+        let matches = App::new("test")
+            .args(DriverInitParams::to_clap_args().as_ref())
+            .get_matches_from(cmdline);
+        let params = DriverInitParams::from_matches(&matches);
+        assert_eq!(
+            KVMInitParams::Vsock {
+                cid: VMADDR_CID_ANY,
+                port: 1234
+            },
+            params.kvm.unwrap()
+        );
+    }
+
+    #[test]
+    fn test_xen_domid() {
+        let cmdline = vec!["test", "--xen_domid=5"];
+        let matches = App::new("test")
+            .args(DriverInitParams::to_clap_args().as_ref())
+            .get_matches_from(cmdline);
+        let params = DriverInitParams::from_matches(&matches);
+        assert_eq!(XenInitParams::DomId(5), params.xen.unwrap())
+    }
+
     // tests for memflow
     #[test]
     fn test_memflow_connector_name() {
@@ -149,4 +309,21 @@ mod tests {
             params.memflow.unwrap().connector_args.unwrap()
         )
     }
+
+    #[test]
+    fn test_memflow_os() {
+        let cmdline = vec![
+            "test",
+            "--memflow_connector_name=qemu",
+            "--memflow_os_name=win32",
+            "--memflow_os_args",
+            "arch=x64",
+        ];
+        let matches = App::new("test")
+            .args(DriverInitParams::to_clap_args().as_ref())
+            .get_matches_from(cmdline);
+        let memflow = DriverInitParams::from_matches(&matches).memflow.unwrap();
+        assert_eq!(Some(String::from("win32")), memflow.os_name);
+        assert_eq!(Some(vec![String::from("arch=x64")]), memflow.os_args)
+    }
 }